@@ -0,0 +1,837 @@
+//! Versioned binary layout of `RiskEngine` state.
+//!
+//! The crate forbids `unsafe`, so the slab is not reinterpreted in place.
+//! Instead every field is written in declaration order, little-endian, into a
+//! caller-provided buffer of exactly `RiskEngine::<N, W>::ENCODED_LEN` bytes
+//! (`ENGINE_BYTES_LEN` for the default size), and read back
+//! the same way. Neither direction allocates or builds a temporary engine, so
+//! both work on a BPF account buffer. A load validates the buffer itself
+//! before writing, so a rejected buffer leaves the target engine unchanged.
+//! `encode`/`decode` are conveniences over the same copies. There is no
+//! zero-copy view of the engine as bytes.
+//!
+//! Layout: a 16-byte header (`ENGINE_MAGIC`, `ENGINE_LAYOUT_VERSION` as u32,
+//! `RiskEngine::<N, W>::LAYOUT_HASH` as u64) followed by the engine fields.
+//! The layout hash covers the field list and the capacity `N`, so encodings of
+//! an engine with a different slab size or field set are rejected rather than
+//! misread. Every engine size has its own encoding; `ENGINE_BYTES_LEN` and
+//! `ENGINE_LAYOUT_HASH` are those of the default-size `RiskEngine`.
+
+use crate::{
+    empty_account, Account, AccountKind, InsuranceFund, RiskEngine, RiskParams, BITMAP_WORDS, I128,
+    MAX_ACCOUNTS, U128,
+};
+
+pub const ENGINE_MAGIC: [u8; 4] = *b"PRCL";
+pub const ENGINE_LAYOUT_VERSION: u32 = 2;
+
+// Section lengths are measured by running the writers below in counting
+// mode at compile time, so they cannot drift from what `to_bytes` writes.
+
+const HEADER_LEN: usize = {
+    let mut w = Writer::counter();
+    write_header(&mut w, 0);
+    w.pos
+};
+/// Scalars before the bitmap
+const HEAD_LEN: usize = {
+    let mut w = Writer::counter();
+    Scalars::ZERO.write_head(&mut w);
+    w.pos
+};
+/// Scalars between the bitmap and the freelist
+const TAIL_LEN: usize = {
+    let mut w = Writer::counter();
+    Scalars::ZERO.write_tail(&mut w);
+    w.pos
+};
+/// Encoded size of one `Account`; accounts are the last section
+pub const ACCOUNT_BYTES_LEN: usize = {
+    let mut w = Writer::counter();
+    write_account(&mut w, &empty_account());
+    w.pos
+};
+
+/// Exact size of an encoded `RiskEngine<n, w>`
+pub const fn engine_bytes_len(n: usize, w: usize) -> usize {
+    accounts_offset(n, w) + n * ACCOUNT_BYTES_LEN
+}
+
+/// Exact size of an encoded default-size engine
+pub const ENGINE_BYTES_LEN: usize = engine_bytes_len(MAX_ACCOUNTS, BITMAP_WORDS);
+
+/// Field order and widths. Changing the encoding means changing this string
+/// (and `ENGINE_LAYOUT_VERSION`), which changes `ENGINE_LAYOUT_HASH`.
+const LAYOUT_DESCRIPTOR: &[u8] = b"RiskEngine{vault:u128,insurance.balance:u128,\
+insurance.fee_revenue:u128,params{warmup_period_slots:u64,maintenance_margin_bps:u64,\
+initial_margin_bps:u64,trading_fee_bps:u64,max_accounts:u64,new_account_fee:u128,\
+risk_reduction_threshold:u128,maintenance_fee_per_slot:u128,max_crank_staleness_slots:u64,\
+liquidation_fee_bps:u64,liquidation_fee_cap:u128,liquidation_buffer_bps:u64,\
+min_liquidation_abs:u128},current_slot:u64,funding_index_qpb_e6:i128,last_funding_slot:u64,\
+funding_rate_bps_per_slot_last:i64,last_crank_slot:u64,max_crank_staleness_slots:u64,\
+total_open_interest:u128,c_tot:u128,pnl_pos_tot:u128,liq_cursor:u16,gc_cursor:u16,\
+last_full_sweep_start_slot:u64,last_full_sweep_completed_slot:u64,crank_cursor:u16,\
+sweep_start_idx:u16,lifetime_liquidations:u64,lifetime_force_realize_closes:u64,\
+net_lp_pos:i128,lp_sum_abs:u128,lp_max_abs:u128,lp_max_abs_sweep:u128,\
+used:[u64;W],num_used_accounts:u16,next_account_id:u64,free_head:u16,\
+next_free:[u16;N],accounts:[Account{account_id:u64,capital:u128,kind:u8,\
+pnl:i128,reserved_pnl:u64,warmup_started_at_slot:u64,warmup_slope_per_step:u128,\
+position_size:i128,entry_price:u64,funding_index:i128,matcher_program:[u8;32],\
+matcher_context:[u8;32],owner:[u8;32],fee_credits:i128,last_fee_slot:u64,locked_capital:u128};N]}";
+
+/// FNV-1a over the descriptor, then the capacity `n`
+pub const fn layout_hash(n: usize) -> u64 {
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+    while i < LAYOUT_DESCRIPTOR.len() {
+        hash ^= LAYOUT_DESCRIPTOR[i] as u64;
+        hash = hash.wrapping_mul(PRIME);
+        i += 1;
+    }
+    let max = (n as u64).to_le_bytes();
+    let mut i = 0;
+    while i < max.len() {
+        hash ^= max[i] as u64;
+        hash = hash.wrapping_mul(PRIME);
+        i += 1;
+    }
+    hash
+}
+
+/// Layout hash of a default-size engine
+pub const ENGINE_LAYOUT_HASH: u64 = layout_hash(MAX_ACCOUNTS);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayoutError {
+    /// Buffer is not exactly the engine's `ENCODED_LEN` bytes
+    BufferLength,
+    /// Header does not start with `ENGINE_MAGIC`
+    BadMagic,
+    /// Header version is not `ENGINE_LAYOUT_VERSION`
+    UnsupportedVersion,
+    /// Header layout hash differs (other field set or capacity)
+    LayoutMismatch,
+    /// Account kind byte is neither User nor LP
+    InvalidAccountKind,
+    /// Bits set beyond the capacity, or popcount != `num_used_accounts`
+    CorruptBitmap,
+    /// Freelist index out of range, cyclic, overlapping used slots, or of
+    /// the wrong length
+    CorruptFreelist,
+    /// A crank cursor is not below the capacity
+    InvalidCursor,
+    /// An account id is not below `next_account_id`
+    InvalidAccountId,
+    /// An account's `locked_capital` exceeds its `capital`
+    InvalidLockedCapital,
+    /// `c_tot`, `pnl_pos_tot` or `total_open_interest` differ from the
+    /// account sums, or `vault < c_tot + insurance`
+    InconsistentAggregates,
+}
+
+/// Appends fields little-endian. Without a buffer it only advances `pos`,
+/// which is how the section lengths are measured.
+struct Writer<'a> {
+    buf: Option<&'a mut [u8]>,
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    const fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf: Some(buf),
+            pos: 0,
+        }
+    }
+    const fn counter() -> Self {
+        Self { buf: None, pos: 0 }
+    }
+    const fn bytes(&mut self, bytes: &[u8]) {
+        if let Some(buf) = &mut self.buf {
+            let (_, rest) = buf.split_at_mut(self.pos);
+            rest.split_at_mut(bytes.len()).0.copy_from_slice(bytes);
+        }
+        self.pos += bytes.len();
+    }
+    const fn u8(&mut self, v: u8) {
+        self.bytes(&[v]);
+    }
+    const fn u16(&mut self, v: u16) {
+        self.bytes(&v.to_le_bytes());
+    }
+    const fn u32(&mut self, v: u32) {
+        self.bytes(&v.to_le_bytes());
+    }
+    const fn u64(&mut self, v: u64) {
+        self.bytes(&v.to_le_bytes());
+    }
+    const fn i64(&mut self, v: i64) {
+        self.bytes(&v.to_le_bytes());
+    }
+    const fn u128(&mut self, v: U128) {
+        self.bytes(&v.get().to_le_bytes());
+    }
+    const fn i128(&mut self, v: I128) {
+        self.bytes(&v.get().to_le_bytes());
+    }
+}
+
+const fn write_header(w: &mut Writer, layout_hash: u64) {
+    w.bytes(&ENGINE_MAGIC);
+    w.u32(ENGINE_LAYOUT_VERSION);
+    w.u64(layout_hash);
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn array<const N: usize>(&mut self) -> [u8; N] {
+        let mut out = [0u8; N];
+        out.copy_from_slice(&self.buf[self.pos..self.pos + N]);
+        self.pos += N;
+        out
+    }
+    fn u8(&mut self) -> u8 {
+        self.array::<1>()[0]
+    }
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.array())
+    }
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.array())
+    }
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.array())
+    }
+    fn i64(&mut self) -> i64 {
+        i64::from_le_bytes(self.array())
+    }
+    fn u128(&mut self) -> U128 {
+        U128::new(u128::from_le_bytes(self.array()))
+    }
+    fn i128(&mut self) -> I128 {
+        I128::new(i128::from_le_bytes(self.array()))
+    }
+}
+
+const fn write_account(w: &mut Writer, a: &Account) {
+    // Exhaustive, so a new `Account` field fails to compile until encoded
+    let Account {
+        account_id,
+        capital,
+        kind,
+        pnl,
+        reserved_pnl,
+        warmup_started_at_slot,
+        warmup_slope_per_step,
+        position_size,
+        entry_price,
+        funding_index,
+        matcher_program,
+        matcher_context,
+        owner,
+        fee_credits,
+        last_fee_slot,
+        locked_capital,
+    } = *a;
+    w.u64(account_id);
+    w.u128(capital);
+    w.u8(kind as u8);
+    w.i128(pnl);
+    w.u64(reserved_pnl);
+    w.u64(warmup_started_at_slot);
+    w.u128(warmup_slope_per_step);
+    w.i128(position_size);
+    w.u64(entry_price);
+    w.i128(funding_index);
+    w.bytes(&matcher_program);
+    w.bytes(&matcher_context);
+    w.bytes(&owner);
+    w.i128(fee_credits);
+    w.u64(last_fee_slot);
+    w.u128(locked_capital);
+}
+
+fn read_account(r: &mut Reader, a: &mut Account) -> Result<(), LayoutError> {
+    a.account_id = r.u64();
+    a.capital = r.u128();
+    a.kind = match r.u8() {
+        0 => AccountKind::User,
+        1 => AccountKind::LP,
+        _ => return Err(LayoutError::InvalidAccountKind),
+    };
+    a.pnl = r.i128();
+    a.reserved_pnl = r.u64();
+    a.warmup_started_at_slot = r.u64();
+    a.warmup_slope_per_step = r.u128();
+    a.position_size = r.i128();
+    a.entry_price = r.u64();
+    a.funding_index = r.i128();
+    a.matcher_program = r.array();
+    a.matcher_context = r.array();
+    a.owner = r.array();
+    a.fee_credits = r.i128();
+    a.last_fee_slot = r.u64();
+    a.locked_capital = r.u128();
+    Ok(())
+}
+
+/// Offset of the occupancy bitmap: header, then the scalars before it
+const BITMAP_OFFSET: usize = HEADER_LEN + HEAD_LEN;
+/// Offset of `next_free`: the bitmap, then the scalars between it and the
+/// freelist
+const fn next_free_offset(w: usize) -> usize {
+    BITMAP_OFFSET + w * 8 + TAIL_LEN
+}
+const fn accounts_offset(n: usize, w: usize) -> usize {
+    next_free_offset(w) + n * 2
+}
+
+/// Every engine field outside the bitmap, freelist and accounts. Decoded on
+/// its own, so an encoding can be validated before any of it reaches the
+/// engine.
+pub(crate) struct Scalars {
+    pub(crate) vault: U128,
+    pub(crate) insurance_fund: InsuranceFund,
+    pub(crate) params: RiskParams,
+    pub(crate) current_slot: u64,
+    pub(crate) funding_index_qpb_e6: I128,
+    pub(crate) last_funding_slot: u64,
+    pub(crate) funding_rate_bps_per_slot_last: i64,
+    pub(crate) last_crank_slot: u64,
+    pub(crate) max_crank_staleness_slots: u64,
+    pub(crate) total_open_interest: U128,
+    pub(crate) c_tot: U128,
+    pub(crate) pnl_pos_tot: U128,
+    pub(crate) liq_cursor: u16,
+    pub(crate) gc_cursor: u16,
+    pub(crate) last_full_sweep_start_slot: u64,
+    pub(crate) last_full_sweep_completed_slot: u64,
+    pub(crate) crank_cursor: u16,
+    pub(crate) sweep_start_idx: u16,
+    pub(crate) lifetime_liquidations: u64,
+    pub(crate) lifetime_force_realize_closes: u64,
+    pub(crate) net_lp_pos: I128,
+    pub(crate) lp_sum_abs: U128,
+    pub(crate) lp_max_abs: U128,
+    pub(crate) lp_max_abs_sweep: U128,
+    pub(crate) num_used_accounts: u16,
+    pub(crate) next_account_id: u64,
+    pub(crate) free_head: u16,
+}
+
+impl Scalars {
+    /// All-zero scalars, only used to measure the encoded sections
+    const ZERO: Self = Self {
+        vault: U128::ZERO,
+        insurance_fund: InsuranceFund {
+            balance: U128::ZERO,
+            fee_revenue: U128::ZERO,
+        },
+        params: RiskParams {
+            warmup_period_slots: 0,
+            maintenance_margin_bps: 0,
+            initial_margin_bps: 0,
+            trading_fee_bps: 0,
+            max_accounts: 0,
+            new_account_fee: U128::ZERO,
+            risk_reduction_threshold: U128::ZERO,
+            maintenance_fee_per_slot: U128::ZERO,
+            max_crank_staleness_slots: 0,
+            liquidation_fee_bps: 0,
+            liquidation_fee_cap: U128::ZERO,
+            liquidation_buffer_bps: 0,
+            min_liquidation_abs: U128::ZERO,
+        },
+        current_slot: 0,
+        funding_index_qpb_e6: I128::ZERO,
+        last_funding_slot: 0,
+        funding_rate_bps_per_slot_last: 0,
+        last_crank_slot: 0,
+        max_crank_staleness_slots: 0,
+        total_open_interest: U128::ZERO,
+        c_tot: U128::ZERO,
+        pnl_pos_tot: U128::ZERO,
+        liq_cursor: 0,
+        gc_cursor: 0,
+        last_full_sweep_start_slot: 0,
+        last_full_sweep_completed_slot: 0,
+        crank_cursor: 0,
+        sweep_start_idx: 0,
+        lifetime_liquidations: 0,
+        lifetime_force_realize_closes: 0,
+        net_lp_pos: I128::ZERO,
+        lp_sum_abs: U128::ZERO,
+        lp_max_abs: U128::ZERO,
+        lp_max_abs_sweep: U128::ZERO,
+        num_used_accounts: 0,
+        next_account_id: 0,
+        free_head: 0,
+    };
+
+    fn of<const N: usize, const W: usize>(e: &RiskEngine<N, W>) -> Self {
+        // Exhaustive, so a new engine field fails to compile until encoded
+        let RiskEngine {
+            vault,
+            insurance_fund,
+            params,
+            current_slot,
+            funding_index_qpb_e6,
+            last_funding_slot,
+            funding_rate_bps_per_slot_last,
+            last_crank_slot,
+            max_crank_staleness_slots,
+            total_open_interest,
+            c_tot,
+            pnl_pos_tot,
+            liq_cursor,
+            gc_cursor,
+            last_full_sweep_start_slot,
+            last_full_sweep_completed_slot,
+            crank_cursor,
+            sweep_start_idx,
+            lifetime_liquidations,
+            lifetime_force_realize_closes,
+            net_lp_pos,
+            lp_sum_abs,
+            lp_max_abs,
+            lp_max_abs_sweep,
+            used: _,
+            num_used_accounts,
+            next_account_id,
+            free_head,
+            next_free: _,
+            accounts: _,
+        } = *e;
+        Self {
+            vault,
+            insurance_fund,
+            params,
+            current_slot,
+            funding_index_qpb_e6,
+            last_funding_slot,
+            funding_rate_bps_per_slot_last,
+            last_crank_slot,
+            max_crank_staleness_slots,
+            total_open_interest,
+            c_tot,
+            pnl_pos_tot,
+            liq_cursor,
+            gc_cursor,
+            last_full_sweep_start_slot,
+            last_full_sweep_completed_slot,
+            crank_cursor,
+            sweep_start_idx,
+            lifetime_liquidations,
+            lifetime_force_realize_closes,
+            net_lp_pos,
+            lp_sum_abs,
+            lp_max_abs,
+            lp_max_abs_sweep,
+            num_used_accounts,
+            next_account_id,
+            free_head,
+        }
+    }
+
+    pub(crate) fn apply<const N: usize, const W: usize>(&self, e: &mut RiskEngine<N, W>) {
+        e.vault = self.vault;
+        e.insurance_fund = self.insurance_fund;
+        e.params = self.params;
+        e.current_slot = self.current_slot;
+        e.funding_index_qpb_e6 = self.funding_index_qpb_e6;
+        e.last_funding_slot = self.last_funding_slot;
+        e.funding_rate_bps_per_slot_last = self.funding_rate_bps_per_slot_last;
+        e.last_crank_slot = self.last_crank_slot;
+        e.max_crank_staleness_slots = self.max_crank_staleness_slots;
+        e.total_open_interest = self.total_open_interest;
+        e.c_tot = self.c_tot;
+        e.pnl_pos_tot = self.pnl_pos_tot;
+        e.liq_cursor = self.liq_cursor;
+        e.gc_cursor = self.gc_cursor;
+        e.last_full_sweep_start_slot = self.last_full_sweep_start_slot;
+        e.last_full_sweep_completed_slot = self.last_full_sweep_completed_slot;
+        e.crank_cursor = self.crank_cursor;
+        e.sweep_start_idx = self.sweep_start_idx;
+        e.lifetime_liquidations = self.lifetime_liquidations;
+        e.lifetime_force_realize_closes = self.lifetime_force_realize_closes;
+        e.net_lp_pos = self.net_lp_pos;
+        e.lp_sum_abs = self.lp_sum_abs;
+        e.lp_max_abs = self.lp_max_abs;
+        e.lp_max_abs_sweep = self.lp_max_abs_sweep;
+        e.num_used_accounts = self.num_used_accounts;
+        e.next_account_id = self.next_account_id;
+        e.free_head = self.free_head;
+    }
+
+    /// Scalars before the bitmap; `w` must sit just after the header
+    const fn write_head(&self, w: &mut Writer) {
+        let InsuranceFund {
+            balance,
+            fee_revenue,
+        } = self.insurance_fund;
+        let RiskParams {
+            warmup_period_slots,
+            maintenance_margin_bps,
+            initial_margin_bps,
+            trading_fee_bps,
+            max_accounts,
+            new_account_fee,
+            risk_reduction_threshold,
+            maintenance_fee_per_slot,
+            max_crank_staleness_slots,
+            liquidation_fee_bps,
+            liquidation_fee_cap,
+            liquidation_buffer_bps,
+            min_liquidation_abs,
+        } = self.params;
+        w.u128(self.vault);
+        w.u128(balance);
+        w.u128(fee_revenue);
+        w.u64(warmup_period_slots);
+        w.u64(maintenance_margin_bps);
+        w.u64(initial_margin_bps);
+        w.u64(trading_fee_bps);
+        w.u64(max_accounts);
+        w.u128(new_account_fee);
+        w.u128(risk_reduction_threshold);
+        w.u128(maintenance_fee_per_slot);
+        w.u64(max_crank_staleness_slots);
+        w.u64(liquidation_fee_bps);
+        w.u128(liquidation_fee_cap);
+        w.u64(liquidation_buffer_bps);
+        w.u128(min_liquidation_abs);
+        w.u64(self.current_slot);
+        w.i128(self.funding_index_qpb_e6);
+        w.u64(self.last_funding_slot);
+        w.i64(self.funding_rate_bps_per_slot_last);
+        w.u64(self.last_crank_slot);
+        w.u64(self.max_crank_staleness_slots);
+        w.u128(self.total_open_interest);
+        w.u128(self.c_tot);
+        w.u128(self.pnl_pos_tot);
+        w.u16(self.liq_cursor);
+        w.u16(self.gc_cursor);
+        w.u64(self.last_full_sweep_start_slot);
+        w.u64(self.last_full_sweep_completed_slot);
+        w.u16(self.crank_cursor);
+        w.u16(self.sweep_start_idx);
+        w.u64(self.lifetime_liquidations);
+        w.u64(self.lifetime_force_realize_closes);
+        w.i128(self.net_lp_pos);
+        w.u128(self.lp_sum_abs);
+        w.u128(self.lp_max_abs);
+        w.u128(self.lp_max_abs_sweep);
+    }
+
+    /// Scalars between the bitmap and the freelist
+    const fn write_tail(&self, w: &mut Writer) {
+        w.u16(self.num_used_accounts);
+        w.u64(self.next_account_id);
+        w.u16(self.free_head);
+    }
+
+    /// Decode from a buffer holding an engine with `w` bitmap words
+    fn read(bytes: &[u8], w: usize) -> Self {
+        let mut r = Reader {
+            buf: bytes,
+            pos: HEADER_LEN,
+        };
+        let vault = r.u128();
+        let insurance_fund = InsuranceFund {
+            balance: r.u128(),
+            fee_revenue: r.u128(),
+        };
+        let params = RiskParams {
+            warmup_period_slots: r.u64(),
+            maintenance_margin_bps: r.u64(),
+            initial_margin_bps: r.u64(),
+            trading_fee_bps: r.u64(),
+            max_accounts: r.u64(),
+            new_account_fee: r.u128(),
+            risk_reduction_threshold: r.u128(),
+            maintenance_fee_per_slot: r.u128(),
+            max_crank_staleness_slots: r.u64(),
+            liquidation_fee_bps: r.u64(),
+            liquidation_fee_cap: r.u128(),
+            liquidation_buffer_bps: r.u64(),
+            min_liquidation_abs: r.u128(),
+        };
+        let mut s = Self {
+            vault,
+            insurance_fund,
+            params,
+            current_slot: r.u64(),
+            funding_index_qpb_e6: r.i128(),
+            last_funding_slot: r.u64(),
+            funding_rate_bps_per_slot_last: r.i64(),
+            last_crank_slot: r.u64(),
+            max_crank_staleness_slots: r.u64(),
+            total_open_interest: r.u128(),
+            c_tot: r.u128(),
+            pnl_pos_tot: r.u128(),
+            liq_cursor: r.u16(),
+            gc_cursor: r.u16(),
+            last_full_sweep_start_slot: r.u64(),
+            last_full_sweep_completed_slot: r.u64(),
+            crank_cursor: r.u16(),
+            sweep_start_idx: r.u16(),
+            lifetime_liquidations: r.u64(),
+            lifetime_force_realize_closes: r.u64(),
+            net_lp_pos: r.i128(),
+            lp_sum_abs: r.u128(),
+            lp_max_abs: r.u128(),
+            lp_max_abs_sweep: r.u128(),
+            num_used_accounts: 0,
+            next_account_id: 0,
+            free_head: 0,
+        };
+        debug_assert_eq!(r.pos, BITMAP_OFFSET);
+        r.pos = next_free_offset(w) - TAIL_LEN;
+        s.num_used_accounts = r.u16();
+        s.next_account_id = r.u64();
+        s.free_head = r.u16();
+        s
+    }
+}
+
+/// The slab sections `validate_layout` walks, read from a live engine or
+/// straight from an encoding
+pub(crate) trait Slab {
+    fn used_word(&self, word: usize) -> u64;
+    fn next_free(&self, idx: usize) -> u16;
+    fn account(&self, idx: usize) -> Result<Account, LayoutError>;
+
+    fn is_used(&self, idx: usize) -> bool {
+        (self.used_word(idx >> 6) >> (idx & 63)) & 1 == 1
+    }
+}
+
+impl<const N: usize, const W: usize> Slab for RiskEngine<N, W> {
+    fn used_word(&self, word: usize) -> u64 {
+        self.used[word]
+    }
+    fn next_free(&self, idx: usize) -> u16 {
+        self.next_free[idx]
+    }
+    fn account(&self, idx: usize) -> Result<Account, LayoutError> {
+        Ok(self.accounts[idx])
+    }
+}
+
+/// Slab sections of an encoded `RiskEngine<N, W>`
+struct Encoded<'a, const N: usize, const W: usize>(&'a [u8]);
+
+impl<const N: usize, const W: usize> Encoded<'_, N, W> {
+    fn reader(&self, pos: usize) -> Reader<'_> {
+        Reader { buf: self.0, pos }
+    }
+}
+
+impl<const N: usize, const W: usize> Slab for Encoded<'_, N, W> {
+    fn used_word(&self, word: usize) -> u64 {
+        self.reader(BITMAP_OFFSET + word * 8).u64()
+    }
+    fn next_free(&self, idx: usize) -> u16 {
+        self.reader(next_free_offset(W) + idx * 2).u16()
+    }
+    fn account(&self, idx: usize) -> Result<Account, LayoutError> {
+        let mut account = empty_account();
+        read_account(
+            &mut self.reader(accounts_offset(N, W) + idx * ACCOUNT_BYTES_LEN),
+            &mut account,
+        )?;
+        Ok(account)
+    }
+}
+
+/// Checks behind `validate_layout`, over scalars and slab from either source
+pub(crate) fn validate<const N: usize, const W: usize>(
+    s: &Scalars,
+    slab: &impl Slab,
+) -> Result<(), LayoutError> {
+    // Bitmap: nothing beyond N, popcount matches the counter
+    let mut used_count: u32 = 0;
+    for word_idx in 0..W {
+        let word = slab.used_word(word_idx);
+        let valid_bits = N.saturating_sub(word_idx * 64).min(64);
+        if valid_bits < 64 && word >> valid_bits != 0 {
+            return Err(LayoutError::CorruptBitmap);
+        }
+        used_count += word.count_ones();
+    }
+    if used_count != s.num_used_accounts as u32 {
+        return Err(LayoutError::CorruptBitmap);
+    }
+
+    // Freelist: in range, acyclic, disjoint from used, covers every free slot
+    let mut visited = [0u64; W];
+    let mut free_count: usize = 0;
+    let mut current = s.free_head;
+    while current != u16::MAX {
+        let idx = current as usize;
+        if idx >= N || slab.is_used(idx) {
+            return Err(LayoutError::CorruptFreelist);
+        }
+        let (w, b) = (idx >> 6, idx & 63);
+        if (visited[w] >> b) & 1 == 1 {
+            return Err(LayoutError::CorruptFreelist);
+        }
+        visited[w] |= 1u64 << b;
+        free_count += 1;
+        current = slab.next_free(idx);
+    }
+    if free_count + used_count as usize != N {
+        return Err(LayoutError::CorruptFreelist);
+    }
+
+    let max = N as u16;
+    if s.crank_cursor >= max
+        || s.gc_cursor >= max
+        || s.liq_cursor >= max
+        || s.sweep_start_idx >= max
+    {
+        return Err(LayoutError::InvalidCursor);
+    }
+
+    // Every slot decodes, including free ones, so a load writes only valid kinds
+    let mut sum_capital: u128 = 0;
+    let mut sum_pnl_pos: u128 = 0;
+    let mut sum_abs_pos: u128 = 0;
+    for idx in 0..N {
+        let account = slab.account(idx)?;
+        if !slab.is_used(idx) {
+            continue;
+        }
+        if account.account_id >= s.next_account_id {
+            return Err(LayoutError::InvalidAccountId);
+        }
+        if account.locked_capital.get() > account.capital.get() {
+            return Err(LayoutError::InvalidLockedCapital);
+        }
+        let pnl = account.pnl.get();
+        sum_capital = sum_capital.saturating_add(account.capital.get());
+        sum_pnl_pos = sum_pnl_pos.saturating_add(pnl.max(0) as u128);
+        sum_abs_pos = sum_abs_pos.saturating_add(account.position_size.get().unsigned_abs());
+    }
+    let (solvent, _) =
+        RiskEngine::signed_residual(s.vault.get(), s.c_tot.get(), s.insurance_fund.balance.get());
+    if s.c_tot.get() != sum_capital
+        || s.pnl_pos_tot.get() != sum_pnl_pos
+        || s.total_open_interest.get() != sum_abs_pos
+        || !solvent
+    {
+        return Err(LayoutError::InconsistentAggregates);
+    }
+    Ok(())
+}
+
+/// Header checks shared by every decoder
+fn check_header(bytes: &[u8], len: usize, hash: u64) -> Result<(), LayoutError> {
+    if bytes.len() != len {
+        return Err(LayoutError::BufferLength);
+    }
+    let mut r = Reader { buf: bytes, pos: 0 };
+    if r.array::<4>() != ENGINE_MAGIC {
+        return Err(LayoutError::BadMagic);
+    }
+    if r.u32() != ENGINE_LAYOUT_VERSION {
+        return Err(LayoutError::UnsupportedVersion);
+    }
+    if r.u64() != hash {
+        return Err(LayoutError::LayoutMismatch);
+    }
+    Ok(())
+}
+
+impl<const N: usize, const W: usize> RiskEngine<N, W> {
+    /// Exact size of this engine's encoding
+    pub const ENCODED_LEN: usize = engine_bytes_len(N, W);
+
+    /// Layout hash written to, and required in, this engine's header
+    pub const LAYOUT_HASH: u64 = layout_hash(N);
+
+    /// Encode the engine into `out`, which must be exactly `ENCODED_LEN`
+    /// bytes. Encoding does not validate; `load_from_bytes` does.
+    pub fn to_bytes(&self, out: &mut [u8]) -> Result<(), LayoutError> {
+        if out.len() != Self::ENCODED_LEN {
+            return Err(LayoutError::BufferLength);
+        }
+        let mut w = Writer::new(out);
+        write_header(&mut w, Self::LAYOUT_HASH);
+
+        let scalars = Scalars::of(self);
+        scalars.write_head(&mut w);
+        for &word in self.used.iter() {
+            w.u64(word);
+        }
+        scalars.write_tail(&mut w);
+        for &next in self.next_free.iter() {
+            w.u16(next);
+        }
+        for account in self.accounts.iter() {
+            write_account(&mut w, account);
+        }
+        debug_assert_eq!(w.pos, Self::ENCODED_LEN);
+        Ok(())
+    }
+
+    /// Encode into `buf` (exactly `ENCODED_LEN` bytes) and return it.
+    ///
+    /// This copies: the crate forbids `unsafe`, so the engine cannot be
+    /// viewed as bytes in place. It is `to_bytes` returning the encoded slice.
+    pub fn encode<'a>(&self, buf: &'a mut [u8]) -> Result<&'a [u8], LayoutError> {
+        self.to_bytes(buf)?;
+        Ok(buf)
+    }
+
+    /// Decode and validate `bytes` (produced by `to_bytes`) into a new engine.
+    ///
+    /// This copies: the crate forbids `unsafe`, so the bytes cannot be
+    /// reinterpreted as an engine. Like `new_sized`, it builds the whole
+    /// engine by value; in BPF use `load_from_bytes` into existing memory
+    /// instead.
+    pub fn decode(bytes: &[u8]) -> Result<Self, LayoutError> {
+        check_header(bytes, Self::ENCODED_LEN, Self::LAYOUT_HASH)?;
+        let mut engine = Self::new_sized(Scalars::read(bytes, W).params);
+        engine.load_from_bytes(bytes)?;
+        Ok(engine)
+    }
+
+    /// Decode `bytes` (produced by `to_bytes` on an engine of the same size)
+    /// into `self`.
+    ///
+    /// The header and every `validate_layout` check run against `bytes`
+    /// before anything is written, so on error `self` is left untouched.
+    pub fn load_from_bytes(&mut self, bytes: &[u8]) -> Result<(), LayoutError> {
+        check_header(bytes, Self::ENCODED_LEN, Self::LAYOUT_HASH)?;
+        let scalars = Scalars::read(bytes, W);
+        let encoded = Encoded::<N, W>(bytes);
+        validate::<N, W>(&scalars, &encoded)?;
+
+        scalars.apply(self);
+        for (word_idx, word) in self.used.iter_mut().enumerate() {
+            *word = encoded.used_word(word_idx);
+        }
+        for (idx, next) in self.next_free.iter_mut().enumerate() {
+            *next = encoded.next_free(idx);
+        }
+        let mut r = encoded.reader(accounts_offset(N, W));
+        for account in self.accounts.iter_mut() {
+            read_account(&mut r, account)?;
+        }
+        debug_assert_eq!(r.pos, Self::ENCODED_LEN);
+        Ok(())
+    }
+
+    /// Structural checks a loaded engine must pass before use: bitmap and
+    /// freelist consistency, cursor ranges, account ids and locked capital,
+    /// O(1) aggregates against account sums, and `vault >= c_tot + insurance`.
+    pub fn validate_layout(&self) -> Result<(), LayoutError> {
+        validate::<N, W>(&Scalars::of(self), self)
+    }
+}
@@ -0,0 +1,697 @@
+//! `percolator` command-line driver.
+//!
+//! Runs `RiskEngine` and prediction-market operations against state kept in a
+//! local file, so scenarios can be scripted, replayed and inspected without
+//! writing Rust.
+//!
+//! The state file is a replay log: one successfully executed command per line,
+//! in the same syntax as the command line. Each invocation rebuilds the state
+//! by replaying the log, runs the new command, and appends it only if it
+//! succeeds. Every operation is deterministic, so the log is both the state
+//! and its audit trail. Its header records the account and market capacities
+//! and the engine layout hash, and a log from a build that differs in any of
+//! them is refused rather than replayed.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process;
+
+use percolator::prediction::*;
+use percolator::{NoOpMatcher, RiskEngine, RiskError, RiskParams, MAX_ACCOUNTS, U128};
+
+const DEFAULT_STATE_FILE: &str = "percolator.state";
+
+/// Safe code can only build the engine and registry by value, and
+/// unoptimised builds keep a few copies of each on the stack while moving
+/// them into their boxes; run on a thread with room for that.
+const STACK_SIZE: usize =
+    4 * (core::mem::size_of::<RiskEngine>() + core::mem::size_of::<MarketRegistry>());
+const STATE_HEADER: &str = "# percolator state v2";
+
+/// First line of every state file. Slot indices in the log only mean the same
+/// thing to a build with the same capacities, so they are recorded with the
+/// engine layout hash and checked on load.
+fn state_header() -> String {
+    format!(
+        "{} accounts={} markets={} layout={:#018x}",
+        STATE_HEADER,
+        MAX_ACCOUNTS,
+        MAX_MARKETS,
+        <RiskEngine>::LAYOUT_HASH
+    )
+}
+
+const USAGE: &str = "\
+usage: percolator [--state FILE] <command> [args...]
+
+engine:
+  add-user <fee>
+  add-lp <fee>
+  deposit <idx> <amount> <slot>
+  withdraw <idx> <amount> <slot> <oracle_price>
+  trade <lp_idx> <user_idx> <slot> <oracle_price> <size>
+  crank <caller_idx> <slot> <oracle_price> [funding_rate_bps_per_slot]
+  liquidate <idx> <slot> <oracle_price>
+  close-account <idx> <slot> <oracle_price>
+
+prediction:
+  create-market <mint_hex> <created_slot> <close_slot> mcap|price <target>
+  stake <market_id> <idx> yes|no <amount> <slot>
+  close <market_id> <slot>
+  resolve <market_id> <slot> <market_cap> <price_e6>
+  settle <market_id> <creator_idx> <slot>
+
+inspect:
+  show
+
+State defaults to ./percolator.state.";
+
+/// One parsed command. Every variant except `Show` mutates state and is
+/// appended to the log.
+#[derive(Debug)]
+enum Command {
+    AddUser {
+        fee: u128,
+    },
+    AddLp {
+        fee: u128,
+    },
+    Deposit {
+        idx: u16,
+        amount: u128,
+        slot: u64,
+    },
+    Withdraw {
+        idx: u16,
+        amount: u128,
+        slot: u64,
+        oracle_price: u64,
+    },
+    Trade {
+        lp_idx: u16,
+        user_idx: u16,
+        slot: u64,
+        oracle_price: u64,
+        size: i128,
+    },
+    Crank {
+        caller_idx: u16,
+        slot: u64,
+        oracle_price: u64,
+        funding_rate_bps_per_slot: i64,
+    },
+    Liquidate {
+        idx: u16,
+        slot: u64,
+        oracle_price: u64,
+    },
+    CloseAccount {
+        idx: u16,
+        slot: u64,
+        oracle_price: u64,
+    },
+    CreateMarket {
+        mint: [u8; 32],
+        created_slot: u64,
+        close_slot: u64,
+        rule: MarketRule,
+    },
+    Stake {
+        market_id: u64,
+        idx: u16,
+        side: Outcome,
+        amount: u128,
+        slot: u64,
+    },
+    Close {
+        market_id: u64,
+        slot: u64,
+    },
+    Resolve {
+        market_id: u64,
+        slot: u64,
+        market_cap: u128,
+        price_e6: u64,
+    },
+    Settle {
+        market_id: u64,
+        creator_idx: u16,
+        slot: u64,
+    },
+    Show,
+}
+
+struct State {
+    engine: Box<RiskEngine>,
+    markets: Box<MarketRegistry>,
+}
+
+fn cli_params() -> RiskParams {
+    RiskParams {
+        warmup_period_slots: 100,
+        maintenance_margin_bps: 500,
+        initial_margin_bps: 1000,
+        trading_fee_bps: 10,
+        max_accounts: MAX_ACCOUNTS as u64,
+        new_account_fee: U128::new(0),
+        risk_reduction_threshold: U128::new(0),
+        maintenance_fee_per_slot: U128::new(0),
+        max_crank_staleness_slots: u64::MAX,
+        liquidation_fee_bps: 50,
+        liquidation_fee_cap: U128::new(100_000),
+        liquidation_buffer_bps: 100,
+        min_liquidation_abs: U128::new(100_000),
+    }
+}
+
+fn cli_market_params() -> MarketParams {
+    MarketParams {
+        max_snapshot_delay_slots: 100,
+        agreement_policy: AgreementPolicy::Exact,
+        dispute_timeout_slots: 100,
+        protocol_fee_bps: 0,
+        creator_fee_bps: 0,
+        betting_cutoff_slots: 1,
+        exit_fee_bps: 0,
+        min_total_stake: 0,
+        min_side_stake: 0,
+    }
+}
+
+/// Distinct owner per account, so engine-backed stakes can tell them apart
+fn account_owner(account_id: u64) -> [u8; 32] {
+    let mut owner = [0u8; 32];
+    owner[..8].copy_from_slice(&account_id.to_le_bytes());
+    owner
+}
+
+impl State {
+    fn new() -> Self {
+        Self {
+            engine: Box::new(RiskEngine::new(cli_params())),
+            markets: Box::new(MarketRegistry::new(EligibilityPolicy::default())),
+        }
+    }
+
+    fn add_account(&mut self, idx: u16) -> Result<String, String> {
+        let account_id = self.engine.accounts[idx as usize].account_id;
+        self.engine
+            .set_owner(idx, account_owner(account_id))
+            .map_err(|e| format!("{:?}", e))?;
+        Ok(format!("idx={} account_id={}", idx, account_id))
+    }
+
+    /// Run one command, returning its report
+    fn execute(&mut self, command: &Command) -> Result<String, String> {
+        let engine = &mut self.engine;
+        let err = |e: RiskError| format!("{:?}", e);
+        let perr = |e: PredictionError| format!("{:?}", e);
+        match *command {
+            Command::AddUser { fee } => {
+                let idx = engine.add_user(fee).map_err(err)?;
+                self.add_account(idx)
+            }
+            Command::AddLp { fee } => {
+                let idx = engine.add_lp([0; 32], [0; 32], fee).map_err(err)?;
+                self.add_account(idx)
+            }
+            Command::Deposit { idx, amount, slot } => {
+                engine.deposit(idx, amount, slot).map_err(err)?;
+                Ok(format!(
+                    "capital={}",
+                    engine.accounts[idx as usize].capital.get()
+                ))
+            }
+            Command::Withdraw {
+                idx,
+                amount,
+                slot,
+                oracle_price,
+            } => {
+                engine
+                    .withdraw(idx, amount, slot, oracle_price)
+                    .map_err(err)?;
+                Ok(format!(
+                    "capital={}",
+                    engine.accounts[idx as usize].capital.get()
+                ))
+            }
+            Command::Trade {
+                lp_idx,
+                user_idx,
+                slot,
+                oracle_price,
+                size,
+            } => {
+                engine
+                    .execute_trade(&NoOpMatcher, lp_idx, user_idx, slot, oracle_price, size)
+                    .map_err(err)?;
+                Ok(format!(
+                    "position_size={}",
+                    engine.accounts[user_idx as usize].position_size.get()
+                ))
+            }
+            Command::Crank {
+                caller_idx,
+                slot,
+                oracle_price,
+                funding_rate_bps_per_slot,
+            } => {
+                let outcome = engine
+                    .keeper_crank(
+                        caller_idx,
+                        slot,
+                        oracle_price,
+                        funding_rate_bps_per_slot,
+                        false,
+                    )
+                    .map_err(err)?;
+                let markets = self.markets.prediction_crank(slot, MAX_MARKETS as u16);
+                Ok(format!(
+                    "liquidations={} gc_closed={} sweep_complete={} \
+                     markets_closed={} markets_cancelled={}",
+                    outcome.num_liquidations,
+                    outcome.num_gc_closed,
+                    outcome.sweep_complete,
+                    markets.num_closed,
+                    markets.num_liquidity_cancelled + markets.num_dispute_cancelled,
+                ))
+            }
+            Command::Liquidate {
+                idx,
+                slot,
+                oracle_price,
+            } => {
+                let liquidated = engine
+                    .liquidate_at_oracle(idx, slot, oracle_price)
+                    .map_err(err)?;
+                Ok(format!("liquidated={}", liquidated))
+            }
+            Command::CloseAccount {
+                idx,
+                slot,
+                oracle_price,
+            } => {
+                let returned = engine.close_account(idx, slot, oracle_price).map_err(err)?;
+                Ok(format!("returned={}", returned))
+            }
+            Command::CreateMarket {
+                mint,
+                created_slot,
+                close_slot,
+                rule,
+            } => {
+                let token = TokenStatus {
+                    mint,
+                    migrated_to_pumpswap: true,
+                    launch_source: LaunchSource::PumpFun,
+                    migration_slot: 0,
+                    liquidity_quote_units: 0,
+                    market_cap_quote_units: 0,
+                    delisted: false,
+                };
+                let market_id = self
+                    .markets
+                    .create_market(token, created_slot, close_slot, rule, cli_market_params())
+                    .map_err(perr)?;
+                Ok(format!("market_id={}", market_id))
+            }
+            Command::Stake {
+                market_id,
+                idx,
+                side,
+                amount,
+                slot,
+            } => {
+                let state = self.markets.get_mut(market_id).map_err(perr)?;
+                state
+                    .stake_on_engine(engine, idx, side, amount, slot)
+                    .map_err(perr)?;
+                Ok(format!(
+                    "yes_capital={} no_capital={}",
+                    state.ledger.pools.yes_capital, state.ledger.pools.no_capital
+                ))
+            }
+            Command::Close { market_id, slot } => {
+                self.markets.close(market_id, slot).map_err(perr)?;
+                let state = self.markets.get(market_id).map_err(perr)?;
+                Ok(format!("phase={:?}", state.phase))
+            }
+            Command::Resolve {
+                market_id,
+                slot,
+                market_cap,
+                price_e6,
+            } => {
+                let state = self.markets.get_mut(market_id).map_err(perr)?;
+                let snapshot = TokenSnapshot {
+                    mint: state.market.token_mint,
+                    migrated_to_pumpswap: true,
+                    market_cap_quote_units: market_cap,
+                    price_e6,
+                    volume: None,
+                    migration_slot: Some(0),
+                    snapshot_slot: slot,
+                };
+                match state.resolve(&snapshot, slot).map_err(perr)? {
+                    ResolvedOutcome::Binary(outcome) => Ok(format!("outcome={:?}", outcome)),
+                    outcome => Ok(format!("outcome={:?}", outcome)),
+                }
+            }
+            Command::Settle {
+                market_id,
+                creator_idx,
+                slot,
+            } => {
+                let state = self.markets.get_mut(market_id).map_err(perr)?;
+                let s = state
+                    .settle_on_engine(engine, creator_idx, slot)
+                    .map_err(perr)?;
+                Ok(format!(
+                    "outcome={:?} winner_capital_total={} loser_capital_total={} \
+                     protocol_fee_paid={} creator_fee_paid={} profit_claim_total={} \
+                     h={}/{}",
+                    s.outcome,
+                    s.winner_capital_total,
+                    s.loser_capital_total,
+                    s.protocol_fee_paid,
+                    s.creator_fee_paid,
+                    s.profit_claim_total,
+                    s.h_num,
+                    s.h_den,
+                ))
+            }
+            Command::Show => Ok(self.show()),
+        }
+    }
+
+    fn show(&self) -> String {
+        let engine = &self.engine;
+        let mut out = format!(
+            "slot={} vault={} insurance={} c_tot={} pnl_pos_tot={} accounts={}",
+            engine.current_slot,
+            engine.vault.get(),
+            engine.insurance_fund.balance.get(),
+            engine.c_tot.get(),
+            engine.pnl_pos_tot.get(),
+            engine.num_used_accounts,
+        );
+        for (idx, account) in engine.accounts.iter().enumerate() {
+            if !engine.is_used(idx) {
+                continue;
+            }
+            out.push_str(&format!(
+                "\naccount idx={} id={} kind={:?} capital={} pnl={} position_size={}",
+                idx,
+                account.account_id,
+                account.kind,
+                account.capital.get(),
+                account.pnl.get(),
+                account.position_size.get(),
+            ));
+        }
+        for idx in 0..MAX_MARKETS {
+            if !self.markets.is_used(idx) {
+                continue;
+            }
+            let state = &self.markets.markets[idx];
+            out.push_str(&format!(
+                "\nmarket id={} phase={:?} close_slot={} yes_capital={} no_capital={}",
+                state.market.market_id,
+                state.phase,
+                state.market.close_slot,
+                state.ledger.pools.yes_capital,
+                state.ledger.pools.no_capital,
+            ));
+        }
+        out
+    }
+}
+
+fn parse_num<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid {}: {}", name, value))
+}
+
+/// Up to 64 hex digits, right-aligned into 32 bytes
+fn parse_mint(value: &str) -> Result<[u8; 32], String> {
+    let invalid = || format!("invalid mint (expected up to 64 hex digits): {}", value);
+    if value.is_empty() || value.len() > 64 || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let padded = format!("{:0>64}", value);
+    let mut mint = [0u8; 32];
+    for (i, byte) in mint.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&padded[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(mint)
+}
+
+fn parse_side(value: &str) -> Result<Outcome, String> {
+    match value {
+        "yes" => Ok(Outcome::Yes),
+        "no" => Ok(Outcome::No),
+        _ => Err(format!("invalid side (expected yes|no): {}", value)),
+    }
+}
+
+fn parse_rule(kind: &str, target: &str) -> Result<MarketRule, String> {
+    match kind {
+        "mcap" => Ok(MarketRule::MarketCapAtCloseAtLeast {
+            target_quote_units: parse_num("target", target)?,
+        }),
+        "price" => Ok(MarketRule::PriceAtCloseAtLeast {
+            target_price_e6: parse_num("target", target)?,
+        }),
+        _ => Err(format!("invalid rule (expected mcap|price): {}", kind)),
+    }
+}
+
+fn parse_command(args: &[&str]) -> Result<Command, String> {
+    let (&name, rest) = args.split_first().ok_or("missing command")?;
+    let arity = |min: usize, max: usize| {
+        if rest.len() < min || rest.len() > max {
+            Err(format!("wrong number of arguments for {}", name))
+        } else {
+            Ok(())
+        }
+    };
+    let command = match name {
+        "add-user" => {
+            arity(1, 1)?;
+            Command::AddUser {
+                fee: parse_num("fee", rest[0])?,
+            }
+        }
+        "add-lp" => {
+            arity(1, 1)?;
+            Command::AddLp {
+                fee: parse_num("fee", rest[0])?,
+            }
+        }
+        "deposit" => {
+            arity(3, 3)?;
+            Command::Deposit {
+                idx: parse_num("idx", rest[0])?,
+                amount: parse_num("amount", rest[1])?,
+                slot: parse_num("slot", rest[2])?,
+            }
+        }
+        "withdraw" => {
+            arity(4, 4)?;
+            Command::Withdraw {
+                idx: parse_num("idx", rest[0])?,
+                amount: parse_num("amount", rest[1])?,
+                slot: parse_num("slot", rest[2])?,
+                oracle_price: parse_num("oracle_price", rest[3])?,
+            }
+        }
+        "trade" => {
+            arity(5, 5)?;
+            Command::Trade {
+                lp_idx: parse_num("lp_idx", rest[0])?,
+                user_idx: parse_num("user_idx", rest[1])?,
+                slot: parse_num("slot", rest[2])?,
+                oracle_price: parse_num("oracle_price", rest[3])?,
+                size: parse_num("size", rest[4])?,
+            }
+        }
+        "crank" => {
+            arity(3, 4)?;
+            Command::Crank {
+                caller_idx: parse_num("caller_idx", rest[0])?,
+                slot: parse_num("slot", rest[1])?,
+                oracle_price: parse_num("oracle_price", rest[2])?,
+                funding_rate_bps_per_slot: match rest.get(3) {
+                    Some(rate) => parse_num("funding_rate_bps_per_slot", rate)?,
+                    None => 0,
+                },
+            }
+        }
+        "liquidate" => {
+            arity(3, 3)?;
+            Command::Liquidate {
+                idx: parse_num("idx", rest[0])?,
+                slot: parse_num("slot", rest[1])?,
+                oracle_price: parse_num("oracle_price", rest[2])?,
+            }
+        }
+        "close-account" => {
+            arity(3, 3)?;
+            Command::CloseAccount {
+                idx: parse_num("idx", rest[0])?,
+                slot: parse_num("slot", rest[1])?,
+                oracle_price: parse_num("oracle_price", rest[2])?,
+            }
+        }
+        "create-market" => {
+            arity(5, 5)?;
+            Command::CreateMarket {
+                mint: parse_mint(rest[0])?,
+                created_slot: parse_num("created_slot", rest[1])?,
+                close_slot: parse_num("close_slot", rest[2])?,
+                rule: parse_rule(rest[3], rest[4])?,
+            }
+        }
+        "stake" => {
+            arity(5, 5)?;
+            Command::Stake {
+                market_id: parse_num("market_id", rest[0])?,
+                idx: parse_num("idx", rest[1])?,
+                side: parse_side(rest[2])?,
+                amount: parse_num("amount", rest[3])?,
+                slot: parse_num("slot", rest[4])?,
+            }
+        }
+        "close" => {
+            arity(2, 2)?;
+            Command::Close {
+                market_id: parse_num("market_id", rest[0])?,
+                slot: parse_num("slot", rest[1])?,
+            }
+        }
+        "resolve" => {
+            arity(4, 4)?;
+            Command::Resolve {
+                market_id: parse_num("market_id", rest[0])?,
+                slot: parse_num("slot", rest[1])?,
+                market_cap: parse_num("market_cap", rest[2])?,
+                price_e6: parse_num("price_e6", rest[3])?,
+            }
+        }
+        "settle" => {
+            arity(3, 3)?;
+            Command::Settle {
+                market_id: parse_num("market_id", rest[0])?,
+                creator_idx: parse_num("creator_idx", rest[1])?,
+                slot: parse_num("slot", rest[2])?,
+            }
+        }
+        "show" => {
+            arity(0, 0)?;
+            Command::Show
+        }
+        _ => return Err(format!("unknown command: {}", name)),
+    };
+    Ok(command)
+}
+
+/// Rebuild state by replaying every logged command
+fn load(path: &str) -> Result<State, String> {
+    let mut state = State::new();
+    let log = match fs::read_to_string(path) {
+        Ok(log) => log,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(state),
+        Err(e) => return Err(format!("{}: {}", path, e)),
+    };
+    let header = state_header();
+    match log.lines().next() {
+        None => return Ok(state),
+        Some(first) if first.trim() == header => {}
+        Some(first) => {
+            return Err(format!(
+                "{}: written by an incompatible build: expected `{}`, found `{}`",
+                path,
+                header,
+                first.trim()
+            ))
+        }
+    }
+    for (n, line) in log.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let args: Vec<&str> = line.split_whitespace().collect();
+        parse_command(&args)
+            .and_then(|command| state.execute(&command))
+            .map_err(|e| format!("{}:{}: replay failed: {}", path, n + 1, e))?;
+    }
+    Ok(state)
+}
+
+fn append(path: &str, args: &[&str]) -> Result<(), String> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    // An empty file (e.g. made with `touch`) still needs its header
+    let is_empty = file
+        .metadata()
+        .map_err(|e| format!("{}: {}", path, e))?
+        .len()
+        == 0;
+    let mut entry = String::new();
+    if is_empty {
+        entry.push_str(&state_header());
+        entry.push('\n');
+    }
+    entry.push_str(&args.join(" "));
+    entry.push('\n');
+    file.write_all(entry.as_bytes())
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+fn run(args: Vec<String>) -> Result<String, (i32, String)> {
+    let usage = |msg: String| (2, format!("{}\n\n{}", msg, USAGE));
+    let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let mut path = DEFAULT_STATE_FILE;
+    if args.first() == Some(&"--state") {
+        if args.len() < 2 {
+            return Err(usage("--state needs a file".into()));
+        }
+        path = args[1];
+        args.drain(..2);
+    }
+    if args.is_empty() || args[0] == "help" || args[0] == "--help" {
+        return Err((2, USAGE.into()));
+    }
+
+    let command = parse_command(&args).map_err(usage)?;
+    let mut state = load(path).map_err(|e| (1, e))?;
+    let report = state.execute(&command).map_err(|e| (1, e))?;
+    if !matches!(command, Command::Show) {
+        append(path, &args).map_err(|e| (1, e))?;
+    }
+    Ok(report)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(args))
+        .expect("spawn worker thread")
+        .join()
+        .expect("worker thread panicked");
+    match result {
+        Ok(report) => println!("{}", report),
+        Err((code, msg)) => {
+            eprintln!("error: {}", msg);
+            process::exit(code);
+        }
+    }
+}
@@ -39,8 +39,6 @@ pub const MAX_ACCOUNTS: usize = 4096; // Production
 // Derived constants - all use size_of, no hardcoded values
 pub const BITMAP_WORDS: usize = (MAX_ACCOUNTS + 63) / 64;
 pub const MAX_ROUNDING_SLACK: u128 = MAX_ACCOUNTS as u128;
-/// Mask for wrapping indices (MAX_ACCOUNTS must be power of 2)
-const ACCOUNT_IDX_MASK: usize = MAX_ACCOUNTS - 1;
 
 /// Maximum number of dust accounts to close per crank call.
 /// Limits compute usage while still making progress on cleanup.
@@ -73,6 +71,9 @@ pub const MAX_POSITION_ABS: u128 = 100_000_000_000_000_000_000;
 pub mod i128;
 pub use i128::{I128, U128};
 pub mod prediction;
+pub mod layout;
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
 
 // ============================================================================
 // Core Data Structures
@@ -165,6 +166,9 @@ pub struct Account {
     /// Last slot when maintenance fees were settled for this account
     pub last_fee_slot: u64,
 
+    /// Capital committed elsewhere, e.g. prediction-market stakes (see
+    /// `RiskEngine::lock_capital`). Never above `capital`.
+    pub locked_capital: U128,
 }
 
 impl Account {
@@ -177,10 +181,15 @@ impl Account {
     pub fn is_user(&self) -> bool {
         matches!(self.kind, AccountKind::User)
     }
+
+    /// Capital that withdrawals and maintenance fees may draw on
+    pub fn free_capital(&self) -> u128 {
+        self.capital.get().saturating_sub(self.locked_capital.get())
+    }
 }
 
 /// Helper to create empty account
-fn empty_account() -> Account {
+const fn empty_account() -> Account {
     Account {
         account_id: 0,
         capital: U128::ZERO,
@@ -197,6 +206,7 @@ fn empty_account() -> Account {
         owner: [0; 32],
         fee_credits: I128::ZERO,
         last_fee_slot: 0,
+        locked_capital: U128::ZERO,
     }
 }
 
@@ -242,7 +252,7 @@ pub struct RiskParams {
     /// Trading fee in basis points
     pub trading_fee_bps: u64,
 
-    /// Maximum number of accounts
+    /// Maximum number of accounts (further capped by the engine's slab size)
     pub max_accounts: u64,
 
     /// Flat account creation fee (absolute amount in capital units)
@@ -287,9 +297,14 @@ pub struct RiskParams {
 }
 
 /// Main risk engine state - fixed slab with bitmap
+///
+/// `N` is the number of account slots and `W = (N + 63) / 64` the number of
+/// bitmap words (stable Rust cannot derive one from the other). Both default
+/// to the feature-selected `MAX_ACCOUNTS`, so `RiskEngine` alone is the
+/// default size; `RiskEngine::<64, 1>::new_sized(params)` builds a smaller one.
 #[repr(C)]
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct RiskEngine {
+pub struct RiskEngine<const N: usize = MAX_ACCOUNTS, const W: usize = BITMAP_WORDS> {
     /// Total vault balance (all deposited funds)
     pub vault: U128,
 
@@ -343,10 +358,10 @@ pub struct RiskEngine {
     // ========================================
     // Crank Cursors (bounded scan support)
     // ========================================
-    /// Cursor for liquidation scan (wraps around MAX_ACCOUNTS)
+    /// Cursor for liquidation scan (wraps around N)
     pub liq_cursor: u16,
 
-    /// Cursor for garbage collection scan (wraps around MAX_ACCOUNTS)
+    /// Cursor for garbage collection scan (wraps around N)
     pub gc_cursor: u16,
 
     /// Slot when the current full sweep started (step 0 was executed)
@@ -391,8 +406,8 @@ pub struct RiskEngine {
     // ========================================
     // Slab Management
     // ========================================
-    /// Occupancy bitmap (4096 bits = 64 u64 words)
-    pub used: [u64; BITMAP_WORDS],
+    /// Occupancy bitmap (N bits in W u64 words)
+    pub used: [u64; W],
 
     /// Number of used accounts (O(1) counter, fixes H2: fee bypass TOCTOU)
     pub num_used_accounts: u16,
@@ -405,10 +420,10 @@ pub struct RiskEngine {
 
 
     /// Freelist next pointers
-    pub next_free: [u16; MAX_ACCOUNTS],
+    pub next_free: [u16; N],
 
-    /// Account slab (4096 accounts)
-    pub accounts: [Account; MAX_ACCOUNTS],
+    /// Account slab (N accounts)
+    pub accounts: [Account; N],
 }
 
 // ============================================================================
@@ -446,6 +461,9 @@ pub enum RiskError {
 
     /// Account kind mismatch
     AccountKindMismatch,
+
+    /// Account has locked capital, which rules out trading and closing
+    CapitalLocked,
 }
 
 pub type Result<T> = core::result::Result<T, RiskError>;
@@ -629,6 +647,233 @@ impl MatchingEngine for NoOpMatcher {
     }
 }
 
+// ============================================================================
+// Event Sink
+// ============================================================================
+
+/// Which fee an `EngineEvent::FeeCharged` books into insurance
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeeKind {
+    /// Trading fee on `execute_trade` (also credited to the user's fee_credits)
+    Trading,
+    /// Maintenance fee debt paid from capital or from a deposit
+    Maintenance,
+    /// Liquidation fee, capped at `liquidation_fee_cap`
+    Liquidation,
+}
+
+/// Typed record of a state change, emitted while an operation runs.
+///
+/// Capital/PnL/position amounts are the account's values immediately around
+/// the change. Funding and mark-to-oracle settlement also move PnL but are not
+/// reported; they are recomputable from the funding index and oracle price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EngineEvent {
+    /// Deposit credited to capital (after any fee debt paid from it)
+    Deposited {
+        idx: u16,
+        amount: u128,
+        capital_before: u128,
+        capital_after: u128,
+    },
+    /// Capital withdrawn from the vault
+    Withdrawn {
+        idx: u16,
+        amount: u128,
+        capital_before: u128,
+        capital_after: u128,
+    },
+    /// Fill between a user and an LP (`size` from the user's side)
+    Traded {
+        user_idx: u16,
+        lp_idx: u16,
+        size: i128,
+        price: u64,
+        user_position_before: i128,
+        user_position_after: i128,
+        lp_position_before: i128,
+        lp_position_after: i128,
+    },
+    /// Position reduced or closed by `liquidate_at_oracle` (fee follows separately)
+    Liquidated {
+        idx: u16,
+        oracle_price: u64,
+        position_before: i128,
+        position_after: i128,
+        capital_before: u128,
+        capital_after: u128,
+    },
+    /// Negative-equity or dust position closed at oracle by the crank
+    ForceClosed {
+        idx: u16,
+        oracle_price: u64,
+        position_before: i128,
+        capital_before: u128,
+        capital_after: u128,
+    },
+    /// Fee booked into insurance (capital is unchanged when paid from a deposit)
+    FeeCharged {
+        idx: u16,
+        kind: FeeKind,
+        amount: u128,
+        capital_before: u128,
+        capital_after: u128,
+    },
+    /// Negative PnL paid from capital, with any remainder written off (spec §6.1)
+    LossSettled {
+        idx: u16,
+        pnl_before: i128,
+        pnl_after: i128,
+        capital_before: u128,
+        capital_after: u128,
+    },
+    /// Warmed profit converted to capital at the haircut ratio (spec §6.2)
+    WarmupConverted {
+        idx: u16,
+        pnl_before: i128,
+        pnl_after: i128,
+        capital_before: u128,
+        capital_after: u128,
+    },
+    /// Account closed by its owner; `capital` left the vault
+    AccountClosed {
+        idx: u16,
+        account_id: u64,
+        capital: u128,
+    },
+    /// Dust slot freed by garbage collection; negative PnL written off
+    DustCollected {
+        idx: u16,
+        account_id: u64,
+        pnl_written_off: u128,
+    },
+    /// Losing prediction-market stake debited from capital at settlement
+    StakeLost {
+        idx: u16,
+        market_id: u64,
+        amount: u128,
+        capital_before: u128,
+        capital_after: u128,
+    },
+    /// Prediction-market winnings credited as junior PnL at settlement
+    WinningsCredited {
+        idx: u16,
+        market_id: u64,
+        amount: u128,
+        pnl_before: i128,
+        pnl_after: i128,
+    },
+    /// Prediction-market creator fee credited to the creator's capital
+    CreatorFeePaid {
+        idx: u16,
+        market_id: u64,
+        amount: u128,
+        capital_before: u128,
+        capital_after: u128,
+    },
+    /// Prediction-market protocol fee and floor dust booked into insurance
+    ProtocolFeeCollected {
+        market_id: u64,
+        amount: u128,
+        rounding_dust: u128,
+    },
+}
+
+/// Receiver for `EngineEvent`s (indexers, audit logs).
+///
+/// Events are emitted as state changes, in order. If the operation then
+/// returns `Err`, the transaction is expected to abort, so the sink should
+/// drop the events it received for that operation.
+pub trait EventSink {
+    fn on_event(&mut self, event: &EngineEvent);
+}
+
+/// Sink that discards every event (used by the sink-less entry points)
+pub struct NoOpSink;
+
+impl EventSink for NoOpSink {
+    fn on_event(&mut self, _event: &EngineEvent) {}
+}
+
+/// Running sums behind `check_conservation`, fed one used account at a
+/// time so that account sets not yet in an engine can be checked too
+#[derive(Default)]
+pub(crate) struct ConservationSums {
+    total_capital: u128,
+    net_pnl: i128,
+    net_mark: i128,
+    mark_failed: bool,
+}
+
+impl ConservationSums {
+    pub(crate) fn add(&mut self, account: &Account, global_index: I128, oracle_price: u64) {
+        self.total_capital = add_u128(self.total_capital, account.capital.get());
+
+        // Compute "would-be settled" PNL for this account
+        let mut settled_pnl = account.pnl.get();
+        if !account.position_size.is_zero() {
+            let delta_f = global_index
+                .get()
+                .saturating_sub(account.funding_index.get());
+            if delta_f != 0 {
+                let raw = account.position_size.get().saturating_mul(delta_f);
+                let payment = if raw > 0 {
+                    raw.saturating_add(999_999).saturating_div(1_000_000)
+                } else {
+                    raw.saturating_div(1_000_000)
+                };
+                settled_pnl = settled_pnl.saturating_sub(payment);
+            }
+
+            match RiskEngine::mark_pnl_for_position(
+                account.position_size.get(),
+                account.entry_price,
+                oracle_price,
+            ) {
+                Ok(mark) => {
+                    self.net_mark = self.net_mark.saturating_add(mark);
+                }
+                Err(_) => {
+                    self.mark_failed = true;
+                }
+            }
+        }
+        self.net_pnl = self.net_pnl.saturating_add(settled_pnl);
+    }
+
+    /// Whether the accounts added so far conserve value against `vault` and
+    /// `insurance`, with at most `max_slack` of rounding slack
+    pub(crate) fn holds(&self, vault: u128, insurance: u128, max_slack: u128) -> bool {
+        if self.mark_failed {
+            return false;
+        }
+
+        // Conservation: vault >= C_tot + I (primary invariant)
+        let primary = vault >= self.total_capital.saturating_add(insurance);
+        if !primary {
+            return false;
+        }
+
+        // Extended: vault >= sum(capital) + sum(settled_pnl + mark_pnl) + insurance
+        let total_pnl = self.net_pnl.saturating_add(self.net_mark);
+        let base = add_u128(self.total_capital, insurance);
+
+        let expected = if total_pnl >= 0 {
+            add_u128(base, total_pnl as u128)
+        } else {
+            base.saturating_sub(neg_i128_to_u128(total_pnl))
+        };
+
+        let actual = vault;
+
+        if actual < expected {
+            return false;
+        }
+        let slack = actual - expected;
+        slack <= max_slack
+    }
+}
+
 // ============================================================================
 // Core Implementation
 // ============================================================================
@@ -639,6 +884,67 @@ impl RiskEngine {
     /// WARNING: This allocates ~6MB on the stack at MAX_ACCOUNTS=4096.
     /// For Solana BPF programs, use `init_in_place` instead.
     pub fn new(params: RiskParams) -> Self {
+        Self::new_sized(params)
+    }
+
+    /// Compute vault residual: vault - c_tot - insurance.
+    /// Returns (is_positive, abs_value) to avoid i128 overflow on large u128 inputs.
+    /// is_positive == true  ⟹ residual = +abs_value (solvent)
+    /// is_positive == false ⟹ residual = -abs_value (deficit / bad debt)
+    #[inline]
+    pub fn signed_residual(vault: u128, c_tot: u128, insurance: u128) -> (bool, u128) {
+        let obligations = c_tot.saturating_add(insurance);
+        if vault >= obligations {
+            (true, vault - obligations)
+        } else {
+            (false, obligations - vault)
+        }
+    }
+
+    /// Compute mark PnL for a position at oracle price (pure helper, no side effects).
+    /// Returns the PnL from closing the position at oracle price.
+    /// - Longs: profit when oracle > entry
+    /// - Shorts: profit when entry > oracle
+    pub fn mark_pnl_for_position(pos: i128, entry: u64, oracle: u64) -> Result<i128> {
+        if pos == 0 {
+            return Ok(0);
+        }
+
+        let abs_pos = saturating_abs_i128(pos) as u128;
+
+        let diff: i128 = if pos > 0 {
+            // Long: profit when oracle > entry
+            (oracle as i128).saturating_sub(entry as i128)
+        } else {
+            // Short: profit when entry > oracle
+            (entry as i128).saturating_sub(oracle as i128)
+        };
+
+        // mark_pnl = diff * abs_pos / 1_000_000
+        diff.checked_mul(abs_pos as i128)
+            .ok_or(RiskError::Overflow)?
+            .checked_div(1_000_000)
+            .ok_or(RiskError::Overflow)
+    }
+}
+
+impl<const N: usize, const W: usize> RiskEngine<N, W> {
+    /// Number of account slots
+    pub const CAPACITY: usize = N;
+
+    /// Mask for wrapping indices (N must be power of 2)
+    const IDX_MASK: usize = N - 1;
+
+    /// Evaluated by the constructors: rejects unusable slab sizes at compile time
+    const SHAPE_OK: () = assert!(
+        N.is_power_of_two() && N < u16::MAX as usize && W == N.div_ceil(64),
+        "RiskEngine<N, W> needs N a power of two below u16::MAX and W = (N + 63) / 64"
+    );
+
+    /// Create an engine with an explicit slab size, e.g.
+    /// `RiskEngine::<64, 1>::new_sized(params)`. See `new` for the stack caveat.
+    pub fn new_sized(params: RiskParams) -> Self {
+        let () = Self::SHAPE_OK;
         let mut engine = Self {
             vault: U128::ZERO,
             insurance_fund: InsuranceFund {
@@ -667,19 +973,19 @@ impl RiskEngine {
             lp_sum_abs: U128::ZERO,
             lp_max_abs: U128::ZERO,
             lp_max_abs_sweep: U128::ZERO,
-            used: [0; BITMAP_WORDS],
+            used: [0; W],
             num_used_accounts: 0,
             next_account_id: 0,
             free_head: 0,
-            next_free: [0; MAX_ACCOUNTS],
-            accounts: [empty_account(); MAX_ACCOUNTS],
+            next_free: [0; N],
+            accounts: [empty_account(); N],
         };
 
-        // Initialize freelist: 0 -> 1 -> 2 -> ... -> 4095 -> NONE
-        for i in 0..MAX_ACCOUNTS - 1 {
+        // Initialize freelist: 0 -> 1 -> 2 -> ... -> N-1 -> NONE
+        for i in 0..N - 1 {
             engine.next_free[i] = (i + 1) as u16;
         }
-        engine.next_free[MAX_ACCOUNTS - 1] = u16::MAX; // Sentinel
+        engine.next_free[N - 1] = u16::MAX; // Sentinel
 
         engine
     }
@@ -692,20 +998,21 @@ impl RiskEngine {
     /// This is the correct way to initialize RiskEngine in Solana BPF programs
     /// where stack space is limited to 4KB.
     pub fn init_in_place(&mut self, params: RiskParams) {
+        let () = Self::SHAPE_OK;
         // Set params (non-zero field)
         self.params = params;
         self.max_crank_staleness_slots = params.max_crank_staleness_slots;
 
-        // Initialize freelist: 0 -> 1 -> 2 -> ... -> MAX_ACCOUNTS-1 -> NONE
+        // Initialize freelist: 0 -> 1 -> 2 -> ... -> N-1 -> NONE
         // All other fields are zero which is correct for:
         // - vault, insurance_fund, current_slot, funding_index, etc. = 0
         // - used bitmap = all zeros (no accounts in use)
         // - accounts = all zeros (equivalent to empty_account())
         // - free_head = 0 (first free slot is 0)
-        for i in 0..MAX_ACCOUNTS - 1 {
+        for i in 0..N - 1 {
             self.next_free[i] = (i + 1) as u16;
         }
-        self.next_free[MAX_ACCOUNTS - 1] = u16::MAX; // Sentinel
+        self.next_free[N - 1] = u16::MAX; // Sentinel
     }
 
     // ========================================
@@ -713,7 +1020,7 @@ impl RiskEngine {
     // ========================================
 
     pub fn is_used(&self, idx: usize) -> bool {
-        if idx >= MAX_ACCOUNTS {
+        if idx >= N {
             return false;
         }
         let w = idx >> 6;
@@ -740,7 +1047,7 @@ impl RiskEngine {
                 let bit = w.trailing_zeros() as usize;
                 let idx = block * 64 + bit;
                 w &= w - 1; // Clear lowest bit
-                if idx >= MAX_ACCOUNTS {
+                if idx >= N {
                     continue; // Guard against stray high bits in bitmap
                 }
                 f(idx, &mut self.accounts[idx]);
@@ -755,7 +1062,7 @@ impl RiskEngine {
                 let bit = w.trailing_zeros() as usize;
                 let idx = block * 64 + bit;
                 w &= w - 1; // Clear lowest bit
-                if idx >= MAX_ACCOUNTS {
+                if idx >= N {
                     continue; // Guard against stray high bits in bitmap
                 }
                 f(idx, &self.accounts[idx]);
@@ -819,7 +1126,7 @@ impl RiskEngine {
         if pnl_pos_tot == 0 {
             return (1, 1);
         }
-        let (solvent, residual) = Self::signed_residual(
+        let (solvent, residual) = RiskEngine::signed_residual(
             self.vault.get(),
             self.c_tot.get(),
             self.insurance_fund.balance.get(),
@@ -830,20 +1137,6 @@ impl RiskEngine {
         (h_num, pnl_pos_tot)
     }
 
-    /// Compute vault residual: vault - c_tot - insurance.
-    /// Returns (is_positive, abs_value) to avoid i128 overflow on large u128 inputs.
-    /// is_positive == true  ⟹ residual = +abs_value (solvent)
-    /// is_positive == false ⟹ residual = -abs_value (deficit / bad debt)
-    #[inline]
-    pub fn signed_residual(vault: u128, c_tot: u128, insurance: u128) -> (bool, u128) {
-        let obligations = c_tot.saturating_add(insurance);
-        if vault >= obligations {
-            (true, vault - obligations)
-        } else {
-            (false, obligations - vault)
-        }
-    }
-
     /// Compute effective positive PnL after haircut for a given account PnL (spec §3.3).
     /// PNL_eff_pos_i = floor(max(PNL_i, 0) * h_num / h_den)
     #[inline]
@@ -910,7 +1203,7 @@ impl RiskEngine {
     pub fn add_user(&mut self, fee_payment: u128) -> Result<u16> {
         // Use O(1) counter instead of O(N) count_used() (fixes H2: TOCTOU fee bypass)
         let used_count = self.num_used_accounts as u64;
-        if used_count >= self.params.max_accounts {
+        if used_count >= self.params.max_accounts.min(N as u64) {
             return Err(RiskError::Overflow);
         }
 
@@ -951,6 +1244,7 @@ impl RiskEngine {
             owner: [0; 32],
             fee_credits: I128::ZERO,
             last_fee_slot: self.current_slot,
+            locked_capital: U128::ZERO,
         };
 
         // Maintain c_tot aggregate (account was created with capital = excess)
@@ -970,7 +1264,7 @@ impl RiskEngine {
     ) -> Result<u16> {
         // Use O(1) counter instead of O(N) count_used() (fixes H2: TOCTOU fee bypass)
         let used_count = self.num_used_accounts as u64;
-        if used_count >= self.params.max_accounts {
+        if used_count >= self.params.max_accounts.min(N as u64) {
             return Err(RiskError::Overflow);
         }
 
@@ -1011,6 +1305,7 @@ impl RiskEngine {
             owner: [0; 32],
             fee_credits: I128::ZERO,
             last_fee_slot: self.current_slot,
+            locked_capital: U128::ZERO,
         };
 
         // Maintain c_tot aggregate (account was created with capital = excess)
@@ -1041,7 +1336,18 @@ impl RiskEngine {
         now_slot: u64,
         oracle_price: u64,
     ) -> Result<u128> {
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+        self.settle_maintenance_fee_with_sink(&mut NoOpSink, idx, now_slot, oracle_price)
+    }
+
+    /// `settle_maintenance_fee`, reporting the fee paid from capital to `sink`
+    pub fn settle_maintenance_fee_with_sink(
+        &mut self,
+        sink: &mut dyn EventSink,
+        idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<u128> {
+        if idx as usize >= N || !self.is_used(idx as usize) {
             return Err(RiskError::Unauthorized);
         }
 
@@ -1071,7 +1377,8 @@ impl RiskEngine {
         if self.accounts[idx as usize].fee_credits.is_negative() {
             let owed = neg_i128_to_u128(self.accounts[idx as usize].fee_credits.get());
             let current_cap = self.accounts[idx as usize].capital.get();
-            let pay = core::cmp::min(owed, current_cap);
+            // Locked capital is not the account's to spend
+            let pay = core::cmp::min(owed, self.accounts[idx as usize].free_capital());
 
             // Use set_capital helper to maintain c_tot aggregate (spec §4.1)
             self.set_capital(idx as usize, current_cap.saturating_sub(pay));
@@ -1082,6 +1389,7 @@ impl RiskEngine {
             self.accounts[idx as usize].fee_credits =
                 self.accounts[idx as usize].fee_credits.saturating_add(pay as i128);
             paid_from_capital = pay;
+            Self::emit_maintenance_fee(sink, idx, pay, current_cap);
         }
 
         // Check maintenance margin if account has a position (MTM check)
@@ -1102,10 +1410,11 @@ impl RiskEngine {
     /// - Still returns Unauthorized if idx invalid
     fn settle_maintenance_fee_best_effort_for_crank(
         &mut self,
+        sink: &mut dyn EventSink,
         idx: u16,
         now_slot: u64,
     ) -> Result<u128> {
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+        if idx as usize >= N || !self.is_used(idx as usize) {
             return Err(RiskError::Unauthorized);
         }
 
@@ -1133,7 +1442,8 @@ impl RiskEngine {
         if self.accounts[idx as usize].fee_credits.is_negative() {
             let owed = neg_i128_to_u128(self.accounts[idx as usize].fee_credits.get());
             let current_cap = self.accounts[idx as usize].capital.get();
-            let pay = core::cmp::min(owed, current_cap);
+            // Locked capital is not the account's to spend
+            let pay = core::cmp::min(owed, self.accounts[idx as usize].free_capital());
 
             // Use set_capital helper to maintain c_tot aggregate (spec §4.1)
             self.set_capital(idx as usize, current_cap.saturating_sub(pay));
@@ -1143,30 +1453,44 @@ impl RiskEngine {
             self.accounts[idx as usize].fee_credits =
                 self.accounts[idx as usize].fee_credits.saturating_add(pay as i128);
             paid_from_capital = pay;
+            Self::emit_maintenance_fee(sink, idx, pay, current_cap);
         }
 
         Ok(paid_from_capital) // Return actual amount paid into insurance
     }
 
+    fn emit_maintenance_fee(sink: &mut dyn EventSink, idx: u16, pay: u128, capital_before: u128) {
+        if pay > 0 {
+            sink.on_event(&EngineEvent::FeeCharged {
+                idx,
+                kind: FeeKind::Maintenance,
+                amount: pay,
+                capital_before,
+                capital_after: capital_before - pay,
+            });
+        }
+    }
+
     /// Best-effort warmup settlement for crank: settles any warmed positive PnL to capital.
     /// Silently ignores errors (e.g., account not found) since crank must not stall on
     /// individual account issues. Used to drain abandoned accounts' positive PnL over time.
-    fn settle_warmup_to_capital_for_crank(&mut self, idx: u16) {
+    fn settle_warmup_to_capital_for_crank(&mut self, sink: &mut dyn EventSink, idx: u16) {
         // Ignore errors: crank is best-effort and must continue processing other accounts
-        let _ = self.settle_warmup_to_capital(idx);
+        let _ = self.settle_warmup_to_capital_with_sink(sink, idx);
     }
 
     /// Pay down existing fee debt (negative fee_credits) using available capital.
     /// Does not advance last_fee_slot or charge new fees — just sweeps capital
     /// that became available (e.g. after warmup settlement) into insurance.
     /// Uses set_capital helper to maintain c_tot aggregate (spec §4.1).
-    fn pay_fee_debt_from_capital(&mut self, idx: u16) {
+    fn pay_fee_debt_from_capital(&mut self, sink: &mut dyn EventSink, idx: u16) {
         if self.accounts[idx as usize].fee_credits.is_negative()
             && !self.accounts[idx as usize].capital.is_zero()
         {
             let owed = neg_i128_to_u128(self.accounts[idx as usize].fee_credits.get());
             let current_cap = self.accounts[idx as usize].capital.get();
-            let pay = core::cmp::min(owed, current_cap);
+            // Locked capital is not the account's to spend
+            let pay = core::cmp::min(owed, self.accounts[idx as usize].free_capital());
             if pay > 0 {
                 // Use set_capital helper to maintain c_tot aggregate (spec §4.1)
                 self.set_capital(idx as usize, current_cap.saturating_sub(pay));
@@ -1174,6 +1498,7 @@ impl RiskEngine {
                 self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue + pay;
                 self.accounts[idx as usize].fee_credits =
                     self.accounts[idx as usize].fee_credits.saturating_add(pay as i128);
+                Self::emit_maintenance_fee(sink, idx, pay, current_cap);
             }
         }
     }
@@ -1182,6 +1507,7 @@ impl RiskEngine {
     /// uses best-effort fee settle that can't stall on margin checks.
     fn touch_account_for_force_realize(
         &mut self,
+        sink: &mut dyn EventSink,
         idx: u16,
         now_slot: u64,
         oracle_price: u64,
@@ -1191,7 +1517,7 @@ impl RiskEngine {
         // Mark-to-market settlement (variation margin)
         self.settle_mark_to_oracle(idx, oracle_price)?;
         // Best-effort fees; never fails due to maintenance margin
-        let _ = self.settle_maintenance_fee_best_effort_for_crank(idx, now_slot)?;
+        let _ = self.settle_maintenance_fee_best_effort_for_crank(sink, idx, now_slot)?;
         Ok(())
     }
 
@@ -1199,6 +1525,7 @@ impl RiskEngine {
     /// uses best-effort fee settle since we're about to liquidate anyway.
     fn touch_account_for_liquidation(
         &mut self,
+        sink: &mut dyn EventSink,
         idx: u16,
         now_slot: u64,
         oracle_price: u64,
@@ -1208,19 +1535,55 @@ impl RiskEngine {
         // Best-effort mark-to-market (saturating — never wedges on extreme PnL)
         self.settle_mark_to_oracle_best_effort(idx, oracle_price)?;
         // Best-effort fees; margin check would just block the liquidation we need to do
-        let _ = self.settle_maintenance_fee_best_effort_for_crank(idx, now_slot)?;
+        let _ = self.settle_maintenance_fee_best_effort_for_crank(sink, idx, now_slot)?;
         Ok(())
     }
 
     /// Set owner pubkey for an account
     pub fn set_owner(&mut self, idx: u16, owner: [u8; 32]) -> Result<()> {
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+        if idx as usize >= N || !self.is_used(idx as usize) {
             return Err(RiskError::Unauthorized);
         }
         self.accounts[idx as usize].owner = owner;
         Ok(())
     }
 
+    /// Lock `amount` of an account's capital. Locked capital stays in
+    /// `capital` (and `c_tot`) but cannot be withdrawn or spent on
+    /// maintenance fees, and the account cannot trade or be closed, until
+    /// `unlock_capital` releases it.
+    pub fn lock_capital(&mut self, idx: u16, amount: u128) -> Result<()> {
+        if idx as usize >= N || !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        let account = &mut self.accounts[idx as usize];
+        let locked = account
+            .locked_capital
+            .get()
+            .checked_add(amount)
+            .ok_or(RiskError::Overflow)?;
+        if locked > account.capital.get() {
+            return Err(RiskError::InsufficientBalance);
+        }
+        account.locked_capital = U128::new(locked);
+        Ok(())
+    }
+
+    /// Release `amount` of an account's locked capital
+    pub fn unlock_capital(&mut self, idx: u16, amount: u128) -> Result<()> {
+        if idx as usize >= N || !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        let account = &mut self.accounts[idx as usize];
+        let locked = account
+            .locked_capital
+            .get()
+            .checked_sub(amount)
+            .ok_or(RiskError::InsufficientBalance)?;
+        account.locked_capital = U128::new(locked);
+        Ok(())
+    }
+
     /// Pre-fund fee credits for an account.
     ///
     /// The wrapper must have already transferred `amount` tokens into the vault.
@@ -1229,7 +1592,7 @@ impl RiskEngine {
     /// does NOT re-book into insurance), and the account's fee_credits balance
     /// increases by `amount`.
     pub fn deposit_fee_credits(&mut self, idx: u16, amount: u128, now_slot: u64) -> Result<()> {
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+        if idx as usize >= N || !self.is_used(idx as usize) {
             return Err(RiskError::Unauthorized);
         }
         self.current_slot = now_slot;
@@ -1255,7 +1618,7 @@ impl RiskEngine {
     /// Only for tests and Kani proofs — production code must use deposit_fee_credits.
     #[cfg(any(test, feature = "test", kani))]
     pub fn add_fee_credits(&mut self, idx: u16, amount: u128) -> Result<()> {
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+        if idx as usize >= N || !self.is_used(idx as usize) {
             return Err(RiskError::Unauthorized);
         }
         self.accounts[idx as usize].fee_credits = self.accounts[idx as usize]
@@ -1289,16 +1652,30 @@ impl RiskEngine {
     /// Returns Err(Undercollateralized) if pnl < 0 (shouldn't happen after settlement).
     /// Returns the capital amount on success.
     pub fn close_account(&mut self, idx: u16, now_slot: u64, oracle_price: u64) -> Result<u128> {
+        self.close_account_with_sink(&mut NoOpSink, idx, now_slot, oracle_price)
+    }
+
+    /// `close_account`, reporting settlement and the closure to `sink`
+    pub fn close_account_with_sink(
+        &mut self,
+        sink: &mut dyn EventSink,
+        idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<u128> {
         // Update current_slot so warmup/bookkeeping progresses consistently
         self.current_slot = now_slot;
 
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+        if idx as usize >= N || !self.is_used(idx as usize) {
             return Err(RiskError::AccountNotFound);
         }
+        if !self.accounts[idx as usize].locked_capital.is_zero() {
+            return Err(RiskError::CapitalLocked);
+        }
 
         // Full settlement: funding + maintenance fees + warmup
         // This converts warmed pnl to capital and realizes negative pnl
-        self.touch_account_full(idx, now_slot, oracle_price)?;
+        self.touch_account_full_with_sink(sink, idx, now_slot, oracle_price)?;
 
         // Position must be zero
         if !self.accounts[idx as usize].position_size.is_zero() {
@@ -1326,6 +1703,7 @@ impl RiskEngine {
         }
 
         let capital = account.capital;
+        let account_id = account.account_id;
 
         // Deduct from vault
         if capital > self.vault {
@@ -1339,6 +1717,12 @@ impl RiskEngine {
         // Free the slot
         self.free_slot(idx);
 
+        sink.on_event(&EngineEvent::AccountClosed {
+            idx,
+            account_id,
+            capital: capital.get(),
+        });
+
         Ok(capital.get())
     }
 
@@ -1368,12 +1752,18 @@ impl RiskEngine {
     ///
     /// Returns the number of accounts closed.
     pub fn garbage_collect_dust(&mut self) -> u32 {
+        self.garbage_collect_dust_with_sink(&mut NoOpSink)
+    }
+
+    /// `garbage_collect_dust`, reporting fees and freed slots to `sink`
+    pub fn garbage_collect_dust_with_sink(&mut self, sink: &mut dyn EventSink) -> u32 {
         // Collect dust candidates: accounts with zero position, capital, reserved, and non-positive pnl
         let mut to_free: [u16; GC_CLOSE_BUDGET as usize] = [0; GC_CLOSE_BUDGET as usize];
+        let mut written_off: [u128; GC_CLOSE_BUDGET as usize] = [0; GC_CLOSE_BUDGET as usize];
         let mut num_to_free = 0usize;
 
-        // Scan up to ACCOUNTS_PER_CRANK slots, capped to MAX_ACCOUNTS
-        let max_scan = (ACCOUNTS_PER_CRANK as usize).min(MAX_ACCOUNTS);
+        // Scan up to ACCOUNTS_PER_CRANK slots, capped to N
+        let max_scan = (ACCOUNTS_PER_CRANK as usize).min(N);
         let start = self.gc_cursor as usize;
 
         for offset in 0..max_scan {
@@ -1382,7 +1772,7 @@ impl RiskEngine {
                 break;
             }
 
-            let idx = (start + offset) & ACCOUNT_IDX_MASK;
+            let idx = (start + offset) & Self::IDX_MASK;
 
             // Check if slot is used via bitmap
             let block = idx >> 6;
@@ -1397,7 +1787,11 @@ impl RiskEngine {
             }
 
             // Best-effort fee settle so accounts with tiny capital get drained in THIS sweep.
-            let _ = self.settle_maintenance_fee_best_effort_for_crank(idx as u16, self.current_slot);
+            let _ = self.settle_maintenance_fee_best_effort_for_crank(
+                sink,
+                idx as u16,
+                self.current_slot,
+            );
 
             // Dust predicate: must have zero position, capital, reserved, and non-positive pnl
             {
@@ -1424,6 +1818,7 @@ impl RiskEngine {
 
             // Write off negative pnl (spec §6.1: unpayable loss just reduces Residual)
             if self.accounts[idx].pnl.is_negative() {
+                written_off[num_to_free] = neg_i128_to_u128(self.accounts[idx].pnl.get());
                 self.set_pnl(idx, 0);
             }
 
@@ -1433,11 +1828,19 @@ impl RiskEngine {
         }
 
         // Update cursor for next call
-        self.gc_cursor = ((start + max_scan) & ACCOUNT_IDX_MASK) as u16;
+        self.gc_cursor = ((start + max_scan) & Self::IDX_MASK) as u16;
 
         // Free all collected dust accounts
         for i in 0..num_to_free {
-            self.free_slot(to_free[i]);
+            let idx = to_free[i];
+            let account_id = self.accounts[idx as usize].account_id;
+            let pnl_written_off = written_off[i];
+            self.free_slot(idx);
+            sink.on_event(&EngineEvent::DustCollected {
+                idx,
+                account_id,
+                pnl_written_off,
+            });
         }
 
         num_to_free as u32
@@ -1506,6 +1909,26 @@ impl RiskEngine {
         oracle_price: u64,
         funding_rate_bps_per_slot: i64,
         allow_panic: bool,
+    ) -> Result<CrankOutcome> {
+        self.keeper_crank_with_sink(
+            &mut NoOpSink,
+            caller_idx,
+            now_slot,
+            oracle_price,
+            funding_rate_bps_per_slot,
+            allow_panic,
+        )
+    }
+
+    /// `keeper_crank`, reporting every fee, liquidation, close and GC to `sink`
+    pub fn keeper_crank_with_sink(
+        &mut self,
+        sink: &mut dyn EventSink,
+        caller_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        funding_rate_bps_per_slot: i64,
+        allow_panic: bool,
     ) -> Result<CrankOutcome> {
         // Validate oracle price bounds (prevents overflow in mark_pnl calculations)
         if oracle_price == 0 || oracle_price > MAX_ORACLE_PRICE {
@@ -1539,7 +1962,7 @@ impl RiskEngine {
         }
 
         // Always attempt caller's maintenance settle (best-effort, no timestamp games)
-        let (slots_forgiven, caller_settle_ok) = if (caller_idx as usize) < MAX_ACCOUNTS
+        let (slots_forgiven, caller_settle_ok) = if (caller_idx as usize) < N
             && self.is_used(caller_idx as usize)
         {
             let last_fee = self.accounts[caller_idx as usize].last_fee_slot;
@@ -1550,7 +1973,7 @@ impl RiskEngine {
                 self.accounts[caller_idx as usize].last_fee_slot = last_fee.saturating_add(forgive);
             }
             let settle_result =
-                self.settle_maintenance_fee_best_effort_for_crank(caller_idx, now_slot);
+                self.settle_maintenance_fee_best_effort_for_crank(sink, caller_idx, now_slot);
             (forgive, settle_result.is_ok())
         } else {
             (0, true)
@@ -1575,7 +1998,7 @@ impl RiskEngine {
         let mut idx = self.crank_cursor as usize;
         let mut slots_scanned: usize = 0;
 
-        while accounts_processed < ACCOUNTS_PER_CRANK && slots_scanned < MAX_ACCOUNTS {
+        while accounts_processed < ACCOUNTS_PER_CRANK && slots_scanned < N {
             slots_scanned += 1;
 
             // Check if slot is used
@@ -1588,15 +2011,21 @@ impl RiskEngine {
 
                 // Always settle maintenance fees for every visited account.
                 // This drains idle accounts over time so they eventually become dust.
-                let _ = self.settle_maintenance_fee_best_effort_for_crank(idx as u16, now_slot);
+                let _ =
+                    self.settle_maintenance_fee_best_effort_for_crank(sink, idx as u16, now_slot);
                 // Touch account and settle warmup to drain abandoned positive PnL
                 let _ = self.touch_account(idx as u16);
-                self.settle_warmup_to_capital_for_crank(idx as u16);
+                self.settle_warmup_to_capital_for_crank(sink, idx as u16);
 
                 // === Liquidation (if not in force-realize mode) ===
                 if !force_realize_active && liq_budget > 0 {
                     if !self.accounts[idx].position_size.is_zero() {
-                        match self.liquidate_at_oracle(idx as u16, now_slot, oracle_price) {
+                        match self.liquidate_at_oracle_with_sink(
+                            sink,
+                            idx as u16,
+                            now_slot,
+                            oracle_price,
+                        ) {
                             Ok(true) => {
                                 num_liquidations += 1;
                                 liq_budget = liq_budget.saturating_sub(1);
@@ -1617,8 +2046,13 @@ impl RiskEngine {
 
                         if equity == 0 || is_dust {
                             // Force close: settle mark, close position, write off loss
-                            let _ = self.touch_account_for_liquidation(idx as u16, now_slot, oracle_price);
-                            let _ = self.oracle_close_position_core(idx as u16, oracle_price);
+                            let _ = self.touch_account_for_liquidation(
+                                sink,
+                                idx as u16,
+                                now_slot,
+                                oracle_price,
+                            );
+                            let _ = self.force_close_at_oracle(sink, idx as u16, oracle_price);
                             self.lifetime_force_realize_closes =
                                 self.lifetime_force_realize_closes.saturating_add(1);
                         }
@@ -1629,10 +2063,18 @@ impl RiskEngine {
                 if force_realize_active && force_realize_budget > 0 {
                     if !self.accounts[idx].position_size.is_zero() {
                         if self
-                            .touch_account_for_force_realize(idx as u16, now_slot, oracle_price)
+                            .touch_account_for_force_realize(
+                                sink,
+                                idx as u16,
+                                now_slot,
+                                oracle_price,
+                            )
                             .is_ok()
                         {
-                            if self.oracle_close_position_core(idx as u16, oracle_price).is_ok() {
+                            if self
+                                .force_close_at_oracle(sink, idx as u16, oracle_price)
+                                .is_ok()
+                            {
                                 force_realize_closed += 1;
                                 force_realize_budget = force_realize_budget.saturating_sub(1);
                                 self.lifetime_force_realize_closes =
@@ -1654,7 +2096,7 @@ impl RiskEngine {
             }
 
             // Advance to next index (with wrap)
-            idx = (idx + 1) & ACCOUNT_IDX_MASK;
+            idx = (idx + 1) & Self::IDX_MASK;
 
             // Check for sweep completion: we've wrapped around to sweep_start_idx
             // (and we've actually processed some slots, not just starting)
@@ -1675,7 +2117,7 @@ impl RiskEngine {
         }
 
         // Garbage collect dust accounts
-        let num_gc_closed = self.garbage_collect_dust();
+        let num_gc_closed = self.garbage_collect_dust_with_sink(sink);
 
         // Detect conditions for informational flags
         let force_realize_needed = self.force_realize_active();
@@ -1701,32 +2143,6 @@ impl RiskEngine {
     // Liquidation
     // ========================================
 
-    /// Compute mark PnL for a position at oracle price (pure helper, no side effects).
-    /// Returns the PnL from closing the position at oracle price.
-    /// - Longs: profit when oracle > entry
-    /// - Shorts: profit when entry > oracle
-    pub fn mark_pnl_for_position(pos: i128, entry: u64, oracle: u64) -> Result<i128> {
-        if pos == 0 {
-            return Ok(0);
-        }
-
-        let abs_pos = saturating_abs_i128(pos) as u128;
-
-        let diff: i128 = if pos > 0 {
-            // Long: profit when oracle > entry
-            (oracle as i128).saturating_sub(entry as i128)
-        } else {
-            // Short: profit when entry > oracle
-            (entry as i128).saturating_sub(oracle as i128)
-        };
-
-        // mark_pnl = diff * abs_pos / 1_000_000
-        diff.checked_mul(abs_pos as i128)
-            .ok_or(RiskError::Overflow)?
-            .checked_div(1_000_000)
-            .ok_or(RiskError::Overflow)
-    }
-
     /// Compute how much position to close for liquidation (closed-form, single-pass).
     ///
     /// Returns (close_abs, is_full_close) where:
@@ -1810,6 +2226,7 @@ impl RiskEngine {
     /// ASSUMES: Caller has already called touch_account_full() on this account.
     fn oracle_close_position_slice_core(
         &mut self,
+        sink: &mut dyn EventSink,
         idx: u16,
         oracle_price: u64,
         close_abs: u128,
@@ -1828,7 +2245,7 @@ impl RiskEngine {
         }
 
         if close_abs >= current_abs_pos {
-            return self.oracle_close_position_core(idx, oracle_price);
+            return self.oracle_close_position_core(sink, idx, oracle_price);
         }
 
         let entry = self.accounts[idx as usize].entry_price;
@@ -1871,7 +2288,7 @@ impl RiskEngine {
         }
 
         // Settle warmup (loss settlement + profit conversion per spec §6)
-        self.settle_warmup_to_capital(idx)?;
+        self.settle_warmup_to_capital_with_sink(sink, idx)?;
 
         // Write off residual negative PnL (capital exhausted) per spec §6.1
         if self.accounts[idx as usize].pnl.is_negative() {
@@ -1895,7 +2312,12 @@ impl RiskEngine {
     /// No ADL needed — undercollateralization is reflected via haircut ratio h.
     ///
     /// ASSUMES: Caller has already called touch_account_full() on this account.
-    fn oracle_close_position_core(&mut self, idx: u16, oracle_price: u64) -> Result<ClosedOutcome> {
+    fn oracle_close_position_core(
+        &mut self,
+        sink: &mut dyn EventSink,
+        idx: u16,
+        oracle_price: u64,
+    ) -> Result<ClosedOutcome> {
         if self.accounts[idx as usize].position_size.is_zero() {
             return Ok(ClosedOutcome {
                 abs_pos: 0,
@@ -1911,7 +2333,7 @@ impl RiskEngine {
         let entry = self.accounts[idx as usize].entry_price;
         let cap_before = self.accounts[idx as usize].capital.get();
 
-        let mark_pnl = match Self::mark_pnl_for_position(pos, entry, oracle_price) {
+        let mark_pnl = match RiskEngine::mark_pnl_for_position(pos, entry, oracle_price) {
             Ok(pnl) => pnl,
             Err(_) => -u128_to_i128_clamped(cap_before),
         };
@@ -1934,7 +2356,7 @@ impl RiskEngine {
         }
 
         // Settle warmup (loss settlement + profit conversion per spec §6)
-        self.settle_warmup_to_capital(idx)?;
+        self.settle_warmup_to_capital_with_sink(sink, idx)?;
 
         // Write off residual negative PnL (capital exhausted) per spec §6.1
         if self.accounts[idx as usize].pnl.is_negative() {
@@ -1952,6 +2374,27 @@ impl RiskEngine {
         })
     }
 
+    /// Crank force-close: full oracle close, reported as `ForceClosed`.
+    fn force_close_at_oracle(
+        &mut self,
+        sink: &mut dyn EventSink,
+        idx: u16,
+        oracle_price: u64,
+    ) -> Result<ClosedOutcome> {
+        let position_before = self.accounts[idx as usize].position_size.get();
+        let outcome = self.oracle_close_position_core(sink, idx, oracle_price)?;
+        if outcome.position_was_closed {
+            sink.on_event(&EngineEvent::ForceClosed {
+                idx,
+                oracle_price,
+                position_before,
+                capital_before: outcome.cap_before,
+                capital_after: outcome.cap_after,
+            });
+        }
+        Ok(outcome)
+    }
+
     /// Liquidate a single account at oracle price if below maintenance margin.
     ///
     /// Returns Ok(true) if liquidation occurred, Ok(false) if not needed/possible.
@@ -1962,10 +2405,21 @@ impl RiskEngine {
         idx: u16,
         now_slot: u64,
         oracle_price: u64,
+    ) -> Result<bool> {
+        self.liquidate_at_oracle_with_sink(&mut NoOpSink, idx, now_slot, oracle_price)
+    }
+
+    /// `liquidate_at_oracle`, reporting the liquidation and its fee to `sink`
+    pub fn liquidate_at_oracle_with_sink(
+        &mut self,
+        sink: &mut dyn EventSink,
+        idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
     ) -> Result<bool> {
         self.current_slot = now_slot;
 
-        if (idx as usize) >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+        if (idx as usize) >= N || !self.is_used(idx as usize) {
             return Ok(false);
         }
 
@@ -1978,9 +2432,11 @@ impl RiskEngine {
         }
 
         // Settle funding + mark-to-market + best-effort fees
-        self.touch_account_for_liquidation(idx, now_slot, oracle_price)?;
+        self.touch_account_for_liquidation(sink, idx, now_slot, oracle_price)?;
 
         let account = &self.accounts[idx as usize];
+        let position_before = account.position_size.get();
+        let capital_before = account.capital.get();
         if self.is_above_maintenance_margin_mtm(account, oracle_price) {
             return Ok(false);
         }
@@ -1994,12 +2450,12 @@ impl RiskEngine {
 
         // Close position (no ADL — losses written off in close helper)
         let mut outcome = if is_full_close {
-            self.oracle_close_position_core(idx, oracle_price)?
+            self.oracle_close_position_core(sink, idx, oracle_price)?
         } else {
-            match self.oracle_close_position_slice_core(idx, oracle_price, close_abs) {
+            match self.oracle_close_position_slice_core(sink, idx, oracle_price, close_abs) {
                 Ok(r) => r,
                 Err(RiskError::Overflow) => {
-                    self.oracle_close_position_core(idx, oracle_price)?
+                    self.oracle_close_position_core(sink, idx, oracle_price)?
                 }
                 Err(e) => return Err(e),
             }
@@ -2017,7 +2473,7 @@ impl RiskEngine {
                 .saturating_add(self.params.liquidation_buffer_bps);
             if !self.is_above_margin_bps_mtm(&self.accounts[idx as usize], oracle_price, target_bps)
             {
-                let fallback = self.oracle_close_position_core(idx, oracle_price)?;
+                let fallback = self.oracle_close_position_core(sink, idx, oracle_price)?;
                 if fallback.position_was_closed {
                     outcome.abs_pos = outcome.abs_pos.saturating_add(fallback.abs_pos);
                 }
@@ -2042,6 +2498,24 @@ impl RiskEngine {
 
         self.lifetime_liquidations = self.lifetime_liquidations.saturating_add(1);
 
+        sink.on_event(&EngineEvent::Liquidated {
+            idx,
+            oracle_price,
+            position_before,
+            position_after: self.accounts[idx as usize].position_size.get(),
+            capital_before,
+            capital_after: account_capital,
+        });
+        if pay > 0 {
+            sink.on_event(&EngineEvent::FeeCharged {
+                idx,
+                kind: FeeKind::Liquidation,
+                amount: pay,
+                capital_before: account_capital,
+                capital_after: account_capital - pay,
+            });
+        }
+
         Ok(true)
     }
 
@@ -2258,7 +2732,7 @@ impl RiskEngine {
     /// This makes positions fungible: any LP can close any user's position
     /// because PnL is settled to a common reference price.
     pub fn settle_mark_to_oracle(&mut self, idx: u16, oracle_price: u64) -> Result<()> {
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+        if idx as usize >= N || !self.is_used(idx as usize) {
             return Err(RiskError::AccountNotFound);
         }
 
@@ -2269,7 +2743,7 @@ impl RiskEngine {
         }
 
         // Compute mark PnL at current oracle
-        let mark = Self::mark_pnl_for_position(
+        let mark = RiskEngine::mark_pnl_for_position(
             self.accounts[idx as usize].position_size.get(),
             self.accounts[idx as usize].entry_price,
             oracle_price,
@@ -2293,7 +2767,7 @@ impl RiskEngine {
     /// checked_add, so it never fails on overflow.  This prevents the liquidation
     /// path from wedging on extreme mark PnL values.
     fn settle_mark_to_oracle_best_effort(&mut self, idx: u16, oracle_price: u64) -> Result<()> {
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+        if idx as usize >= N || !self.is_used(idx as usize) {
             return Err(RiskError::AccountNotFound);
         }
 
@@ -2303,7 +2777,7 @@ impl RiskEngine {
         }
 
         // Compute mark PnL at current oracle
-        let mark = Self::mark_pnl_for_position(
+        let mark = RiskEngine::mark_pnl_for_position(
             self.accounts[idx as usize].position_size.get(),
             self.accounts[idx as usize].entry_price,
             oracle_price,
@@ -2323,6 +2797,17 @@ impl RiskEngine {
     /// This is the standard "lazy settlement" path called on every user operation.
     /// Triggers liquidation check if fees push account below maintenance margin.
     pub fn touch_account_full(&mut self, idx: u16, now_slot: u64, oracle_price: u64) -> Result<()> {
+        self.touch_account_full_with_sink(&mut NoOpSink, idx, now_slot, oracle_price)
+    }
+
+    /// `touch_account_full`, reporting fees and warmup settlement to `sink`
+    pub fn touch_account_full_with_sink(
+        &mut self,
+        sink: &mut dyn EventSink,
+        idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<()> {
         // Update current_slot for consistent warmup/bookkeeping
         self.current_slot = now_slot;
 
@@ -2355,14 +2840,14 @@ impl RiskEngine {
         }
 
         // 3. Settle maintenance fees (may trigger undercollateralized error)
-        self.settle_maintenance_fee(idx, now_slot, oracle_price)?;
+        self.settle_maintenance_fee_with_sink(sink, idx, now_slot, oracle_price)?;
 
         // 4. Settle warmup (convert warmed PnL to capital, realize losses)
-        self.settle_warmup_to_capital(idx)?;
+        self.settle_warmup_to_capital_with_sink(sink, idx)?;
 
         // 5. Sweep any fee debt from newly-available capital (warmup may
         //    have created capital that should pay outstanding fee debt)
-        self.pay_fee_debt_from_capital(idx);
+        self.pay_fee_debt_from_capital(sink, idx);
 
         // 6. Re-check maintenance margin after fee debt sweep
         if !self.accounts[idx as usize].position_size.is_zero() {
@@ -2406,6 +2891,17 @@ impl RiskEngine {
     /// with the remainder added to capital. This ensures fee conservation
     /// (fees are never forgiven) and prevents stuck accounts.
     pub fn deposit(&mut self, idx: u16, amount: u128, now_slot: u64) -> Result<()> {
+        self.deposit_with_sink(&mut NoOpSink, idx, amount, now_slot)
+    }
+
+    /// `deposit`, reporting the deposit and any fees or settlement to `sink`
+    pub fn deposit_with_sink(
+        &mut self,
+        sink: &mut dyn EventSink,
+        idx: u16,
+        amount: u128,
+        now_slot: u64,
+    ) -> Result<()> {
         // Update current_slot so warmup/bookkeeping progresses consistently
         self.current_slot = now_slot;
 
@@ -2442,20 +2938,38 @@ impl RiskEngine {
 
             // Credit back what was paid
             account.fee_credits = account.fee_credits.saturating_add(pay as i128);
+
+            if pay > 0 {
+                let capital = account.capital.get();
+                sink.on_event(&EngineEvent::FeeCharged {
+                    idx,
+                    kind: FeeKind::Maintenance,
+                    amount: pay,
+                    capital_before: capital,
+                    capital_after: capital,
+                });
+            }
         }
 
         // Vault gets full deposit (tokens received)
         self.vault = U128::new(add_u128(self.vault.get(), amount));
 
         // Capital gets remainder after fees (via set_capital to maintain c_tot)
-        let new_cap = add_u128(self.accounts[idx as usize].capital.get(), deposit_remaining);
+        let old_cap = self.accounts[idx as usize].capital.get();
+        let new_cap = add_u128(old_cap, deposit_remaining);
         self.set_capital(idx as usize, new_cap);
+        sink.on_event(&EngineEvent::Deposited {
+            idx,
+            amount,
+            capital_before: old_cap,
+            capital_after: new_cap,
+        });
 
         // Settle warmup after deposit (allows losses to be paid promptly if underwater)
-        self.settle_warmup_to_capital(idx)?;
+        self.settle_warmup_to_capital_with_sink(sink, idx)?;
 
         // If any older fee debt remains, use capital to pay it now.
-        self.pay_fee_debt_from_capital(idx);
+        self.pay_fee_debt_from_capital(sink, idx);
 
         Ok(())
     }
@@ -2468,6 +2982,18 @@ impl RiskEngine {
         amount: u128,
         now_slot: u64,
         oracle_price: u64,
+    ) -> Result<()> {
+        self.withdraw_with_sink(&mut NoOpSink, idx, amount, now_slot, oracle_price)
+    }
+
+    /// `withdraw`, reporting the withdrawal and any fees or settlement to `sink`
+    pub fn withdraw_with_sink(
+        &mut self,
+        sink: &mut dyn EventSink,
+        idx: u16,
+        amount: u128,
+        now_slot: u64,
+        oracle_price: u64,
     ) -> Result<()> {
         // Update current_slot so warmup/bookkeeping progresses consistently
         self.current_slot = now_slot;
@@ -2489,7 +3015,7 @@ impl RiskEngine {
         }
 
         // Full settlement: funding + maintenance fees + warmup
-        self.touch_account_full(idx, now_slot, oracle_price)?;
+        self.touch_account_full_with_sink(sink, idx, now_slot, oracle_price)?;
 
         // Read account state (scope the borrow)
         let (old_capital, pnl, position_size, entry_price, fee_credits) = {
@@ -2503,8 +3029,8 @@ impl RiskEngine {
             )
         };
 
-        // Check we have enough capital
-        if old_capital.get() < amount {
+        // Check we have enough capital, not counting locked capital
+        if self.accounts[idx as usize].free_capital() < amount {
             return Err(RiskError::InsufficientBalance);
         }
 
@@ -2513,7 +3039,7 @@ impl RiskEngine {
         // Fail-safe: if mark_pnl overflows (corrupted entry_price/position_size), treat as 0 equity
         let new_capital = sub_u128(old_capital.get(), amount);
         let new_equity_mtm = {
-            let eq = match Self::mark_pnl_for_position(position_size.get(), entry_price, oracle_price)
+            let eq = match RiskEngine::mark_pnl_for_position(position_size.get(), entry_price, oracle_price)
             {
                 Ok(mark_pnl) => {
                     let cap_i = u128_to_i128_clamped(new_capital);
@@ -2579,6 +3105,13 @@ impl RiskEngine {
             "Withdraw: negative PnL must settle immediately"
         );
 
+        sink.on_event(&EngineEvent::Withdrawn {
+            idx,
+            amount,
+            capital_before: old_capital.get(),
+            capital_after: new_capital,
+        });
+
         Ok(())
     }
 
@@ -2609,7 +3142,7 @@ impl RiskEngine {
     /// FAIL-SAFE: On overflow, returns 0 (worst-case equity) to ensure liquidation
     /// can still trigger. This prevents overflow from blocking liquidation.
     pub fn account_equity_mtm_at_oracle(&self, account: &Account, oracle_price: u64) -> u128 {
-        let mark = match Self::mark_pnl_for_position(
+        let mark = match RiskEngine::mark_pnl_for_position(
             account.position_size.get(),
             account.entry_price,
             oracle_price,
@@ -2700,6 +3233,29 @@ impl RiskEngine {
         now_slot: u64,
         oracle_price: u64,
         size: i128,
+    ) -> Result<()> {
+        self.execute_trade_with_sink(
+            &mut NoOpSink,
+            matcher,
+            lp_idx,
+            user_idx,
+            now_slot,
+            oracle_price,
+            size,
+        )
+    }
+
+    /// `execute_trade`, reporting the fill, its fee and settlement to `sink`
+    #[allow(clippy::too_many_arguments)]
+    pub fn execute_trade_with_sink<M: MatchingEngine>(
+        &mut self,
+        sink: &mut dyn EventSink,
+        matcher: &M,
+        lp_idx: u16,
+        user_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        size: i128,
     ) -> Result<()> {
         // Update current_slot so warmup/bookkeeping progresses consistently
         self.current_slot = now_slot;
@@ -2733,6 +3289,13 @@ impl RiskEngine {
             return Err(RiskError::AccountKindMismatch);
         }
 
+        // Trading losses could eat into locked capital
+        if !self.accounts[lp_idx as usize].locked_capital.is_zero()
+            || !self.accounts[user_idx as usize].locked_capital.is_zero()
+        {
+            return Err(RiskError::CapitalLocked);
+        }
+
         // Check if trade increases risk (absolute exposure for either party)
         let old_user_pos = self.accounts[user_idx as usize].position_size.get();
         let old_lp_pos = self.accounts[lp_idx as usize].position_size.get();
@@ -2822,8 +3385,8 @@ impl RiskEngine {
             self.update_warmup_slope(lp_idx)?;
         }
 
-        self.settle_maintenance_fee(user_idx, now_slot, oracle_price)?;
-        self.settle_maintenance_fee(lp_idx, now_slot, oracle_price)?;
+        self.settle_maintenance_fee_with_sink(sink, user_idx, now_slot, oracle_price)?;
+        self.settle_maintenance_fee_with_sink(sink, lp_idx, now_slot, oracle_price)?;
 
         // Calculate fee (ceiling division to prevent micro-trade fee evasion)
         let notional =
@@ -2916,7 +3479,7 @@ impl RiskEngine {
         let (h_num, h_den) = if projected_pnl_pos_tot == 0 {
             (1u128, 1u128)
         } else {
-            let (solvent, residual) = Self::signed_residual(
+            let (solvent, residual) = RiskEngine::signed_residual(
                 self.vault.get(),
                 self.c_tot.get(),
                 self.insurance_fund.balance.get(),
@@ -3084,16 +3647,36 @@ impl RiskEngine {
         // lp_max_abs: monotone increase only (conservative upper bound)
         self.lp_max_abs = U128::new(self.lp_max_abs.get().max(new_lp_abs));
 
+        sink.on_event(&EngineEvent::Traded {
+            user_idx,
+            lp_idx,
+            size: exec_size,
+            price: exec_price,
+            user_position_before: old_user_pos,
+            user_position_after: new_user_position,
+            lp_position_before: old_lp_pos,
+            lp_position_after: new_lp_position,
+        });
+        if fee > 0 {
+            sink.on_event(&EngineEvent::FeeCharged {
+                idx: user_idx,
+                kind: FeeKind::Trading,
+                amount: fee,
+                capital_before: new_user_capital + fee,
+                capital_after: new_user_capital,
+            });
+        }
+
         // Two-pass settlement: losses first, then profits.
         // This ensures the loser's capital reduction increases Residual before
         // the winner's profit conversion reads the haircut ratio. Without this,
         // the winner's matured PnL can be haircutted to 0 because Residual
         // hasn't been increased by the loser's loss settlement yet (Finding G).
-        self.settle_loss_only(user_idx)?;
-        self.settle_loss_only(lp_idx)?;
+        self.settle_loss_only_with_sink(sink, user_idx)?;
+        self.settle_loss_only_with_sink(sink, lp_idx)?;
         // Now Residual reflects realized losses; profit conversion uses correct h.
-        self.settle_warmup_to_capital(user_idx)?;
-        self.settle_warmup_to_capital(lp_idx)?;
+        self.settle_warmup_to_capital_with_sink(sink, user_idx)?;
+        self.settle_warmup_to_capital_with_sink(sink, lp_idx)?;
 
         // Now recompute warmup slopes after PnL changes (resets started_at_slot)
         self.update_warmup_slope(user_idx)?;
@@ -3106,10 +3689,23 @@ impl RiskEngine {
     /// Used in two-pass settlement to ensure all losses are realized (increasing
     /// Residual) before any profit conversions use the haircut ratio.
     pub fn settle_loss_only(&mut self, idx: u16) -> Result<()> {
+        self.settle_loss_only_with_sink(&mut NoOpSink, idx)
+    }
+
+    /// `settle_loss_only`, reporting the settled loss to `sink`
+    pub fn settle_loss_only_with_sink(&mut self, sink: &mut dyn EventSink, idx: u16) -> Result<()> {
         if !self.is_used(idx as usize) {
             return Err(RiskError::AccountNotFound);
         }
 
+        self.settle_loss(sink, idx);
+
+        Ok(())
+    }
+
+    /// §6.1 shared by settle_loss_only and settle_warmup_to_capital: negative PnL
+    /// pays from capital, the remainder is written off.
+    fn settle_loss(&mut self, sink: &mut dyn EventSink, idx: u16) {
         let pnl = self.accounts[idx as usize].pnl.get();
         if pnl < 0 {
             let need = neg_i128_to_u128(pnl);
@@ -3125,9 +3721,15 @@ impl RiskEngine {
             if self.accounts[idx as usize].pnl.is_negative() {
                 self.set_pnl(idx as usize, 0);
             }
-        }
 
-        Ok(())
+            sink.on_event(&EngineEvent::LossSettled {
+                idx,
+                pnl_before: pnl,
+                pnl_after: 0,
+                capital_before: capital,
+                capital_after: capital - pay,
+            });
+        }
     }
 
     /// Settle warmup: loss settlement + profit conversion per spec §6
@@ -3138,27 +3740,21 @@ impl RiskEngine {
     /// §6.2 Profit conversion: warmable gross profit converts to capital at haircut ratio h.
     ///   y = floor(x * h_num / h_den), where (h_num, h_den) is computed pre-conversion.
     pub fn settle_warmup_to_capital(&mut self, idx: u16) -> Result<()> {
+        self.settle_warmup_to_capital_with_sink(&mut NoOpSink, idx)
+    }
+
+    /// `settle_warmup_to_capital`, reporting loss settlement and conversion to `sink`
+    pub fn settle_warmup_to_capital_with_sink(
+        &mut self,
+        sink: &mut dyn EventSink,
+        idx: u16,
+    ) -> Result<()> {
         if !self.is_used(idx as usize) {
             return Err(RiskError::AccountNotFound);
         }
 
         // §6.1 Loss settlement (negative PnL → reduce capital immediately)
-        let pnl = self.accounts[idx as usize].pnl.get();
-        if pnl < 0 {
-            let need = neg_i128_to_u128(pnl);
-            let capital = self.accounts[idx as usize].capital.get();
-            let pay = core::cmp::min(need, capital);
-
-            if pay > 0 {
-                self.set_capital(idx as usize, capital - pay);
-                self.set_pnl(idx as usize, pnl.saturating_add(pay as i128));
-            }
-
-            // Write off any remaining negative PnL (spec §6.1 step 4)
-            if self.accounts[idx as usize].pnl.is_negative() {
-                self.set_pnl(idx as usize, 0);
-            }
-        }
+        self.settle_loss(sink, idx);
 
         // §6.2 Profit conversion (warmup converts junior profit → protected principal)
         let pnl = self.accounts[idx as usize].pnl.get();
@@ -3187,8 +3783,17 @@ impl RiskEngine {
                 // Reduce junior profit claim by x
                 self.set_pnl(idx as usize, pnl - (x as i128));
                 // Increase protected principal by y
-                let new_cap = add_u128(self.accounts[idx as usize].capital.get(), y);
+                let old_cap = self.accounts[idx as usize].capital.get();
+                let new_cap = add_u128(old_cap, y);
                 self.set_capital(idx as usize, new_cap);
+
+                sink.on_event(&EngineEvent::WarmupConverted {
+                    idx,
+                    pnl_before: pnl,
+                    pnl_after: pnl - (x as i128),
+                    capital_before: old_cap,
+                    capital_after: new_cap,
+                });
             }
 
             // Advance warmup time base and update slope (spec §5.4)
@@ -3249,77 +3854,18 @@ impl RiskEngine {
     ///
     /// We also verify the full accounting identity including settled/unsettled PnL:
     /// vault >= sum(capital) + sum(settled_pnl + mark_pnl) + insurance
-    /// The difference (slack) must be bounded by MAX_ROUNDING_SLACK.
+    /// The difference (slack) must be bounded by one unit per account slot
+    /// (MAX_ROUNDING_SLACK for the default size).
     pub fn check_conservation(&self, oracle_price: u64) -> bool {
-        let mut total_capital = 0u128;
-        let mut net_pnl: i128 = 0;
-        let mut net_mark: i128 = 0;
-        let mut mark_ok = true;
-        let global_index = self.funding_index_qpb_e6;
-
+        let mut sums = ConservationSums::default();
         self.for_each_used(|_idx, account| {
-            total_capital = add_u128(total_capital, account.capital.get());
-
-            // Compute "would-be settled" PNL for this account
-            let mut settled_pnl = account.pnl.get();
-            if !account.position_size.is_zero() {
-                let delta_f = global_index
-                    .get()
-                    .saturating_sub(account.funding_index.get());
-                if delta_f != 0 {
-                    let raw = account.position_size.get().saturating_mul(delta_f);
-                    let payment = if raw > 0 {
-                        raw.saturating_add(999_999).saturating_div(1_000_000)
-                    } else {
-                        raw.saturating_div(1_000_000)
-                    };
-                    settled_pnl = settled_pnl.saturating_sub(payment);
-                }
-
-                match Self::mark_pnl_for_position(
-                    account.position_size.get(),
-                    account.entry_price,
-                    oracle_price,
-                ) {
-                    Ok(mark) => {
-                        net_mark = net_mark.saturating_add(mark);
-                    }
-                    Err(_) => {
-                        mark_ok = false;
-                    }
-                }
-            }
-            net_pnl = net_pnl.saturating_add(settled_pnl);
+            sums.add(account, self.funding_index_qpb_e6, oracle_price);
         });
-
-        if !mark_ok {
-            return false;
-        }
-
-        // Conservation: vault >= C_tot + I (primary invariant)
-        let primary = self.vault.get()
-            >= total_capital.saturating_add(self.insurance_fund.balance.get());
-        if !primary {
-            return false;
-        }
-
-        // Extended: vault >= sum(capital) + sum(settled_pnl + mark_pnl) + insurance
-        let total_pnl = net_pnl.saturating_add(net_mark);
-        let base = add_u128(total_capital, self.insurance_fund.balance.get());
-
-        let expected = if total_pnl >= 0 {
-            add_u128(base, total_pnl as u128)
-        } else {
-            base.saturating_sub(neg_i128_to_u128(total_pnl))
-        };
-
-        let actual = self.vault.get();
-
-        if actual < expected {
-            return false;
-        }
-        let slack = actual - expected;
-        slack <= MAX_ROUNDING_SLACK
+        sums.holds(
+            self.vault.get(),
+            self.insurance_fund.balance.get(),
+            N as u128,
+        )
     }
 
     /// Advance to next slot (for testing warmup)
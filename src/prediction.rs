@@ -1,7 +1,7 @@
 //! Prediction market primitives built on Percolator-style payout safety.
 //!
 //! Scope:
-//! - Binary markets (`YES` / `NO`)
+//! - Binary markets (`YES` / `NO`), categorical N-outcome and scalar LONG/SHORT markets
 //! - Only tokens that have already migrated from Pump.fun to PumpSwap
 //! - Deterministic settlement with bounded payout via global ratio `h`
 
@@ -9,6 +9,49 @@
 
 use core::cmp::min;
 
+use crate::{
+    Account, EngineEvent, EventSink, InsuranceFund, NoOpSink, RiskEngine, RiskError, U128,
+};
+
+/// Version of the resolution/settlement logic recorded in `ResolutionRecord`.
+/// Bump whenever resolution semantics or the hashed input encoding change.
+pub const RESOLVER_VERSION: u32 = 3;
+
+/// Maximum number of oracle sources consulted for a single resolution
+pub const MAX_ORACLE_SOURCES: usize = 4;
+
+// Participant ledger capacity follows the same feature split as MAX_ACCOUNTS.
+#[cfg(kani)]
+pub const MAX_PARTICIPANTS: usize = 4; // Small for fast formal verification
+
+#[cfg(all(feature = "test", not(kani)))]
+pub const MAX_PARTICIPANTS: usize = 16; // Small for tests
+
+#[cfg(all(not(kani), not(feature = "test")))]
+pub const MAX_PARTICIPANTS: usize = 256; // Production
+
+// Market registry capacity, same feature split.
+#[cfg(kani)]
+pub const MAX_MARKETS: usize = 4; // Small for fast formal verification
+
+#[cfg(all(feature = "test", not(kani)))]
+pub const MAX_MARKETS: usize = 16; // Small for tests
+
+#[cfg(all(not(kani), not(feature = "test")))]
+pub const MAX_MARKETS: usize = 64; // Production
+
+pub const MARKET_BITMAP_WORDS: usize = MAX_MARKETS.div_ceil(64);
+
+/// Maximum number of denylisted mints in an `EligibilityPolicy`
+pub const MAX_DENYLIST: usize = 16;
+
+/// Maximum number of outcomes in a categorical market
+#[cfg(kani)]
+pub const MAX_OUTCOMES: usize = 3; // Small for fast formal verification
+
+#[cfg(not(kani))]
+pub const MAX_OUTCOMES: usize = 8;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Outcome {
     Yes,
@@ -17,13 +60,97 @@ pub enum Outcome {
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum MarketRule {
-    MarketCapAtCloseAtLeast { target_quote_units: u128 },
+    MarketCapAtCloseAtLeast {
+        target_quote_units: u128,
+    },
+    /// Price target at close. `target_price_e6` uses the same 1e6 scaling as
+    /// the risk engine's `oracle_price`.
+    PriceAtCloseAtLeast {
+        target_price_e6: u64,
+    },
+    /// Cumulative volume over the inclusive slot window
+    /// `[window_start_slot, window_end_slot]`, which must lie inside the
+    /// market's `[created_slot, close_slot]`.
+    VolumeInWindowAtLeast {
+        window_start_slot: u64,
+        window_end_slot: u64,
+        target_volume_quote_units: u128,
+    },
+    /// Token migrates to PumpSwap within the market's `[created_slot, close_slot]`.
+    /// Only tokens that have NOT yet migrated are eligible.
+    MigrationWithinWindow,
+    /// Categorical: market cap at close falls into one of `num_outcomes`
+    /// buckets. Bucket `i < num_outcomes - 1` is `[upper_bounds[i - 1],
+    /// upper_bounds[i])` (from 0 for `i = 0`); the last bucket is unbounded.
+    /// Bounds must be strictly increasing and unused bounds zero.
+    MarketCapBuckets {
+        upper_bounds: [u128; MAX_OUTCOMES - 1],
+        num_outcomes: u8,
+    },
+    /// Scalar: market cap at close, clamped to `[lower_quote_units,
+    /// upper_quote_units]`, sets the LONG share of the pot linearly
+    /// (0 at the lower bound, 1 at the upper bound).
+    MarketCapScalar {
+        lower_quote_units: u128,
+        upper_quote_units: u128,
+    },
+}
+
+/// Where a token was launched, from its launch metadata
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LaunchSource {
+    PumpFun,
+    Other,
 }
 
+/// Token facts at market creation, checked against an `EligibilityPolicy`
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct TokenStatus {
     pub mint: [u8; 32],
     pub migrated_to_pumpswap: bool,
+    pub launch_source: LaunchSource,
+    /// Slot the token migrated to PumpSwap (ignored if not migrated)
+    pub migration_slot: u64,
+    pub liquidity_quote_units: u128,
+    pub market_cap_quote_units: u128,
+    /// Token has been delisted; evidence for `CancelReason::TokenDelisted`
+    pub delisted: bool,
+}
+
+/// Program-wide token eligibility rules applied by `create_market`.
+///
+/// Zero thresholds disable the corresponding check. Only the first
+/// `denylist_len` entries of `denylist` are used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EligibilityPolicy {
+    pub required_launch_source: LaunchSource,
+    /// Slots that must have passed since migration (migrated tokens only)
+    pub min_slots_since_migration: u64,
+    pub min_liquidity_quote_units: u128,
+    pub min_market_cap_quote_units: u128,
+    pub denylist: [[u8; 32]; MAX_DENYLIST],
+    pub denylist_len: u8,
+}
+
+impl Default for EligibilityPolicy {
+    /// Pump.fun origin only, no thresholds, empty denylist
+    fn default() -> Self {
+        Self {
+            required_launch_source: LaunchSource::PumpFun,
+            min_slots_since_migration: 0,
+            min_liquidity_quote_units: 0,
+            min_market_cap_quote_units: 0,
+            denylist: [[0; 32]; MAX_DENYLIST],
+            denylist_len: 0,
+        }
+    }
+}
+
+impl EligibilityPolicy {
+    pub fn is_denylisted(&self, mint: &[u8; 32]) -> bool {
+        let len = min(self.denylist_len as usize, MAX_DENYLIST);
+        self.denylist[..len].iter().any(|m| m == mint)
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -31,16 +158,120 @@ pub struct TokenSnapshot {
     pub mint: [u8; 32],
     pub migrated_to_pumpswap: bool,
     pub market_cap_quote_units: u128,
+    /// Token price in quote units, scaled by 1e6 (same scale as `oracle_price`)
+    pub price_e6: u64,
+    /// Windowed volume, required only by `VolumeInWindowAtLeast` markets
+    pub volume: Option<VolumeWindowSnapshot>,
+    /// Slot at which the token migrated; `Some` iff `migrated_to_pumpswap`
+    pub migration_slot: Option<u64>,
     pub snapshot_slot: u64,
 }
 
+/// Cumulative traded volume over the inclusive slot window
+/// `[window_start_slot, window_end_slot]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VolumeWindowSnapshot {
+    pub window_start_slot: u64,
+    pub window_end_slot: u64,
+    pub cumulative_volume_quote_units: u128,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Market {
     pub market_id: u64,
     pub token_mint: [u8; 32],
     pub created_slot: u64,
     pub close_slot: u64,
+    /// First slot at which stakes are rejected: `close_slot - params.betting_cutoff_slots`
+    pub betting_cutoff_slot: u64,
     pub rule: MarketRule,
+    pub params: MarketParams,
+}
+
+/// Per-market parameters, fixed at creation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MarketParams {
+    /// Maximum slots between `close_slot` and the resolving snapshot.
+    /// Older snapshots are rejected as stale (see docs/oracle.md freshness policy).
+    pub max_snapshot_delay_slots: u64,
+    /// How closely secondary oracle sources must agree with the primary
+    pub agreement_policy: AgreementPolicy,
+    /// Slots a market may remain `Disputed` before it can be voided
+    pub dispute_timeout_slots: u64,
+    /// Protocol fee on the losing pool, in basis points
+    pub protocol_fee_bps: u64,
+    /// Market-creator fee on the losing pool, in basis points
+    pub creator_fee_bps: u64,
+    /// Slots before `close_slot` at which staking stops (at least 1), so
+    /// bets cannot be placed once the closing value is already visible
+    pub betting_cutoff_slots: u64,
+    /// Fee kept from early unstakes, in basis points (rounded up)
+    pub exit_fee_bps: u64,
+    /// Minimum YES + NO stake at close for the market to settle (0 = none)
+    pub min_total_stake: u128,
+    /// Minimum stake on each side (each outcome, for categorical markets) at
+    /// close for the market to settle (0 = none)
+    pub min_side_stake: u128,
+}
+
+/// Agreement required between oracle sources on the rule's critical field
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgreementPolicy {
+    /// Values must match exactly
+    Exact,
+    /// Values may differ by at most `tolerance_bps` of the larger value
+    /// (at most 10_000). Sources must still agree on the outcome.
+    WithinBps { tolerance_bps: u64 },
+}
+
+/// A snapshot tagged with the oracle source that produced it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourcedSnapshot {
+    pub source_id: u32,
+    pub snapshot: TokenSnapshot,
+}
+
+/// Snapshot field on which two sources disagreed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisputeField {
+    MigrationStatus,
+    MarketCap,
+    Price,
+    Volume,
+    MigrationSlot,
+    Outcome,
+}
+
+/// Discrepancy between the primary source and a secondary source.
+///
+/// Values are widened to u128; booleans and outcomes encode as 0/1
+/// (`No`/`Yes`), buckets as their index, and a missing migration slot
+/// encodes as `u128::MAX`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dispute {
+    pub field: DisputeField,
+    pub primary_source_id: u32,
+    pub conflicting_source_id: u32,
+    pub primary_value: u128,
+    pub conflicting_value: u128,
+}
+
+/// Resolved value of a market, by the kind of its rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResolvedOutcome {
+    /// Binary rules
+    Binary(Outcome),
+    /// `MarketCapBuckets`: index of the winning bucket
+    Bucket(u8),
+    /// `MarketCapScalar`: LONG share of the pot, `long_num / long_den`
+    Scalar { long_num: u128, long_den: u128 },
+}
+
+/// Result of multi-source resolution
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Resolved(ResolvedOutcome),
+    Disputed(Dispute),
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -54,7 +285,15 @@ pub struct Settlement {
     pub outcome: Outcome,
     pub winner_capital_total: u128,
     pub loser_capital_total: u128,
+    /// Winner capital actually returned; below `winner_capital_total` only
+    /// when the vault cannot cover senior claims in full
+    pub winner_capital_paid: u128,
     pub residual: u128,
+    /// Protocol fee actually paid out of the residual
+    pub protocol_fee_paid: u128,
+    /// Creator fee actually paid out of the residual
+    pub creator_fee_paid: u128,
+    /// Loser pool net of (nominal) fees, claimable by winners as profit
     pub profit_claim_total: u128,
     pub h_num: u128,
     pub h_den: u128,
@@ -62,37 +301,328 @@ pub struct Settlement {
     pub winner_payout_total: u128,
 }
 
+/// Minimal audit record written for every settled market (docs/oracle.md).
+///
+/// `input_hash` is SHA-256 over the canonical encoding of the resolved
+/// outcome, the resolving snapshot, the pools and the settlement (see
+/// `settlement_input_hash`), so auditors can replay settlement and compare
+/// byte for byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResolutionRecord {
+    pub market_id: u64,
+    /// Binary side, winning bucket or scalar share the snapshot resolves to
+    pub outcome: ResolvedOutcome,
+    pub input_hash: [u8; 32],
+    /// Oracle sources consulted, primary first; only `..num_sources` is meaningful
+    pub source_ids: [u32; MAX_ORACLE_SOURCES],
+    pub num_sources: u8,
+    pub resolver_version: u32,
+    pub settled_slot: u64,
+}
+
+/// One bettor's stakes in a single market
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParticipantPosition {
+    pub owner: [u8; 32],
+    pub yes_capital: u128,
+    pub no_capital: u128,
+    /// Exit fees this participant paid on unstakes
+    pub exit_fees: u128,
+}
+
+/// Per-account YES/NO stakes recorded while the market is open.
+///
+/// `pools` and `exit_fees` are maintained incrementally and always equal the
+/// column sums of `positions[..num_participants]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParticipantLedger {
+    pub pools: Pools,
+    /// Exit fees kept from unstakes. They stay in the pot and fund the
+    /// winning side's profit (see `settlement_pools`); if the market is
+    /// cancelled, each payer gets their own back (see `refund_participants`).
+    pub exit_fees: u128,
+    pub num_participants: u16,
+    pub positions: [ParticipantPosition; MAX_PARTICIPANTS],
+}
+
+/// Per-participant settlement or refund result, indexed like
+/// `ParticipantLedger::positions`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParticipantPayouts {
+    pub payouts: [u128; MAX_PARTICIPANTS],
+    /// Sum of `payouts`; never exceeds the amount being distributed
+    /// (`Settlement::winner_payout_total`, or the refundable total)
+    pub total_paid: u128,
+    /// Amount being distributed minus `total_paid`, left behind by floor rounding
+    pub rounding_dust: u128,
+}
+
+/// Why a market was voided
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CancelReason {
+    /// Resolved outcome has no stake behind it, so there is nobody to pay
+    EmptyWinnerSide,
+    /// No valid snapshot arrived before the freshness window closed
+    SnapshotUnavailable,
+    /// Dispute was not resolved within `dispute_timeout_slots`
+    DisputeTimeout,
+    /// Token was delisted; the rule can no longer be evaluated
+    TokenDelisted,
+    /// Stakes at close missed `min_total_stake` or `min_side_stake`
+    InsufficientLiquidity,
+}
+
+/// Lifecycle phase of a market.
+///
+/// Transitions (see `MarketState`):
+/// - `Open -> Closed` once `close_slot` is reached
+/// - `Closed -> Resolved` from an oracle snapshot, `Disputed -> Resolved`
+///   only from sources covering the dispute
+/// - `Closed -> Disputed` to pause resolution
+/// - `Resolved -> Settled` exactly once
+/// - `-> Cancelled` for a valid `CancelReason`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarketPhase {
+    /// Accepting stakes until `close_slot`
+    Open,
+    /// Stakes frozen, awaiting an oracle snapshot
+    Closed,
+    /// Outcome fixed, awaiting settlement
+    Resolved,
+    /// Payouts computed (terminal)
+    Settled,
+    /// Market voided, stakes refundable (terminal)
+    Cancelled,
+    /// Resolution paused pending dispute review
+    Disputed,
+}
+
+/// Stateful wrapper around an immutable `Market`.
+///
+/// Every transition takes the current slot, which must never go backwards.
+/// Resolution and settlement are one-shot: once an outcome is fixed it cannot
+/// be replaced, and a market is settled at most once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MarketState {
+    pub market: Market,
+    pub phase: MarketPhase,
+    /// Slot of the most recent transition, stake or unstake (or creation)
+    pub last_transition_slot: u64,
+    pub ledger: ParticipantLedger,
+    /// Stakes of a `MarketCapBuckets` market, which `ledger` cannot hold;
+    /// empty (zero outcomes) for every other rule
+    pub categorical: CategoricalLedger,
+    /// Set on entering `Resolved`
+    pub outcome: Option<ResolvedOutcome>,
+    /// Set on entering `Settled` (binary and categorical markets)
+    pub settlement: Option<Settlement>,
+    /// Set on entering `Settled` (scalar markets, see `settle_scalar`)
+    pub scalar_settlement: Option<ScalarSettlement>,
+    /// First source-detected discrepancy since the market entered `Disputed`
+    pub dispute: Option<Dispute>,
+    /// Slot at which the market last entered `Disputed`
+    pub disputed_since_slot: u64,
+    /// Set on entering `Cancelled`
+    pub cancel_reason: Option<CancelReason>,
+    /// Set once a Cancelled market's refunds are paid (`pay_refunds`)
+    pub refunded: bool,
+    /// Engine account backing each ledger position, or `NO_ENGINE_ACCOUNT`
+    /// for positions recorded with `stake`
+    pub account_idx: [u16; MAX_PARTICIPANTS],
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PredictionError {
     TokenNotMigrated,
+    TokenAlreadyMigrated,
+    /// Token is delisted and cannot back a new market
+    TokenDelisted,
+    /// Token launch metadata does not match `required_launch_source`
+    UnsupportedLaunchSource,
+    /// Token mint is on the policy denylist
+    MintDenylisted,
+    /// Fewer than `min_slots_since_migration` slots since migration
+    MigrationTooRecent,
+    /// Token liquidity below `min_liquidity_quote_units`
+    InsufficientTokenLiquidity,
+    /// Token market cap below `min_market_cap_quote_units`
+    MarketCapTooLow,
     InvalidCloseSlot,
     SnapshotBeforeClose,
+    SnapshotStale,
     SnapshotTokenMismatch,
     SnapshotNotMigrated,
+    SnapshotMigrationInconsistent,
+    InvalidVolumeWindow,
+    SnapshotMissingVolume,
+    SnapshotVolumeWindowPartial,
+    SnapshotVolumeWindowMismatch,
     EmptyWinnerSide,
+    ZeroStake,
+    LedgerFull,
+    LedgerPoolsMismatch,
+    InvalidTransition,
+    SlotRegression,
+    BettingClosed,
+    /// Stake at or after `betting_cutoff_slot`, inside the late-bet window
+    BettingCutoffPassed,
+    /// `betting_cutoff_slots` is zero or leaves no slot open for betting
+    InvalidBettingCutoff,
+    /// Unstake larger than the owner's stake on that side
+    InsufficientStake,
+    /// `min_total_stake` set below what `min_side_stake` already requires
+    InvalidStakeThreshold,
+    /// Stakes miss `min_total_stake` or `min_side_stake`; the market is refund-only
+    InsufficientLiquidity,
+    /// Every registry slot is occupied
+    RegistryFull,
+    /// No market with that id is registered
+    MarketNotFound,
+    CloseSlotNotReached,
+    SnapshotInFuture,
+    InvalidAgreementPolicy,
+    NoOracleSources,
+    TooManyOracleSources,
+    DuplicateOracleSource,
+    /// Disputed market re-resolved without both sources of the recorded
+    /// dispute (or with fewer than two sources after a manual dispute)
+    DisputeSourcesMissing,
+    CancelNotAllowed,
+    InvalidFee,
+    /// Bucket count outside `2..=MAX_OUTCOMES`, or bounds not strictly increasing
+    InvalidBuckets,
+    /// Binary-only operation on a categorical or scalar market
+    NotBinaryMarket,
+    /// Categorical-only operation on a binary market
+    NotCategoricalMarket,
+    /// Outcome index not below the market's `num_outcomes`
+    InvalidOutcomeIndex,
+    /// Scalar range with `lower >= upper`
+    InvalidScalarRange,
+    /// Scalar-only operation on a non-scalar market
+    NotScalarMarket,
+    /// Position is backed by a different engine account (or by none)
+    AccountMismatch,
+    /// Engine settlement needs every position backed by an engine account
+    PositionNotEngineBacked,
+    /// Engine account has an open position or negative PnL, either of which
+    /// could eat into staked capital
+    AccountNotFlat,
+    Engine(RiskError),
     MathOverflow,
 }
 
 pub fn create_market(
     token: TokenStatus,
+    policy: &EligibilityPolicy,
     market_id: u64,
     created_slot: u64,
     close_slot: u64,
     rule: MarketRule,
+    params: MarketParams,
 ) -> Result<Market, PredictionError> {
-    if !token.migrated_to_pumpswap {
+    if token.launch_source != policy.required_launch_source {
+        return Err(PredictionError::UnsupportedLaunchSource);
+    }
+    if policy.is_denylisted(&token.mint) {
+        return Err(PredictionError::MintDenylisted);
+    }
+    if token.delisted {
+        return Err(PredictionError::TokenDelisted);
+    }
+    if let MarketRule::MigrationWithinWindow = rule {
+        // Migration markets ask whether a Pump.fun token *will* migrate
+        if token.migrated_to_pumpswap {
+            return Err(PredictionError::TokenAlreadyMigrated);
+        }
+    } else if !token.migrated_to_pumpswap {
         return Err(PredictionError::TokenNotMigrated);
+    } else if created_slot.saturating_sub(token.migration_slot) < policy.min_slots_since_migration {
+        return Err(PredictionError::MigrationTooRecent);
+    }
+    if token.liquidity_quote_units < policy.min_liquidity_quote_units {
+        return Err(PredictionError::InsufficientTokenLiquidity);
+    }
+    if token.market_cap_quote_units < policy.min_market_cap_quote_units {
+        return Err(PredictionError::MarketCapTooLow);
     }
     if close_slot <= created_slot {
         return Err(PredictionError::InvalidCloseSlot);
     }
+    // Cutoff must fall strictly inside (created_slot, close_slot)
+    let betting_cutoff_slot = close_slot.saturating_sub(params.betting_cutoff_slots);
+    if params.betting_cutoff_slots == 0 || betting_cutoff_slot <= created_slot {
+        return Err(PredictionError::InvalidBettingCutoff);
+    }
+    if let MarketRule::VolumeInWindowAtLeast {
+        window_start_slot,
+        window_end_slot,
+        ..
+    } = rule
+    {
+        if window_start_slot > window_end_slot
+            || window_start_slot < created_slot
+            || window_end_slot > close_slot
+        {
+            return Err(PredictionError::InvalidVolumeWindow);
+        }
+    }
+    if let MarketRule::MarketCapBuckets {
+        upper_bounds,
+        num_outcomes,
+    } = rule
+    {
+        let n = num_outcomes as usize;
+        if !(2..=MAX_OUTCOMES).contains(&n)
+            || upper_bounds[..n - 1].windows(2).any(|w| w[0] >= w[1])
+            || upper_bounds[n - 1..].iter().any(|&b| b != 0)
+        {
+            return Err(PredictionError::InvalidBuckets);
+        }
+    }
+    if let MarketRule::MarketCapScalar {
+        lower_quote_units,
+        upper_quote_units,
+    } = rule
+    {
+        if lower_quote_units >= upper_quote_units {
+            return Err(PredictionError::InvalidScalarRange);
+        }
+    }
+    if params
+        .protocol_fee_bps
+        .saturating_add(params.creator_fee_bps)
+        > 10_000
+        || params.exit_fee_bps > 10_000
+    {
+        return Err(PredictionError::InvalidFee);
+    }
+    // Every side must fit under the total minimum, when one is set
+    let sides = match rule {
+        MarketRule::MarketCapBuckets { num_outcomes, .. } => num_outcomes as u128,
+        _ => 2,
+    };
+    let min_sides_total = params
+        .min_side_stake
+        .checked_mul(sides)
+        .ok_or(PredictionError::InvalidStakeThreshold)?;
+    if params.min_total_stake != 0 && params.min_total_stake < min_sides_total {
+        return Err(PredictionError::InvalidStakeThreshold);
+    }
+    if let AgreementPolicy::WithinBps { tolerance_bps } = params.agreement_policy {
+        if tolerance_bps > 10_000 {
+            return Err(PredictionError::InvalidAgreementPolicy);
+        }
+    }
 
     Ok(Market {
         market_id,
         token_mint: token.mint,
         created_slot,
         close_slot,
+        betting_cutoff_slot,
         rule,
+        params,
     })
 }
 
@@ -100,15 +630,7 @@ pub fn resolve_outcome(
     market: &Market,
     snapshot: &TokenSnapshot,
 ) -> Result<Outcome, PredictionError> {
-    if snapshot.snapshot_slot < market.close_slot {
-        return Err(PredictionError::SnapshotBeforeClose);
-    }
-    if snapshot.mint != market.token_mint {
-        return Err(PredictionError::SnapshotTokenMismatch);
-    }
-    if !snapshot.migrated_to_pumpswap {
-        return Err(PredictionError::SnapshotNotMigrated);
-    }
+    check_snapshot(market, snapshot)?;
 
     let outcome = match market.rule {
         MarketRule::MarketCapAtCloseAtLeast { target_quote_units } => {
@@ -118,10 +640,228 @@ pub fn resolve_outcome(
                 Outcome::No
             }
         }
+        MarketRule::PriceAtCloseAtLeast { target_price_e6 } => {
+            if snapshot.price_e6 >= target_price_e6 {
+                Outcome::Yes
+            } else {
+                Outcome::No
+            }
+        }
+        MarketRule::VolumeInWindowAtLeast {
+            window_start_slot,
+            window_end_slot,
+            target_volume_quote_units,
+        } => {
+            let volume = snapshot
+                .volume
+                .ok_or(PredictionError::SnapshotMissingVolume)?;
+            if volume.window_start_slot != window_start_slot
+                || volume.window_end_slot != window_end_slot
+            {
+                // A sub-window undercounts volume; anything else is a different window
+                let inside = volume.window_start_slot >= window_start_slot
+                    && volume.window_end_slot <= window_end_slot;
+                return Err(if inside {
+                    PredictionError::SnapshotVolumeWindowPartial
+                } else {
+                    PredictionError::SnapshotVolumeWindowMismatch
+                });
+            }
+            if volume.cumulative_volume_quote_units >= target_volume_quote_units {
+                Outcome::Yes
+            } else {
+                Outcome::No
+            }
+        }
+        MarketRule::MigrationWithinWindow => {
+            match (snapshot.migrated_to_pumpswap, snapshot.migration_slot) {
+                (false, None) => Outcome::No,
+                (true, Some(slot)) => {
+                    if slot >= market.created_slot && slot <= market.close_slot {
+                        Outcome::Yes
+                    } else {
+                        Outcome::No
+                    }
+                }
+                _ => return Err(PredictionError::SnapshotMigrationInconsistent),
+            }
+        }
+        MarketRule::MarketCapBuckets { .. } | MarketRule::MarketCapScalar { .. } => {
+            return Err(PredictionError::NotBinaryMarket)
+        }
     };
     Ok(outcome)
 }
 
+/// Resolve a categorical market to the index of the bucket its snapshot falls in.
+pub fn resolve_bucket(market: &Market, snapshot: &TokenSnapshot) -> Result<u8, PredictionError> {
+    let MarketRule::MarketCapBuckets {
+        upper_bounds,
+        num_outcomes,
+    } = market.rule
+    else {
+        return Err(PredictionError::NotCategoricalMarket);
+    };
+    check_snapshot(market, snapshot)?;
+
+    let bounds = &upper_bounds[..num_outcomes as usize - 1];
+    let bucket = bounds
+        .iter()
+        .position(|&b| snapshot.market_cap_quote_units < b)
+        .unwrap_or(bounds.len());
+    Ok(bucket as u8)
+}
+
+/// Resolve `market` under whichever kind of rule it has: `resolve_bucket`
+/// for categorical, `resolve_scalar` for scalar and `resolve_outcome` for
+/// binary markets.
+pub fn resolve_market(
+    market: &Market,
+    snapshot: &TokenSnapshot,
+) -> Result<ResolvedOutcome, PredictionError> {
+    match market.rule {
+        MarketRule::MarketCapBuckets { .. } => {
+            resolve_bucket(market, snapshot).map(ResolvedOutcome::Bucket)
+        }
+        MarketRule::MarketCapScalar { .. } => {
+            let (long_num, long_den) = resolve_scalar(market, snapshot)?;
+            Ok(ResolvedOutcome::Scalar { long_num, long_den })
+        }
+        _ => resolve_outcome(market, snapshot).map(ResolvedOutcome::Binary),
+    }
+}
+
+/// Snapshot checks shared by every rule: timing, freshness, mint, migration
+fn check_snapshot(market: &Market, snapshot: &TokenSnapshot) -> Result<(), PredictionError> {
+    if snapshot.snapshot_slot < market.close_slot {
+        return Err(PredictionError::SnapshotBeforeClose);
+    }
+    if snapshot.snapshot_slot - market.close_slot > market.params.max_snapshot_delay_slots {
+        return Err(PredictionError::SnapshotStale);
+    }
+    if snapshot.mint != market.token_mint {
+        return Err(PredictionError::SnapshotTokenMismatch);
+    }
+    let is_migration_rule = matches!(market.rule, MarketRule::MigrationWithinWindow);
+    if !snapshot.migrated_to_pumpswap && !is_migration_rule {
+        return Err(PredictionError::SnapshotNotMigrated);
+    }
+    Ok(())
+}
+
+/// Resolve from several oracle sources. `sources[0]` is the primary source.
+///
+/// Every snapshot must pass `resolve_market` on its own. Each secondary is
+/// then compared with the primary on migration status and on the field the
+/// market's rule reads, under `market.params.agreement_policy`, and must
+/// produce the same outcome (or bucket). The first discrepancy found is
+/// returned as `Resolution::Disputed`; the primary's outcome is never used
+/// in that case.
+///
+/// A scalar share moves with the value itself, so for scalar markets
+/// agreement on the value is all that is checked and the primary's share
+/// is used.
+pub fn resolve_with_sources(
+    market: &Market,
+    sources: &[SourcedSnapshot],
+) -> Result<Resolution, PredictionError> {
+    let primary = sources.first().ok_or(PredictionError::NoOracleSources)?;
+    if sources.len() > MAX_ORACLE_SOURCES {
+        return Err(PredictionError::TooManyOracleSources);
+    }
+    for (i, source) in sources.iter().enumerate() {
+        if sources[..i].iter().any(|s| s.source_id == source.source_id) {
+            return Err(PredictionError::DuplicateOracleSource);
+        }
+    }
+
+    let primary_outcome = resolve_market(market, &primary.snapshot)?;
+    for secondary in &sources[1..] {
+        let outcome = resolve_market(market, &secondary.snapshot)?;
+
+        let dispute = |field, primary_value, conflicting_value| {
+            Ok(Resolution::Disputed(Dispute {
+                field,
+                primary_source_id: primary.source_id,
+                conflicting_source_id: secondary.source_id,
+                primary_value,
+                conflicting_value,
+            }))
+        };
+
+        let (a, b) = (&primary.snapshot, &secondary.snapshot);
+        if a.migrated_to_pumpswap != b.migrated_to_pumpswap {
+            return dispute(
+                DisputeField::MigrationStatus,
+                a.migrated_to_pumpswap as u128,
+                b.migrated_to_pumpswap as u128,
+            );
+        }
+
+        let (field, x, y, policy) = match market.rule {
+            MarketRule::MarketCapAtCloseAtLeast { .. }
+            | MarketRule::MarketCapBuckets { .. }
+            | MarketRule::MarketCapScalar { .. } => (
+                DisputeField::MarketCap,
+                a.market_cap_quote_units,
+                b.market_cap_quote_units,
+                market.params.agreement_policy,
+            ),
+            MarketRule::PriceAtCloseAtLeast { .. } => (
+                DisputeField::Price,
+                a.price_e6 as u128,
+                b.price_e6 as u128,
+                market.params.agreement_policy,
+            ),
+            MarketRule::VolumeInWindowAtLeast { .. } => (
+                DisputeField::Volume,
+                // Present on both: resolve_market checked it above
+                a.volume.map_or(0, |v| v.cumulative_volume_quote_units),
+                b.volume.map_or(0, |v| v.cumulative_volume_quote_units),
+                market.params.agreement_policy,
+            ),
+            // Migration is a discrete event; slots must match exactly
+            MarketRule::MigrationWithinWindow => (
+                DisputeField::MigrationSlot,
+                a.migration_slot.map_or(u128::MAX, |s| s as u128),
+                b.migration_slot.map_or(u128::MAX, |s| s as u128),
+                AgreementPolicy::Exact,
+            ),
+        };
+        if !values_agree(policy, x, y) {
+            return dispute(field, x, y);
+        }
+
+        // Values within tolerance can still straddle a threshold or bucket bound
+        let encode = |outcome| match outcome {
+            ResolvedOutcome::Binary(side) => Some((side == Outcome::Yes) as u128),
+            ResolvedOutcome::Bucket(bucket) => Some(bucket as u128),
+            ResolvedOutcome::Scalar { .. } => None,
+        };
+        if let (Some(x), Some(y)) = (encode(primary_outcome), encode(outcome)) {
+            if x != y {
+                return dispute(DisputeField::Outcome, x, y);
+            }
+        }
+    }
+
+    Ok(Resolution::Resolved(primary_outcome))
+}
+
+/// Whether two source values agree under `policy`
+fn values_agree(policy: AgreementPolicy, a: u128, b: u128) -> bool {
+    match policy {
+        AgreementPolicy::Exact => a == b,
+        AgreementPolicy::WithinBps { tolerance_bps } => {
+            let (hi, lo) = if a >= b { (a, b) } else { (b, a) };
+            // floor(hi * bps / 10_000) without overflow (bps <= 10_000)
+            let bps = tolerance_bps as u128;
+            let tolerance = (hi / 10_000) * bps + (hi % 10_000) * bps / 10_000;
+            hi - lo <= tolerance
+        }
+    }
+}
+
 /// Settle market-wide payouts using Percolator-style bounded profit conversion.
 ///
 /// `available_vault_funds` can be lower than total pooled capital in stress
@@ -133,8 +873,39 @@ pub fn settle_market(
     pools: Pools,
     available_vault_funds: u128,
 ) -> Result<Settlement, PredictionError> {
+    check_liquidity(pools, &market.params)?;
     let outcome = resolve_outcome(market, snapshot)?;
+    settle_outcome(outcome, pools, available_vault_funds, &market.params)
+}
+
+/// Whether `pools` meet the market's `min_total_stake` and `min_side_stake`.
+///
+/// A market that fails this at close never settles: it can only be refunded
+/// (`CancelReason::InsufficientLiquidity`).
+pub fn check_liquidity(pools: Pools, params: &MarketParams) -> Result<(), PredictionError> {
+    let total = pools
+        .yes_capital
+        .checked_add(pools.no_capital)
+        .ok_or(PredictionError::MathOverflow)?;
+    if total < params.min_total_stake
+        || pools.yes_capital < params.min_side_stake
+        || pools.no_capital < params.min_side_stake
+    {
+        return Err(PredictionError::InsufficientLiquidity);
+    }
+    Ok(())
+}
 
+/// Settle pools for an outcome that has already been resolved.
+///
+/// Same payout math as `settle_market`; used by `MarketState` so that the
+/// outcome fixed at resolution is the one that gets settled.
+pub fn settle_outcome(
+    outcome: Outcome,
+    pools: Pools,
+    available_vault_funds: u128,
+    params: &MarketParams,
+) -> Result<Settlement, PredictionError> {
     let (winner_capital_total, loser_capital_total) = match outcome {
         Outcome::Yes => (pools.yes_capital, pools.no_capital),
         Outcome::No => (pools.no_capital, pools.yes_capital),
@@ -144,11 +915,21 @@ pub fn settle_market(
         return Err(PredictionError::EmptyWinnerSide);
     }
 
-    // Potential positive profit for winners in a binary market
-    let profit_claim_total = loser_capital_total;
+    // Fees come off the top of the loser pool; the rest is the potential
+    // positive profit for winners in a binary market
+    let (protocol_fee, creator_fee, profit_claim_total) = split_fees(loser_capital_total, params)?;
 
+    // Senior capital is bounded by the vault too; any shortfall leaves no residual
+    let winner_capital_paid = min(available_vault_funds, winner_capital_total);
     let residual = available_vault_funds.saturating_sub(winner_capital_total);
-    let funded_profit = min(residual, profit_claim_total);
+
+    // Fees are paid from the residual before h is applied to winner profit
+    let protocol_fee_paid = min(residual, protocol_fee);
+    let creator_fee_paid = min(residual - protocol_fee_paid, creator_fee);
+    let funded_profit = min(
+        residual - protocol_fee_paid - creator_fee_paid,
+        profit_claim_total,
+    );
 
     let (h_num, h_den) = if profit_claim_total == 0 {
         (1, 1)
@@ -156,7 +937,7 @@ pub fn settle_market(
         (funded_profit, profit_claim_total)
     };
 
-    let winner_payout_total = winner_capital_total
+    let winner_payout_total = winner_capital_paid
         .checked_add(funded_profit)
         .ok_or(PredictionError::MathOverflow)?;
 
@@ -164,7 +945,10 @@ pub fn settle_market(
         outcome,
         winner_capital_total,
         loser_capital_total,
+        winner_capital_paid,
         residual,
+        protocol_fee_paid,
+        creator_fee_paid,
         profit_claim_total,
         h_num,
         h_den,
@@ -173,135 +957,5346 @@ pub fn settle_market(
     })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// `settle_market`, plus the audit record for the settlement.
+///
+/// `source_ids` lists the oracle sources behind `snapshot` (primary first).
+pub fn settle_market_with_record(
+    market: &Market,
+    snapshot: &TokenSnapshot,
+    pools: Pools,
+    available_vault_funds: u128,
+    source_ids: &[u32],
+    settled_slot: u64,
+) -> Result<(Settlement, ResolutionRecord), PredictionError> {
+    let settlement = settle_market(market, snapshot, pools, available_vault_funds)?;
+    let record = resolution_record(
+        market,
+        snapshot,
+        pools,
+        &settlement,
+        source_ids,
+        settled_slot,
+    )?;
+    Ok((settlement, record))
+}
 
-    fn mint(byte: u8) -> [u8; 32] {
-        [byte; 32]
+/// Build the audit record for an existing settlement.
+///
+/// The recorded outcome is what `snapshot` resolves to under the market's
+/// rule, so a categorical record names the winning bucket even though its
+/// `Settlement` is that of the collapsed market (`pools` are then the
+/// collapsed pools). Scalar markets use `scalar_resolution_record`.
+pub fn resolution_record(
+    market: &Market,
+    snapshot: &TokenSnapshot,
+    pools: Pools,
+    settlement: &Settlement,
+    source_ids: &[u32],
+    settled_slot: u64,
+) -> Result<ResolutionRecord, PredictionError> {
+    let outcome = resolve_market(market, snapshot)?;
+    let settled_side = match outcome {
+        ResolvedOutcome::Binary(side) => side,
+        ResolvedOutcome::Bucket(_) => Outcome::Yes,
+        ResolvedOutcome::Scalar { .. } => return Err(PredictionError::NotBinaryMarket),
+    };
+    if settlement.outcome != settled_side {
+        return Err(PredictionError::LedgerPoolsMismatch);
     }
+    let (ids, num_sources) = record_sources(source_ids)?;
 
-    #[test]
-    fn rejects_non_migrated_token_on_market_creation() {
-        let token = TokenStatus {
-            mint: mint(7),
-            migrated_to_pumpswap: false,
-        };
-
-        let err = create_market(
-            token,
-            1,
-            100,
-            200,
-            MarketRule::MarketCapAtCloseAtLeast {
-                target_quote_units: 1_000_000,
-            },
-        )
-        .unwrap_err();
+    Ok(ResolutionRecord {
+        market_id: market.market_id,
+        outcome,
+        input_hash: settlement_input_hash(snapshot, outcome, pools, settlement),
+        source_ids: ids,
+        num_sources,
+        resolver_version: RESOLVER_VERSION,
+        settled_slot,
+    })
+}
 
-        assert_eq!(err, PredictionError::TokenNotMigrated);
+/// `resolution_record` for a scalar market's `ScalarSettlement`
+pub fn scalar_resolution_record(
+    market: &Market,
+    snapshot: &TokenSnapshot,
+    pools: Pools,
+    settlement: &ScalarSettlement,
+    source_ids: &[u32],
+    settled_slot: u64,
+) -> Result<ResolutionRecord, PredictionError> {
+    let (long_num, long_den) = resolve_scalar(market, snapshot)?;
+    if (long_num, long_den) != (settlement.long_num, settlement.long_den) {
+        return Err(PredictionError::LedgerPoolsMismatch);
     }
+    let (ids, num_sources) = record_sources(source_ids)?;
 
-    #[test]
-    fn full_coverage_settlement_pays_full_profit() {
-        let token = TokenStatus {
-            mint: mint(1),
-            migrated_to_pumpswap: true,
-        };
-        let market = create_market(
-            token,
-            42,
-            100,
-            200,
-            MarketRule::MarketCapAtCloseAtLeast {
-                target_quote_units: 1_000_000,
-            },
-        )
-        .unwrap();
-        let snapshot = TokenSnapshot {
-            mint: mint(1),
-            migrated_to_pumpswap: true,
-            market_cap_quote_units: 2_000_000,
-            snapshot_slot: 201,
-        };
-        let pools = Pools {
-            yes_capital: 100,
-            no_capital: 40,
-        };
+    Ok(ResolutionRecord {
+        market_id: market.market_id,
+        outcome: ResolvedOutcome::Scalar { long_num, long_den },
+        input_hash: scalar_settlement_input_hash(snapshot, pools, settlement),
+        source_ids: ids,
+        num_sources,
+        resolver_version: RESOLVER_VERSION,
+        settled_slot,
+    })
+}
 
-        let settlement = settle_market(&market, &snapshot, pools, 140).unwrap();
-        assert_eq!(settlement.outcome, Outcome::Yes);
-        assert_eq!(settlement.h_num, 40);
-        assert_eq!(settlement.h_den, 40);
-        assert_eq!(settlement.winner_payout_total, 140);
+/// Source ids for a record, zero-padded, and their count
+fn record_sources(source_ids: &[u32]) -> Result<([u32; MAX_ORACLE_SOURCES], u8), PredictionError> {
+    if source_ids.is_empty() {
+        return Err(PredictionError::NoOracleSources);
+    }
+    if source_ids.len() > MAX_ORACLE_SOURCES {
+        return Err(PredictionError::TooManyOracleSources);
+    }
+    let mut ids = [0u32; MAX_ORACLE_SOURCES];
+    ids[..source_ids.len()].copy_from_slice(source_ids);
+    Ok((ids, source_ids.len() as u8))
+}
+
+/// SHA-256 over the canonical settlement input encoding.
+///
+/// All integers are little-endian and fixed width; options are a tag byte
+/// (0 = None, 1 = Some) followed by the payload, zero-filled when None:
+///
+/// ```text
+/// resolver_version u32
+/// outcome:  kind u8 (0 = binary, 1 = bucket, 2 = scalar) | a u128 | b u128
+///           binary: a = 0 (No) or 1 (Yes), b = 0; bucket: a = index, b = 0;
+///           scalar: a = long_num, b = long_den
+/// snapshot: mint [32] | migrated u8 | market_cap u128 | price_e6 u64
+///           | volume tag u8, window_start u64, window_end u64, volume u128
+///           | migration_slot tag u8, slot u64 | snapshot_slot u64
+/// pools:    yes_capital u128 | no_capital u128
+/// settlement: outcome u8 (0 = No, 1 = Yes) | winner_capital_total | loser_capital_total
+///           | winner_capital_paid | residual | protocol_fee_paid | creator_fee_paid
+///           | profit_claim_total | h_num | h_den | winner_profit_paid
+///           | winner_payout_total   (all u128)
+/// ```
+///
+/// Categorical markets hash their collapsed pools and settlement (winning
+/// bucket as YES); the bucket index is in `outcome`.
+pub fn settlement_input_hash(
+    snapshot: &TokenSnapshot,
+    outcome: ResolvedOutcome,
+    pools: Pools,
+    settlement: &Settlement,
+) -> [u8; 32] {
+    let mut h = settlement_input_prefix(snapshot, outcome, pools);
+    h.update(&[(settlement.outcome == Outcome::Yes) as u8]);
+    for v in [
+        settlement.winner_capital_total,
+        settlement.loser_capital_total,
+        settlement.winner_capital_paid,
+        settlement.residual,
+        settlement.protocol_fee_paid,
+        settlement.creator_fee_paid,
+        settlement.profit_claim_total,
+        settlement.h_num,
+        settlement.h_den,
+        settlement.winner_profit_paid,
+        settlement.winner_payout_total,
+    ] {
+        h.update(&v.to_le_bytes());
+    }
+    h.finalize()
+}
+
+/// `settlement_input_hash` for a scalar market: the same prefix with the
+/// scalar outcome and LONG/SHORT pools, then every `ScalarSettlement` field
+/// after `long_den`, in declaration order (all u128).
+pub fn scalar_settlement_input_hash(
+    snapshot: &TokenSnapshot,
+    pools: Pools,
+    settlement: &ScalarSettlement,
+) -> [u8; 32] {
+    let outcome = ResolvedOutcome::Scalar {
+        long_num: settlement.long_num,
+        long_den: settlement.long_den,
+    };
+    let mut h = settlement_input_prefix(snapshot, outcome, pools);
+    for v in [
+        settlement.long_capital_total,
+        settlement.short_capital_total,
+        settlement.long_claim,
+        settlement.short_claim,
+        settlement.long_capital_paid,
+        settlement.short_capital_paid,
+        settlement.residual,
+        settlement.protocol_fee_paid,
+        settlement.creator_fee_paid,
+        settlement.profit_claim_total,
+        settlement.h_num,
+        settlement.h_den,
+        settlement.long_payout_total,
+        settlement.short_payout_total,
+    ] {
+        h.update(&v.to_le_bytes());
+    }
+    h.finalize()
+}
+
+/// Version, outcome, snapshot and pools: everything hashed before the settlement
+fn settlement_input_prefix(
+    snapshot: &TokenSnapshot,
+    outcome: ResolvedOutcome,
+    pools: Pools,
+) -> Sha256 {
+    let mut h = Sha256::new();
+    h.update(&RESOLVER_VERSION.to_le_bytes());
+
+    let (kind, a, b) = match outcome {
+        ResolvedOutcome::Binary(side) => (0u8, (side == Outcome::Yes) as u128, 0),
+        ResolvedOutcome::Bucket(bucket) => (1, bucket as u128, 0),
+        ResolvedOutcome::Scalar { long_num, long_den } => (2, long_num, long_den),
+    };
+    h.update(&[kind]);
+    h.update(&a.to_le_bytes());
+    h.update(&b.to_le_bytes());
+
+    h.update(&snapshot.mint);
+    h.update(&[snapshot.migrated_to_pumpswap as u8]);
+    h.update(&snapshot.market_cap_quote_units.to_le_bytes());
+    h.update(&snapshot.price_e6.to_le_bytes());
+    let volume = snapshot.volume.unwrap_or(VolumeWindowSnapshot {
+        window_start_slot: 0,
+        window_end_slot: 0,
+        cumulative_volume_quote_units: 0,
+    });
+    h.update(&[snapshot.volume.is_some() as u8]);
+    h.update(&volume.window_start_slot.to_le_bytes());
+    h.update(&volume.window_end_slot.to_le_bytes());
+    h.update(&volume.cumulative_volume_quote_units.to_le_bytes());
+    h.update(&[snapshot.migration_slot.is_some() as u8]);
+    h.update(&snapshot.migration_slot.unwrap_or(0).to_le_bytes());
+    h.update(&snapshot.snapshot_slot.to_le_bytes());
+
+    h.update(&pools.yes_capital.to_le_bytes());
+    h.update(&pools.no_capital.to_le_bytes());
+    h
+}
+
+impl ParticipantLedger {
+    pub fn new() -> Self {
+        Self {
+            pools: Pools {
+                yes_capital: 0,
+                no_capital: 0,
+            },
+            exit_fees: 0,
+            num_participants: 0,
+            positions: [ParticipantPosition {
+                owner: [0; 32],
+                yes_capital: 0,
+                no_capital: 0,
+                exit_fees: 0,
+            }; MAX_PARTICIPANTS],
+        }
+    }
+
+    /// Index of `owner`'s position, if they have staked
+    pub fn find(&self, owner: &[u8; 32]) -> Option<u16> {
+        self.positions[..self.num_participants as usize]
+            .iter()
+            .position(|p| p.owner == *owner)
+            .map(|i| i as u16)
+    }
+
+    /// Add `amount` to `owner`'s stake on `side`, opening a position if needed.
+    /// Returns the position index.
+    pub fn record_stake(
+        &mut self,
+        owner: [u8; 32],
+        side: Outcome,
+        amount: u128,
+    ) -> Result<u16, PredictionError> {
+        if amount == 0 {
+            return Err(PredictionError::ZeroStake);
+        }
+
+        let idx = match self.find(&owner) {
+            Some(idx) => idx,
+            None => {
+                let idx = self.num_participants;
+                if idx as usize >= MAX_PARTICIPANTS {
+                    return Err(PredictionError::LedgerFull);
+                }
+                self.positions[idx as usize] = ParticipantPosition {
+                    owner,
+                    yes_capital: 0,
+                    no_capital: 0,
+                    exit_fees: 0,
+                };
+                self.num_participants += 1;
+                idx
+            }
+        };
+
+        // Check both sums before writing so a failed stake leaves no trace
+        let position = &mut self.positions[idx as usize];
+        let (stake, pool) = match side {
+            Outcome::Yes => (&mut position.yes_capital, &mut self.pools.yes_capital),
+            Outcome::No => (&mut position.no_capital, &mut self.pools.no_capital),
+        };
+        let new_stake = stake
+            .checked_add(amount)
+            .ok_or(PredictionError::MathOverflow)?;
+        let new_pool = pool
+            .checked_add(amount)
+            .ok_or(PredictionError::MathOverflow)?;
+        *stake = new_stake;
+        *pool = new_pool;
+
+        Ok(idx)
+    }
+
+    /// Remove `amount` from `owner`'s stake on `side`. The position stays
+    /// (possibly empty) so indices remain stable. Returns the position index.
+    pub fn withdraw_stake(
+        &mut self,
+        owner: [u8; 32],
+        side: Outcome,
+        amount: u128,
+    ) -> Result<u16, PredictionError> {
+        if amount == 0 {
+            return Err(PredictionError::ZeroStake);
+        }
+        let idx = self
+            .find(&owner)
+            .ok_or(PredictionError::InsufficientStake)?;
+
+        let position = &mut self.positions[idx as usize];
+        let (stake, pool) = match side {
+            Outcome::Yes => (&mut position.yes_capital, &mut self.pools.yes_capital),
+            Outcome::No => (&mut position.no_capital, &mut self.pools.no_capital),
+        };
+        if amount > *stake {
+            return Err(PredictionError::InsufficientStake);
+        }
+        // pool >= stake by the column-sum invariant
+        *stake -= amount;
+        *pool -= amount;
+        Ok(idx)
+    }
+
+    /// Pools to settle `outcome` against: exit fees join the losing side, so
+    /// winners receive them as profit whichever side they were kept from.
+    pub fn settlement_pools(&self, outcome: Outcome) -> Result<Pools, PredictionError> {
+        let mut pools = self.pools;
+        let loser = match outcome {
+            Outcome::Yes => &mut pools.no_capital,
+            Outcome::No => &mut pools.yes_capital,
+        };
+        *loser = loser
+            .checked_add(self.exit_fees)
+            .ok_or(PredictionError::MathOverflow)?;
+        Ok(pools)
+    }
+}
+
+impl Default for ParticipantLedger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Split a market-level `Settlement` across the participants in `ledger`.
+///
+/// Each winner receives `capital_i + floor(profit_i * h)` where
+/// `profit_i = floor(capital_i * profit_claim_total / winner_capital_total)`.
+/// If the vault could not cover winner capital, capital is scaled pro-rata
+/// (floored) first. Losers receive nothing.
+///
+/// Every term is floored, so `total_paid <= winner_payout_total`; the
+/// difference is returned as `rounding_dust`.
+pub fn settle_participants(
+    ledger: &ParticipantLedger,
+    settlement: &Settlement,
+) -> Result<ParticipantPayouts, PredictionError> {
+    let pools = ledger.settlement_pools(settlement.outcome)?;
+    let (winner_pool, loser_pool) = match settlement.outcome {
+        Outcome::Yes => (pools.yes_capital, pools.no_capital),
+        Outcome::No => (pools.no_capital, pools.yes_capital),
+    };
+    if winner_pool != settlement.winner_capital_total
+        || loser_pool != settlement.loser_capital_total
+    {
+        return Err(PredictionError::LedgerPoolsMismatch);
+    }
+    if settlement.winner_capital_total == 0 {
+        return Err(PredictionError::EmptyWinnerSide);
+    }
+
+    let mut payouts = [0u128; MAX_PARTICIPANTS];
+    let mut total_paid = 0u128;
+
+    for (i, position) in ledger.positions[..ledger.num_participants as usize]
+        .iter()
+        .enumerate()
+    {
+        let capital_i = match settlement.outcome {
+            Outcome::Yes => position.yes_capital,
+            Outcome::No => position.no_capital,
+        };
+        if capital_i == 0 {
+            continue;
+        }
+
+        let capital_paid_i = if settlement.winner_capital_paid == settlement.winner_capital_total {
+            capital_i
+        } else {
+            mul_div_floor(
+                capital_i,
+                settlement.winner_capital_paid,
+                settlement.winner_capital_total,
+            )?
+        };
+        let profit_i = mul_div_floor(
+            capital_i,
+            settlement.profit_claim_total,
+            settlement.winner_capital_total,
+        )?;
+        let profit_paid_i = mul_div_floor(profit_i, settlement.h_num, settlement.h_den)?;
+
+        let payout_i = capital_paid_i
+            .checked_add(profit_paid_i)
+            .ok_or(PredictionError::MathOverflow)?;
+        payouts[i] = payout_i;
+        total_paid = total_paid
+            .checked_add(payout_i)
+            .ok_or(PredictionError::MathOverflow)?;
+    }
+
+    // Floors only ever round down, so this cannot underflow for a settlement
+    // produced by settle_market; treat anything else as corrupt input.
+    let rounding_dust = settlement
+        .winner_payout_total
+        .checked_sub(total_paid)
+        .ok_or(PredictionError::MathOverflow)?;
+
+    Ok(ParticipantPayouts {
+        payouts,
+        total_paid,
+        rounding_dust,
+    })
+}
+
+impl MarketState {
+    pub fn new(market: Market) -> Self {
+        Self {
+            market,
+            phase: MarketPhase::Open,
+            last_transition_slot: market.created_slot,
+            ledger: ParticipantLedger::new(),
+            categorical: CategoricalLedger::new(match market.rule {
+                MarketRule::MarketCapBuckets { num_outcomes, .. } => num_outcomes,
+                _ => 0,
+            }),
+            outcome: None,
+            settlement: None,
+            scalar_settlement: None,
+            dispute: None,
+            disputed_since_slot: 0,
+            cancel_reason: None,
+            refunded: false,
+            account_idx: [NO_ENGINE_ACCOUNT; MAX_PARTICIPANTS],
+        }
+    }
+
+    /// Record a stake while betting is open (`created_slot <= now_slot < betting_cutoff_slot`).
+    ///
+    /// Scalar markets take LONG as `Outcome::Yes` and SHORT as `Outcome::No`;
+    /// categorical markets stake through `stake_outcome`.
+    pub fn stake(
+        &mut self,
+        owner: [u8; 32],
+        side: Outcome,
+        amount: u128,
+        now_slot: u64,
+    ) -> Result<u16, PredictionError> {
+        if self.is_categorical() {
+            return Err(PredictionError::NotBinaryMarket);
+        }
+        self.require_betting_open(now_slot)?;
+        let pos = self.ledger.record_stake(owner, side, amount)?;
+        self.last_transition_slot = now_slot;
+        Ok(pos)
+    }
+
+    /// Record a stake on outcome `outcome` of a categorical market while
+    /// betting is open. Returns the position index in `categorical`.
+    pub fn stake_outcome(
+        &mut self,
+        owner: [u8; 32],
+        outcome: u8,
+        amount: u128,
+        now_slot: u64,
+    ) -> Result<u16, PredictionError> {
+        if !self.is_categorical() {
+            return Err(PredictionError::NotCategoricalMarket);
+        }
+        self.require_betting_open(now_slot)?;
+        let pos = self.categorical.record_stake(owner, outcome, amount)?;
+        self.last_transition_slot = now_slot;
+        Ok(pos)
+    }
+
+    /// Withdraw `amount` of stake before the betting cutoff. Returns the
+    /// refund, `amount - ceil(amount * exit_fee_bps / 10_000)`; the fee stays
+    /// in the pot as `ledger.exit_fees`, recorded against `owner`.
+    ///
+    /// Engine-backed positions cannot be unstaked here: their stake is
+    /// account capital, which `settle_on_engine` accounts for directly.
+    /// Binary markets only: the fee joins the losing pool at settlement, and
+    /// categorical and scalar pots have no single losing side.
+    pub fn unstake(
+        &mut self,
+        owner: [u8; 32],
+        side: Outcome,
+        amount: u128,
+        now_slot: u64,
+    ) -> Result<u128, PredictionError> {
+        if !self.is_binary() {
+            return Err(PredictionError::NotBinaryMarket);
+        }
+        self.require_betting_open(now_slot)?;
+        if let Some(pos) = self.ledger.find(&owner) {
+            if self.account_idx[pos as usize] != NO_ENGINE_ACCOUNT {
+                return Err(PredictionError::AccountMismatch);
+            }
+        }
+
+        // Round the fee up, like the engine's trading fee, so splitting an
+        // exit into small pieces cannot avoid it
+        let fee = amount
+            .checked_mul(self.market.params.exit_fee_bps as u128)
+            .map(|x| x.div_ceil(10_000))
+            .ok_or(PredictionError::MathOverflow)?;
+        let exit_fees = self
+            .ledger
+            .exit_fees
+            .checked_add(fee)
+            .ok_or(PredictionError::MathOverflow)?;
+        let pos = self.ledger.withdraw_stake(owner, side, amount)?;
+        self.ledger.exit_fees = exit_fees;
+        // Bounded by the ledger total just checked
+        self.ledger.positions[pos as usize].exit_fees += fee;
+        self.last_transition_slot = now_slot;
+        Ok(amount - fee)
+    }
+
+    /// Open → Closed, once `now_slot >= close_slot`. A market whose stakes
+    /// miss the liquidity thresholds goes straight to Cancelled instead
+    /// (`CancelReason::InsufficientLiquidity`), so it can only be refunded.
+    pub fn close(&mut self, now_slot: u64) -> Result<(), PredictionError> {
+        self.require_phase(MarketPhase::Open)?;
+        self.require_slot(now_slot)?;
+        if now_slot < self.market.close_slot {
+            return Err(PredictionError::CloseSlotNotReached);
+        }
+        if self.check_stake_liquidity().is_err() {
+            self.cancel_reason = Some(CancelReason::InsufficientLiquidity);
+            self.transition(MarketPhase::Cancelled, now_slot);
+            return Ok(());
+        }
+        self.transition(MarketPhase::Closed, now_slot);
+        Ok(())
+    }
+
+    /// Closed → Resolved. The snapshot must already exist at `now_slot`.
+    ///
+    /// A single snapshot cannot settle a dispute; a Disputed market resolves
+    /// only through `resolve_from_sources`.
+    pub fn resolve(
+        &mut self,
+        snapshot: &TokenSnapshot,
+        now_slot: u64,
+    ) -> Result<ResolvedOutcome, PredictionError> {
+        if self.phase == MarketPhase::Disputed {
+            return Err(PredictionError::DisputeSourcesMissing);
+        }
+        self.require_phase(MarketPhase::Closed)?;
+        self.require_slot(now_slot)?;
+        if snapshot.snapshot_slot > now_slot {
+            return Err(PredictionError::SnapshotInFuture);
+        }
+        let outcome = resolve_market(&self.market, snapshot)?;
+        self.outcome = Some(outcome);
+        self.dispute = None;
+        self.transition(MarketPhase::Resolved, now_slot);
+        Ok(outcome)
+    }
+
+    /// Closed | Disputed → Resolved when all sources agree (see
+    /// `resolve_with_sources`); otherwise → Disputed with the discrepancy
+    /// recorded. A disputed market cannot settle until it is resolved again.
+    ///
+    /// Re-resolving a Disputed market must consult both sources of the
+    /// recorded dispute, so the disagreement cannot be dropped by leaving a
+    /// source out; after a manual `dispute` at least two sources are needed.
+    /// The first recorded dispute stays binding until the market resolves.
+    pub fn resolve_from_sources(
+        &mut self,
+        sources: &[SourcedSnapshot],
+        now_slot: u64,
+    ) -> Result<Resolution, PredictionError> {
+        if !matches!(self.phase, MarketPhase::Closed | MarketPhase::Disputed) {
+            return Err(PredictionError::InvalidTransition);
+        }
+        self.require_slot(now_slot)?;
+        if sources.iter().any(|s| s.snapshot.snapshot_slot > now_slot) {
+            return Err(PredictionError::SnapshotInFuture);
+        }
+        if self.phase == MarketPhase::Disputed {
+            let consulted = |id: u32| sources.iter().any(|s| s.source_id == id);
+            let covered = match self.dispute {
+                Some(d) => consulted(d.primary_source_id) && consulted(d.conflicting_source_id),
+                None => sources.len() >= 2,
+            };
+            if !covered {
+                return Err(PredictionError::DisputeSourcesMissing);
+            }
+        }
+
+        let resolution = resolve_with_sources(&self.market, sources)?;
+        match resolution {
+            Resolution::Resolved(outcome) => {
+                self.outcome = Some(outcome);
+                self.dispute = None;
+                self.transition(MarketPhase::Resolved, now_slot);
+            }
+            Resolution::Disputed(dispute) => {
+                self.outcome = None;
+                if self.dispute.is_none() {
+                    self.dispute = Some(dispute);
+                }
+                self.transition(MarketPhase::Disputed, now_slot);
+            }
+        }
+        Ok(resolution)
+    }
+
+    /// Closed → Disputed, pausing resolution for review. A resolved outcome
+    /// is binding: only disagreeing sources can dispute after that point,
+    /// and they do so before resolution (see `resolve_from_sources`).
+    pub fn dispute(&mut self, now_slot: u64) -> Result<(), PredictionError> {
+        self.require_phase(MarketPhase::Closed)?;
+        self.require_slot(now_slot)?;
+        self.dispute = None;
+        self.transition(MarketPhase::Disputed, now_slot);
+        Ok(())
+    }
+
+    /// Resolved → Settled. One-shot: settles the outcome fixed at resolution.
+    ///
+    /// A categorical market settles as its collapsed binary market (see
+    /// `settle_categorical`); split it with `settle_participants` over
+    /// `categorical.collapse(winning)`. Markets with engine-backed positions
+    /// settle through `settle_on_engine` instead.
+    pub fn settle(
+        &mut self,
+        available_vault_funds: u128,
+        now_slot: u64,
+    ) -> Result<Settlement, PredictionError> {
+        self.require_phase(MarketPhase::Resolved)?;
+        self.require_slot(now_slot)?;
+        self.require_no_engine_positions()?;
+        let outcome = self.outcome.ok_or(PredictionError::InvalidTransition)?;
+        self.check_stake_liquidity()?;
+        let params = &self.market.params;
+        let settlement = match outcome {
+            ResolvedOutcome::Binary(outcome) => {
+                let pools = self.ledger.settlement_pools(outcome)?;
+                settle_outcome(outcome, pools, available_vault_funds, params)?
+            }
+            ResolvedOutcome::Bucket(winning) => settle_categorical(
+                winning,
+                &self.categorical.pools,
+                available_vault_funds,
+                params,
+            )?,
+            // Scalar markets settle through settle_scalar
+            ResolvedOutcome::Scalar { .. } => return Err(PredictionError::NotBinaryMarket),
+        };
+        self.settlement = Some(settlement);
+        self.transition(MarketPhase::Settled, now_slot);
+        Ok(settlement)
+    }
+
+    /// Resolved → Settled for a scalar market (see `settle_scalar`), which
+    /// `settle` cannot express. Split the result across participants with
+    /// `settle_scalar_participants` over `ledger`.
+    pub fn settle_scalar(
+        &mut self,
+        available_vault_funds: u128,
+        now_slot: u64,
+    ) -> Result<ScalarSettlement, PredictionError> {
+        self.require_phase(MarketPhase::Resolved)?;
+        self.require_slot(now_slot)?;
+        let Some(ResolvedOutcome::Scalar { long_num, long_den }) = self.outcome else {
+            return Err(PredictionError::NotScalarMarket);
+        };
+        self.check_stake_liquidity()?;
+        let settlement = settle_scalar(
+            long_num,
+            long_den,
+            self.ledger.pools,
+            available_vault_funds,
+            &self.market.params,
+        )?;
+        self.scalar_settlement = Some(settlement);
+        self.transition(MarketPhase::Settled, now_slot);
+        Ok(settlement)
+    }
+
+    /// Void the market so that every stake is refunded (see `refunds`).
+    ///
+    /// Each reason is only accepted in the situation it describes:
+    /// - `EmptyWinnerSide`: Resolved, with no stake on the resolved outcome
+    ///   (for scalar markets: a side with a claim on the pot but no stake)
+    /// - `SnapshotUnavailable`: Closed, and `now_slot` is past the last slot a
+    ///   fresh snapshot could have (`close_slot + max_snapshot_delay_slots`)
+    /// - `DisputeTimeout`: Disputed for at least `dispute_timeout_slots`
+    /// - `TokenDelisted`: never here; it needs evidence (see `cancel_delisted`)
+    /// - `InsufficientLiquidity`: after close, with stakes below the
+    ///   thresholds (normally applied by `close` itself)
+    pub fn cancel(&mut self, reason: CancelReason, now_slot: u64) -> Result<(), PredictionError> {
+        if self.is_terminal() {
+            return Err(PredictionError::InvalidTransition);
+        }
+        self.require_slot(now_slot)?;
+
+        let params = &self.market.params;
+        let allowed = match reason {
+            CancelReason::EmptyWinnerSide => {
+                self.phase == MarketPhase::Resolved
+                    && match self.outcome {
+                        Some(ResolvedOutcome::Binary(Outcome::Yes)) => {
+                            self.ledger.pools.yes_capital == 0
+                        }
+                        Some(ResolvedOutcome::Binary(Outcome::No)) => {
+                            self.ledger.pools.no_capital == 0
+                        }
+                        Some(ResolvedOutcome::Bucket(winning)) => {
+                            self.categorical.pools.capital[winning as usize] == 0
+                        }
+                        Some(ResolvedOutcome::Scalar { long_num, long_den }) => {
+                            let pools = self.ledger.pools;
+                            scalar_claims(pools, long_num, long_den).is_ok_and(
+                                |(long_claim, short_claim)| {
+                                    (long_claim > 0 && pools.yes_capital == 0)
+                                        || (short_claim > 0 && pools.no_capital == 0)
+                                },
+                            )
+                        }
+                        None => false,
+                    }
+            }
+            CancelReason::SnapshotUnavailable => {
+                self.phase == MarketPhase::Closed
+                    && now_slot.saturating_sub(self.market.close_slot)
+                        > params.max_snapshot_delay_slots
+            }
+            CancelReason::DisputeTimeout => {
+                self.phase == MarketPhase::Disputed
+                    && now_slot.saturating_sub(self.disputed_since_slot)
+                        >= params.dispute_timeout_slots
+            }
+            CancelReason::TokenDelisted => false,
+            CancelReason::InsufficientLiquidity => {
+                self.phase != MarketPhase::Open && self.check_stake_liquidity().is_err()
+            }
+        };
+        if !allowed {
+            return Err(PredictionError::CancelNotAllowed);
+        }
+
+        self.cancel_reason = Some(reason);
+        self.transition(MarketPhase::Cancelled, now_slot);
+        Ok(())
+    }
+
+    /// Void the market because its token was delisted, with `token` as the
+    /// evidence: it must be this market's mint with `delisted` set. Allowed
+    /// in Open, Closed and Disputed; once Resolved, the outcome stands.
+    pub fn cancel_delisted(
+        &mut self,
+        token: &TokenStatus,
+        now_slot: u64,
+    ) -> Result<(), PredictionError> {
+        if self.is_terminal() {
+            return Err(PredictionError::InvalidTransition);
+        }
+        self.require_slot(now_slot)?;
+        if token.mint != self.market.token_mint {
+            return Err(PredictionError::SnapshotTokenMismatch);
+        }
+        if !token.delisted || self.phase == MarketPhase::Resolved {
+            return Err(PredictionError::CancelNotAllowed);
+        }
+        self.cancel_reason = Some(CancelReason::TokenDelisted);
+        self.transition(MarketPhase::Cancelled, now_slot);
+        Ok(())
+    }
+
+    /// Refund every stake of a cancelled market (see `refund_participants`
+    /// and, for categorical markets, `refund_categorical_participants`).
+    pub fn refunds(
+        &self,
+        available_vault_funds: u128,
+    ) -> Result<ParticipantPayouts, PredictionError> {
+        self.require_phase(MarketPhase::Cancelled)?;
+        if self.is_categorical() {
+            refund_categorical_participants(&self.categorical, available_vault_funds)
+        } else {
+            refund_participants(&self.ledger, available_vault_funds)
+        }
+    }
+
+    /// Pay a cancelled market's refunds (see `refunds`). One-shot, like
+    /// `settle`: afterwards the market is finished and can be collected.
+    /// Engine-backed stakes are released with `refund_on_engine` instead.
+    pub fn pay_refunds(
+        &mut self,
+        available_vault_funds: u128,
+    ) -> Result<ParticipantPayouts, PredictionError> {
+        if self.refunded {
+            return Err(PredictionError::InvalidTransition);
+        }
+        self.require_no_engine_positions()?;
+        let refunds = self.refunds(available_vault_funds)?;
+        self.refunded = true;
+        Ok(refunds)
+    }
+
+    /// Settled and Cancelled admit no further transitions
+    pub fn is_terminal(&self) -> bool {
+        matches!(self.phase, MarketPhase::Settled | MarketPhase::Cancelled)
+    }
+
+    /// Settled, or Cancelled with refunds paid: nothing is left to pay out
+    pub fn is_finished(&self) -> bool {
+        self.phase == MarketPhase::Settled
+            || (self.phase == MarketPhase::Cancelled && self.refunded)
+    }
+
+    fn is_categorical(&self) -> bool {
+        matches!(self.market.rule, MarketRule::MarketCapBuckets { .. })
+    }
+
+    fn is_binary(&self) -> bool {
+        !matches!(
+            self.market.rule,
+            MarketRule::MarketCapBuckets { .. } | MarketRule::MarketCapScalar { .. }
+        )
+    }
+
+    /// Liquidity thresholds against whichever ledger holds this market's stakes
+    fn check_stake_liquidity(&self) -> Result<(), PredictionError> {
+        if self.is_categorical() {
+            check_categorical_liquidity(&self.categorical.pools, &self.market.params)
+        } else {
+            check_liquidity(self.ledger.pools, &self.market.params)
+        }
+    }
+
+    fn require_phase(&self, phase: MarketPhase) -> Result<(), PredictionError> {
+        if self.phase != phase {
+            return Err(PredictionError::InvalidTransition);
+        }
+        Ok(())
+    }
+
+    fn require_betting_open(&self, now_slot: u64) -> Result<(), PredictionError> {
+        self.require_phase(MarketPhase::Open)?;
+        self.require_slot(now_slot)?;
+        if now_slot >= self.market.close_slot {
+            return Err(PredictionError::BettingClosed);
+        }
+        if now_slot >= self.market.betting_cutoff_slot {
+            return Err(PredictionError::BettingCutoffPassed);
+        }
+        Ok(())
+    }
+
+    /// Stakes locked in engine accounts must be released by the engine
+    /// bridge, or they would stay locked once the market is finished
+    fn require_no_engine_positions(&self) -> Result<(), PredictionError> {
+        let n = self.ledger.num_participants as usize;
+        if self.account_idx[..n]
+            .iter()
+            .any(|&idx| idx != NO_ENGINE_ACCOUNT)
+        {
+            return Err(PredictionError::AccountMismatch);
+        }
+        Ok(())
+    }
+
+    fn require_slot(&self, now_slot: u64) -> Result<(), PredictionError> {
+        if now_slot < self.last_transition_slot {
+            return Err(PredictionError::SlotRegression);
+        }
+        Ok(())
+    }
+
+    fn transition(&mut self, phase: MarketPhase, now_slot: u64) {
+        // Re-disputing an already disputed market must not restart its timeout
+        if phase == MarketPhase::Disputed && self.phase != MarketPhase::Disputed {
+            self.disputed_since_slot = now_slot;
+        }
+        self.phase = phase;
+        self.last_transition_slot = now_slot;
+    }
+}
+
+/// Refund every participant's YES + NO stake at par, plus the exit fees they
+/// paid: a voided market has no winners for those fees to fund.
+///
+/// If the vault holds less than that total, each refund is capped pro-rata
+/// at `floor(stake_i * available_vault_funds / total_stake)` and the floor
+/// remainder is reported as `rounding_dust`.
+pub fn refund_participants(
+    ledger: &ParticipantLedger,
+    available_vault_funds: u128,
+) -> Result<ParticipantPayouts, PredictionError> {
+    let total_stake = ledger
+        .pools
+        .yes_capital
+        .checked_add(ledger.pools.no_capital)
+        .and_then(|x| x.checked_add(ledger.exit_fees))
+        .ok_or(PredictionError::MathOverflow)?;
+    let positions = &ledger.positions[..ledger.num_participants as usize];
+    refund_pro_rata(total_stake, available_vault_funds, positions.len(), |i| {
+        positions[i]
+            .yes_capital
+            .checked_add(positions[i].no_capital)
+            .and_then(|x| x.checked_add(positions[i].exit_fees))
+            .ok_or(PredictionError::MathOverflow)
+    })
+}
+
+/// Refund every stake of a categorical market, across all outcomes, exactly
+/// as `refund_participants` does for a binary one.
+pub fn refund_categorical_participants(
+    ledger: &CategoricalLedger,
+    available_vault_funds: u128,
+) -> Result<ParticipantPayouts, PredictionError> {
+    let n = ledger.pools.num_outcomes as usize;
+    let sum = |capital: &[u128; MAX_OUTCOMES]| {
+        capital[..n]
+            .iter()
+            .try_fold(0u128, |acc, &c| acc.checked_add(c))
+            .ok_or(PredictionError::MathOverflow)
+    };
+    let total_stake = sum(&ledger.pools.capital)?;
+    let positions = &ledger.positions[..ledger.num_participants as usize];
+    refund_pro_rata(total_stake, available_vault_funds, positions.len(), |i| {
+        sum(&positions[i].capital)
+    })
+}
+
+/// Pay `stake_of(i)` to each of `num_participants`, scaled down pro-rata
+/// when the vault holds less than `total_stake`
+fn refund_pro_rata(
+    total_stake: u128,
+    available_vault_funds: u128,
+    num_participants: usize,
+    stake_of: impl Fn(usize) -> Result<u128, PredictionError>,
+) -> Result<ParticipantPayouts, PredictionError> {
+    let refundable = min(total_stake, available_vault_funds);
+
+    let mut payouts = [0u128; MAX_PARTICIPANTS];
+    let mut total_paid = 0u128;
+
+    for (i, payout) in payouts[..num_participants].iter_mut().enumerate() {
+        let stake_i = stake_of(i)?;
+        let refund_i = if refundable == total_stake {
+            stake_i
+        } else {
+            mul_div_floor(stake_i, refundable, total_stake)?
+        };
+        *payout = refund_i;
+        total_paid = total_paid
+            .checked_add(refund_i)
+            .ok_or(PredictionError::MathOverflow)?;
+    }
+
+    let rounding_dust = refundable
+        .checked_sub(total_paid)
+        .ok_or(PredictionError::LedgerPoolsMismatch)?;
+
+    Ok(ParticipantPayouts {
+        payouts,
+        total_paid,
+        rounding_dust,
+    })
+}
+
+/// floor(a * b / d) with a 256-bit intermediate product, failing only when
+/// the quotient does not fit in u128 or `d == 0`
+fn mul_div_floor(a: u128, b: u128, d: u128) -> Result<u128, PredictionError> {
+    if d == 0 {
+        return Err(PredictionError::MathOverflow);
+    }
+    if let Some(x) = a.checked_mul(b) {
+        return Ok(x / d);
+    }
+    let (hi, lo) = widening_mul(a, b);
+    if hi >= d {
+        return Err(PredictionError::MathOverflow);
+    }
+    // Restoring division of (hi, lo) by d, one bit of lo at a time.
+    // rem < d throughout, so rem - d is exact even when the shift carries out.
+    let mut rem = hi;
+    let mut quotient = 0u128;
+    for i in (0..128).rev() {
+        let carry = rem >> 127;
+        rem = (rem << 1) | ((lo >> i) & 1);
+        quotient <<= 1;
+        if carry == 1 || rem >= d {
+            rem = rem.wrapping_sub(d);
+            quotient |= 1;
+        }
+    }
+    Ok(quotient)
+}
+
+/// Split `pool` into `(protocol_fee, creator_fee, remainder)`, flooring each
+/// fee. Params that did not come through `create_market` may carry fees above
+/// 100%, so the bound is checked here too.
+fn split_fees(pool: u128, params: &MarketParams) -> Result<(u128, u128, u128), PredictionError> {
+    if params
+        .protocol_fee_bps
+        .saturating_add(params.creator_fee_bps)
+        > 10_000
+    {
+        return Err(PredictionError::InvalidFee);
+    }
+    let protocol_fee = mul_div_floor(pool, params.protocol_fee_bps as u128, 10_000)?;
+    let creator_fee = mul_div_floor(pool, params.creator_fee_bps as u128, 10_000)?;
+    let remainder = pool
+        .checked_sub(protocol_fee)
+        .and_then(|rest| rest.checked_sub(creator_fee))
+        .ok_or(PredictionError::InvalidFee)?;
+    Ok((protocol_fee, creator_fee, remainder))
+}
+
+/// Full 256-bit product of `a * b` as `(high, low)` halves
+fn widening_mul(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a_hi, a_lo) = (a >> 64, a & MASK);
+    let (b_hi, b_lo) = (b >> 64, b & MASK);
+    let lo_lo = a_lo * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let hi_lo = a_hi * b_lo;
+    let hi_hi = a_hi * b_hi;
+    let mid = (lo_lo >> 64) + (lo_hi & MASK) + (hi_lo & MASK);
+    let low = (lo_lo & MASK) | (mid << 64);
+    let high = hi_hi + (lo_hi >> 64) + (hi_lo >> 64) + (mid >> 64);
+    (high, low)
+}
+
+// ============================================================================
+// Parimutuel odds queries (exact rationals, no settlement side effects)
+// ============================================================================
+
+/// Implied probability of `side` as `(side_capital, total_capital)`.
+/// `None` while nothing has been staked.
+pub fn implied_probability(pools: Pools, side: Outcome) -> Option<(u128, u128)> {
+    let total = pools.yes_capital.checked_add(pools.no_capital)?;
+    if total == 0 {
+        return None;
+    }
+    let side_capital = match side {
+        Outcome::Yes => pools.yes_capital,
+        Outcome::No => pools.no_capital,
+    };
+    Some((side_capital, total))
+}
+
+/// Payout multiplier for an extra `stake` on `side` if `side` wins, as
+/// `(num, den)` with `payout = stake * num / den` before flooring.
+///
+/// Assumes the stake is added to the pools and the vault covers them
+/// (`h = 1`); combine with `projected_h` for a stressed vault. Fees follow
+/// `market.params`, and exit fees already in the pot join the losing side,
+/// as in `MarketState::settle`.
+pub fn payout_multiplier(
+    market: &Market,
+    ledger: &ParticipantLedger,
+    side: Outcome,
+    stake: u128,
+) -> Result<(u128, u128), PredictionError> {
+    if stake == 0 {
+        return Err(PredictionError::ZeroStake);
+    }
+    let pools = ledger.settlement_pools(side)?;
+    let (winner_capital, loser_capital) = match side {
+        Outcome::Yes => (pools.yes_capital, pools.no_capital),
+        Outcome::No => (pools.no_capital, pools.yes_capital),
+    };
+    let winner_capital = winner_capital
+        .checked_add(stake)
+        .ok_or(PredictionError::MathOverflow)?;
+    let (_, _, profit_claim_total) = split_fees(loser_capital, &market.params)?;
+    let num = winner_capital
+        .checked_add(profit_claim_total)
+        .ok_or(PredictionError::MathOverflow)?;
+    Ok((num, winner_capital))
+}
+
+/// Coverage ratio `h` as `(h_num, h_den)` if the market settled now on
+/// `outcome` against `available_vault_funds`, exit fees included.
+pub fn projected_h(
+    market: &Market,
+    ledger: &ParticipantLedger,
+    outcome: Outcome,
+    available_vault_funds: u128,
+) -> Result<(u128, u128), PredictionError> {
+    let pools = ledger.settlement_pools(outcome)?;
+    let settlement = settle_outcome(outcome, pools, available_vault_funds, &market.params)?;
+    Ok((settlement.h_num, settlement.h_den))
+}
+
+// ============================================================================
+// Categorical (N-outcome) markets
+// ============================================================================
+
+/// Capital staked on each outcome of a categorical market
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CategoricalPools {
+    /// Indexed by outcome; entries at or past `num_outcomes` stay zero
+    pub capital: [u128; MAX_OUTCOMES],
+    pub num_outcomes: u8,
+}
+
+impl CategoricalPools {
+    pub fn new(num_outcomes: u8) -> Self {
+        Self {
+            capital: [0; MAX_OUTCOMES],
+            num_outcomes,
+        }
+    }
+
+    /// Reduce to binary pools: the `winning` outcome as YES, every other
+    /// outcome pooled as NO.
+    pub fn collapse(&self, winning: u8) -> Result<Pools, PredictionError> {
+        if winning >= self.num_outcomes {
+            return Err(PredictionError::InvalidOutcomeIndex);
+        }
+        let mut losers = 0u128;
+        for (i, &capital) in self.capital[..self.num_outcomes as usize]
+            .iter()
+            .enumerate()
+        {
+            if i != winning as usize {
+                losers = losers
+                    .checked_add(capital)
+                    .ok_or(PredictionError::MathOverflow)?;
+            }
+        }
+        Ok(Pools {
+            yes_capital: self.capital[winning as usize],
+            no_capital: losers,
+        })
+    }
+}
+
+/// One account's stakes across the outcomes of a categorical market
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CategoricalPosition {
+    pub owner: [u8; 32],
+    pub capital: [u128; MAX_OUTCOMES],
+}
+
+/// Per-account stakes of a categorical market. As with `ParticipantLedger`,
+/// `pools` always equals the column sums of `positions[..num_participants]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CategoricalLedger {
+    pub pools: CategoricalPools,
+    pub num_participants: u16,
+    pub positions: [CategoricalPosition; MAX_PARTICIPANTS],
+}
+
+impl CategoricalLedger {
+    pub fn new(num_outcomes: u8) -> Self {
+        Self {
+            pools: CategoricalPools::new(num_outcomes),
+            num_participants: 0,
+            positions: [CategoricalPosition {
+                owner: [0; 32],
+                capital: [0; MAX_OUTCOMES],
+            }; MAX_PARTICIPANTS],
+        }
+    }
+
+    /// Index of `owner`'s position, if they have staked
+    pub fn find(&self, owner: &[u8; 32]) -> Option<u16> {
+        self.positions[..self.num_participants as usize]
+            .iter()
+            .position(|p| p.owner == *owner)
+            .map(|i| i as u16)
+    }
+
+    /// Add `amount` to `owner`'s stake on `outcome`, opening a position if
+    /// needed. Returns the position index.
+    pub fn record_stake(
+        &mut self,
+        owner: [u8; 32],
+        outcome: u8,
+        amount: u128,
+    ) -> Result<u16, PredictionError> {
+        if amount == 0 {
+            return Err(PredictionError::ZeroStake);
+        }
+        if outcome >= self.pools.num_outcomes {
+            return Err(PredictionError::InvalidOutcomeIndex);
+        }
+
+        let idx = match self.find(&owner) {
+            Some(idx) => idx,
+            None => {
+                let idx = self.num_participants;
+                if idx as usize >= MAX_PARTICIPANTS {
+                    return Err(PredictionError::LedgerFull);
+                }
+                self.positions[idx as usize] = CategoricalPosition {
+                    owner,
+                    capital: [0; MAX_OUTCOMES],
+                };
+                self.num_participants += 1;
+                idx
+            }
+        };
+
+        // Check both sums before writing so a failed stake leaves no trace
+        let stake = &mut self.positions[idx as usize].capital[outcome as usize];
+        let pool = &mut self.pools.capital[outcome as usize];
+        let new_stake = stake
+            .checked_add(amount)
+            .ok_or(PredictionError::MathOverflow)?;
+        let new_pool = pool
+            .checked_add(amount)
+            .ok_or(PredictionError::MathOverflow)?;
+        *stake = new_stake;
+        *pool = new_pool;
+        Ok(idx)
+    }
+
+    /// Reduce to a binary ledger (see `CategoricalPools::collapse`), with
+    /// positions in the same order.
+    pub fn collapse(&self, winning: u8) -> Result<ParticipantLedger, PredictionError> {
+        let mut ledger = ParticipantLedger::new();
+        ledger.pools = self.pools.collapse(winning)?;
+        ledger.num_participants = self.num_participants;
+        for (binary, position) in ledger
+            .positions
+            .iter_mut()
+            .zip(&self.positions[..self.num_participants as usize])
+        {
+            let n = self.pools.num_outcomes as usize;
+            let total = position.capital[..n]
+                .iter()
+                .try_fold(0u128, |acc, &c| acc.checked_add(c))
+                .ok_or(PredictionError::MathOverflow)?;
+            let won = position.capital[winning as usize];
+            *binary = ParticipantPosition {
+                owner: position.owner,
+                yes_capital: won,
+                no_capital: total - won,
+                exit_fees: 0,
+            };
+        }
+        Ok(ledger)
+    }
+}
+
+/// Categorical counterpart of `check_liquidity`: the total stake must meet
+/// `min_total_stake` and every outcome's pool `min_side_stake`.
+///
+/// It looks at the pools only, never at the winning outcome, so whether a
+/// market settles or becomes refund-only is decided at close.
+pub fn check_categorical_liquidity(
+    pools: &CategoricalPools,
+    params: &MarketParams,
+) -> Result<(), PredictionError> {
+    let outcomes = &pools.capital[..pools.num_outcomes as usize];
+    let total = outcomes
+        .iter()
+        .try_fold(0u128, |acc, &c| acc.checked_add(c))
+        .ok_or(PredictionError::MathOverflow)?;
+    if total < params.min_total_stake || outcomes.iter().any(|&c| c < params.min_side_stake) {
+        return Err(PredictionError::InsufficientLiquidity);
+    }
+    Ok(())
+}
+
+/// Settle a categorical market whose winning outcome is already known.
+///
+/// Every losing outcome's pool funds the winners' profit: the pools are
+/// collapsed to winning outcome (YES) against the rest (NO) and settled with
+/// the binary math, so fees, `h` and the vault bound are unchanged. The
+/// returned `Settlement` is that of the collapsed market, so its `outcome`
+/// is always `Yes`; use it with `settle_participants` on the ledger from
+/// `CategoricalLedger::collapse`.
+pub fn settle_categorical(
+    winning: u8,
+    pools: &CategoricalPools,
+    available_vault_funds: u128,
+    params: &MarketParams,
+) -> Result<Settlement, PredictionError> {
+    let binary = pools.collapse(winning)?;
+    settle_outcome(Outcome::Yes, binary, available_vault_funds, params)
+}
+
+/// Resolve a categorical market from `snapshot` and settle it. Returns the
+/// winning outcome along with the settlement.
+pub fn settle_categorical_market(
+    market: &Market,
+    snapshot: &TokenSnapshot,
+    pools: &CategoricalPools,
+    available_vault_funds: u128,
+) -> Result<(u8, Settlement), PredictionError> {
+    let winning = resolve_bucket(market, snapshot)?;
+    if let MarketRule::MarketCapBuckets { num_outcomes, .. } = market.rule {
+        if pools.num_outcomes != num_outcomes {
+            return Err(PredictionError::InvalidOutcomeIndex);
+        }
+    }
+    check_categorical_liquidity(pools, &market.params)?;
+    let settlement = settle_categorical(winning, pools, available_vault_funds, &market.params)?;
+    Ok((winning, settlement))
+}
+
+// ============================================================================
+// Scalar (range) markets
+// ============================================================================
+
+/// Result of settling a scalar market.
+///
+/// The pot `P = long_capital + short_capital` is split into claims by the
+/// resolved LONG share: `long_claim = floor(P * long_num / long_den)`,
+/// `short_claim = P - long_claim`. Each side's claim up to its own capital is
+/// senior; the excess on the side that gained is profit, funded from the
+/// residual after senior capital and fees, exactly as in `settle_outcome`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScalarSettlement {
+    /// Resolved LONG share of the pot, as a rational in `[0, 1]`
+    pub long_num: u128,
+    pub long_den: u128,
+    pub long_capital_total: u128,
+    pub short_capital_total: u128,
+    pub long_claim: u128,
+    pub short_claim: u128,
+    /// Senior capital paid per side (pro-rata floored if the vault is short)
+    pub long_capital_paid: u128,
+    pub short_capital_paid: u128,
+    pub residual: u128,
+    pub protocol_fee_paid: u128,
+    pub creator_fee_paid: u128,
+    /// Gaining side's excess claim, net of (nominal) fees
+    pub profit_claim_total: u128,
+    pub h_num: u128,
+    pub h_den: u128,
+    pub long_payout_total: u128,
+    pub short_payout_total: u128,
+}
+
+/// Resolve a scalar market to the LONG share of the pot as `(num, den)`.
+pub fn resolve_scalar(
+    market: &Market,
+    snapshot: &TokenSnapshot,
+) -> Result<(u128, u128), PredictionError> {
+    let MarketRule::MarketCapScalar {
+        lower_quote_units,
+        upper_quote_units,
+    } = market.rule
+    else {
+        return Err(PredictionError::NotScalarMarket);
+    };
+    check_snapshot(market, snapshot)?;
+
+    let value = snapshot
+        .market_cap_quote_units
+        .clamp(lower_quote_units, upper_quote_units);
+    Ok((
+        value - lower_quote_units,
+        upper_quote_units - lower_quote_units,
+    ))
+}
+
+/// Settle scalar pools for a resolved LONG share `long_num / long_den`.
+///
+/// LONG stakes are `pools.yes_capital` and SHORT stakes `pools.no_capital`,
+/// so a `ParticipantLedger` records them as `Outcome::Yes` / `Outcome::No`.
+/// Fees are charged on the value moved between sides, like the loser pool of
+/// a binary market. With `long_num == long_den` this is exactly
+/// `settle_outcome(Outcome::Yes, ..)`.
+pub fn settle_scalar(
+    long_num: u128,
+    long_den: u128,
+    pools: Pools,
+    available_vault_funds: u128,
+    params: &MarketParams,
+) -> Result<ScalarSettlement, PredictionError> {
+    let (long_claim, short_claim) = scalar_claims(pools, long_num, long_den)?;
+    let pot = long_claim + short_claim;
+    // A claim on a side nobody staked has no one to pay out to
+    if (long_claim > 0 && pools.yes_capital == 0) || (short_claim > 0 && pools.no_capital == 0) {
+        return Err(PredictionError::EmptyWinnerSide);
+    }
+
+    // Senior: each side's claim up to its own capital. Junior: the excess,
+    // which only one side can have and which the other side's shortfall funds.
+    let long_senior = min(long_claim, pools.yes_capital);
+    let short_senior = min(short_claim, pools.no_capital);
+    let senior_total = long_senior + short_senior;
+    let moved = pot - senior_total;
+
+    let (protocol_fee, creator_fee, profit_claim_total) = split_fees(moved, params)?;
+
+    let (long_capital_paid, short_capital_paid) = if available_vault_funds >= senior_total {
+        (long_senior, short_senior)
+    } else {
+        (
+            mul_div_floor(long_senior, available_vault_funds, senior_total)?,
+            mul_div_floor(short_senior, available_vault_funds, senior_total)?,
+        )
+    };
+    let residual = available_vault_funds.saturating_sub(senior_total);
+
+    let protocol_fee_paid = min(residual, protocol_fee);
+    let creator_fee_paid = min(residual - protocol_fee_paid, creator_fee);
+    let funded_profit = min(
+        residual - protocol_fee_paid - creator_fee_paid,
+        profit_claim_total,
+    );
+    let (h_num, h_den) = if profit_claim_total == 0 {
+        (1, 1)
+    } else {
+        (funded_profit, profit_claim_total)
+    };
+
+    let (long_profit, short_profit) = if long_claim > pools.yes_capital {
+        (funded_profit, 0)
+    } else {
+        (0, funded_profit)
+    };
+    Ok(ScalarSettlement {
+        long_num,
+        long_den,
+        long_capital_total: pools.yes_capital,
+        short_capital_total: pools.no_capital,
+        long_claim,
+        short_claim,
+        long_capital_paid,
+        short_capital_paid,
+        residual,
+        protocol_fee_paid,
+        creator_fee_paid,
+        profit_claim_total,
+        h_num,
+        h_den,
+        long_payout_total: long_capital_paid + long_profit,
+        short_payout_total: short_capital_paid + short_profit,
+    })
+}
+
+/// Split the pot into `(long_claim, short_claim)` by the LONG share
+fn scalar_claims(
+    pools: Pools,
+    long_num: u128,
+    long_den: u128,
+) -> Result<(u128, u128), PredictionError> {
+    if long_den == 0 || long_num > long_den {
+        return Err(PredictionError::InvalidScalarRange);
+    }
+    let pot = pools
+        .yes_capital
+        .checked_add(pools.no_capital)
+        .ok_or(PredictionError::MathOverflow)?;
+    let long_claim = mul_div_floor(pot, long_num, long_den)?;
+    Ok((long_claim, pot - long_claim))
+}
+
+/// Resolve a scalar market from `snapshot` and settle it.
+pub fn settle_scalar_market(
+    market: &Market,
+    snapshot: &TokenSnapshot,
+    pools: Pools,
+    available_vault_funds: u128,
+) -> Result<ScalarSettlement, PredictionError> {
+    check_liquidity(pools, &market.params)?;
+    let (long_num, long_den) = resolve_scalar(market, snapshot)?;
+    settle_scalar(
+        long_num,
+        long_den,
+        pools,
+        available_vault_funds,
+        &market.params,
+    )
+}
+
+/// Split a `ScalarSettlement` across `ledger` (LONG = YES, SHORT = NO).
+///
+/// Each side's payout total is shared pro-rata by stake on that side,
+/// `floor(stake_i * side_payout_total / side_capital_total)`; the floor
+/// remainder is returned as `rounding_dust`.
+pub fn settle_scalar_participants(
+    ledger: &ParticipantLedger,
+    settlement: &ScalarSettlement,
+) -> Result<ParticipantPayouts, PredictionError> {
+    if ledger.pools.yes_capital != settlement.long_capital_total
+        || ledger.pools.no_capital != settlement.short_capital_total
+    {
+        return Err(PredictionError::LedgerPoolsMismatch);
+    }
+
+    let mut payouts = [0u128; MAX_PARTICIPANTS];
+    let mut total_paid = 0u128;
+    for (payout, position) in payouts
+        .iter_mut()
+        .zip(&ledger.positions[..ledger.num_participants as usize])
+    {
+        let mut amount = 0u128;
+        if position.yes_capital > 0 {
+            amount += mul_div_floor(
+                position.yes_capital,
+                settlement.long_payout_total,
+                settlement.long_capital_total,
+            )?;
+        }
+        if position.no_capital > 0 {
+            amount += mul_div_floor(
+                position.no_capital,
+                settlement.short_payout_total,
+                settlement.short_capital_total,
+            )?;
+        }
+        *payout = amount;
+        total_paid += amount;
+    }
+
+    let payout_total = settlement.long_payout_total + settlement.short_payout_total;
+    Ok(ParticipantPayouts {
+        payouts,
+        total_paid,
+        rounding_dust: payout_total - total_paid,
+    })
+}
+
+// ============================================================================
+// RiskEngine bridge: stakes as capital, winnings as warmup-gated PnL
+// ============================================================================
+
+/// `MarketState::account_idx` entry for a position with no engine account
+pub const NO_ENGINE_ACCOUNT: u16 = u16::MAX;
+
+/// Engine state a deposit can touch, restored if the stake it funds fails
+struct DepositCheckpoint {
+    account: Account,
+    vault: U128,
+    c_tot: U128,
+    pnl_pos_tot: U128,
+    insurance_fund: InsuranceFund,
+    current_slot: u64,
+}
+
+impl DepositCheckpoint {
+    fn save(engine: &RiskEngine, idx: u16) -> Self {
+        Self {
+            account: engine.accounts[idx as usize],
+            vault: engine.vault,
+            c_tot: engine.c_tot,
+            pnl_pos_tot: engine.pnl_pos_tot,
+            insurance_fund: engine.insurance_fund,
+            current_slot: engine.current_slot,
+        }
+    }
+
+    fn restore(self, engine: &mut RiskEngine, idx: u16) {
+        engine.accounts[idx as usize] = self.account;
+        engine.vault = self.vault;
+        engine.c_tot = self.c_tot;
+        engine.pnl_pos_tot = self.pnl_pos_tot;
+        engine.insurance_fund = self.insurance_fund;
+        engine.current_slot = self.current_slot;
+    }
+}
+
+impl MarketState {
+    /// Stake by depositing `amount` into engine account `idx`.
+    ///
+    /// Warmed PnL and maintenance fees due are settled against the account
+    /// first. The capital the deposit then adds (`amount`, less any fee debt
+    /// the account's free capital could not cover) is recorded as the stake,
+    /// never more than `amount`, and locked in the account (see
+    /// `RiskEngine::lock_capital`) until `settle_on_engine` or
+    /// `refund_on_engine` releases it, so it can be neither withdrawn nor
+    /// traded against. The position is keyed by the account's `owner`, and
+    /// the account must be flat: no open position and no negative PnL. If
+    /// the stake cannot be recorded, the deposit is rolled back.
+    pub fn stake_on_engine(
+        &mut self,
+        engine: &mut RiskEngine,
+        idx: u16,
+        side: Outcome,
+        amount: u128,
+        now_slot: u64,
+    ) -> Result<u16, PredictionError> {
+        self.stake_on_engine_with_sink(&mut NoOpSink, engine, idx, side, amount, now_slot)
+    }
+
+    /// `stake_on_engine`, reporting the deposit and any fees it pays to `sink`
+    pub fn stake_on_engine_with_sink(
+        &mut self,
+        sink: &mut dyn EventSink,
+        engine: &mut RiskEngine,
+        idx: u16,
+        side: Outcome,
+        amount: u128,
+        now_slot: u64,
+    ) -> Result<u16, PredictionError> {
+        if !self.is_binary() {
+            return Err(PredictionError::NotBinaryMarket);
+        }
+        self.require_betting_open(now_slot)?;
+        if amount == 0 {
+            return Err(PredictionError::ZeroStake);
+        }
+        if now_slot < engine.current_slot {
+            return Err(PredictionError::SlotRegression);
+        }
+        if !engine.is_used(idx as usize) {
+            return Err(PredictionError::Engine(RiskError::AccountNotFound));
+        }
+        let account = &engine.accounts[idx as usize];
+        if !account.position_size.is_zero() || account.pnl.is_negative() {
+            return Err(PredictionError::AccountNotFlat);
+        }
+        let owner = account.owner;
+        if let Some(pos) = self.ledger.find(&owner) {
+            if self.account_idx[pos as usize] != idx {
+                return Err(PredictionError::AccountMismatch);
+            }
+        }
+
+        let checkpoint = DepositCheckpoint::save(engine, idx);
+        // Warmed PnL and fees due would otherwise land in the deposit's
+        // capital delta; settle them first so the stake is at most `amount`.
+        // The account is flat, so the oracle price is never read.
+        engine.current_slot = now_slot;
+        let result = engine
+            .settle_warmup_to_capital_with_sink(sink, idx)
+            .and_then(|()| engine.settle_maintenance_fee_with_sink(sink, idx, now_slot, 0))
+            .map_err(PredictionError::Engine)
+            .and_then(|_| {
+                let capital_before = engine.accounts[idx as usize].capital.get();
+                engine
+                    .deposit_with_sink(sink, idx, amount, now_slot)
+                    .map_err(PredictionError::Engine)?;
+                // Only fee debt the account could not pay comes off `amount`
+                let staked = engine.accounts[idx as usize]
+                    .capital
+                    .get()
+                    .saturating_sub(capital_before);
+                engine
+                    .lock_capital(idx, staked)
+                    .map_err(PredictionError::Engine)?;
+                self.stake(owner, side, staked, now_slot)
+            });
+        match result {
+            Ok(pos) => {
+                self.account_idx[pos as usize] = idx;
+                Ok(pos)
+            }
+            Err(e) => {
+                checkpoint.restore(engine, idx);
+                Err(e)
+            }
+        }
+    }
+
+    /// Resolved → Settled against the engine that holds the stakes.
+    ///
+    /// - Every stake is unlocked, and each loser's stake is debited from its
+    ///   account's capital; the debited total is `loser_capital_total`.
+    /// - Fees come off the debited total: the protocol fee goes to the
+    ///   insurance fund, the creator fee to `creator_idx`'s capital.
+    /// - Each winner is credited `floor(stake_i * profit_claim_total / W)` as
+    ///   junior `pnl`, which converts to capital through warmup at the
+    ///   engine's `haircut_ratio()`. Floor dust goes to the insurance fund.
+    ///
+    /// Every check runs before the engine is touched. Winner capital never
+    /// leaves its accounts, and the vault is untouched, so `C_tot + I` can
+    /// only shrink. The reported `h` is the engine's ratio after crediting;
+    /// `winner_profit_paid` is the profit it currently backs.
+    pub fn settle_on_engine(
+        &mut self,
+        engine: &mut RiskEngine,
+        creator_idx: u16,
+        now_slot: u64,
+    ) -> Result<Settlement, PredictionError> {
+        self.settle_on_engine_with_sink(&mut NoOpSink, engine, creator_idx, now_slot)
+    }
+
+    /// `settle_on_engine`, reporting each loser debit, winner credit and fee
+    /// to `sink`
+    pub fn settle_on_engine_with_sink(
+        &mut self,
+        sink: &mut dyn EventSink,
+        engine: &mut RiskEngine,
+        creator_idx: u16,
+        now_slot: u64,
+    ) -> Result<Settlement, PredictionError> {
+        self.require_phase(MarketPhase::Resolved)?;
+        self.require_slot(now_slot)?;
+        if now_slot < engine.current_slot {
+            return Err(PredictionError::SlotRegression);
+        }
+        let outcome = match self.outcome {
+            Some(ResolvedOutcome::Binary(outcome)) => outcome,
+            Some(_) => return Err(PredictionError::NotBinaryMarket),
+            None => return Err(PredictionError::InvalidTransition),
+        };
+        self.require_engine_accounts(engine)?;
+        if !engine.is_used(creator_idx as usize) {
+            return Err(PredictionError::Engine(RiskError::AccountNotFound));
+        }
+        check_liquidity(self.ledger.pools, &self.market.params)?;
+        let (winner_capital_total, loser_capital_total) = match outcome {
+            Outcome::Yes => (self.ledger.pools.yes_capital, self.ledger.pools.no_capital),
+            Outcome::No => (self.ledger.pools.no_capital, self.ledger.pools.yes_capital),
+        };
+        if winner_capital_total == 0 {
+            return Err(PredictionError::EmptyWinnerSide);
+        }
+
+        let (protocol_fee, creator_fee, profit_claim_total) =
+            split_fees(loser_capital_total, &self.market.params)?;
+        let n = self.ledger.num_participants as usize;
+        let mut claimed = 0u128;
+        for (position, &idx) in self.ledger.positions[..n]
+            .iter()
+            .zip(&self.account_idx[..n])
+        {
+            let account = &engine.accounts[idx as usize];
+            if !account.position_size.is_zero() {
+                return Err(PredictionError::AccountNotFlat);
+            }
+            let (stake, winnings) = match outcome {
+                Outcome::Yes => (position.yes_capital, position.no_capital),
+                Outcome::No => (position.no_capital, position.yes_capital),
+            };
+            let claim = mul_div_floor(stake, profit_claim_total, winner_capital_total)?;
+            i128::try_from(claim)
+                .ok()
+                .and_then(|c| account.pnl.get().checked_add(c))
+                .ok_or(PredictionError::MathOverflow)?;
+            claimed = claimed
+                .checked_add(claim)
+                .ok_or(PredictionError::MathOverflow)?;
+            // The locked stake is still in capital, so the debit below is exact
+            let locked = stake
+                .checked_add(winnings)
+                .ok_or(PredictionError::MathOverflow)?;
+            if account.locked_capital.get() < locked {
+                return Err(PredictionError::Engine(RiskError::InsufficientBalance));
+            }
+        }
+        let to_insurance = protocol_fee
+            .checked_add(profit_claim_total - claimed)
+            .ok_or(PredictionError::MathOverflow)?;
+        let insurance_balance = engine
+            .insurance_fund
+            .balance
+            .get()
+            .checked_add(to_insurance)
+            .ok_or(PredictionError::MathOverflow)?;
+        let fee_revenue = engine
+            .insurance_fund
+            .fee_revenue
+            .get()
+            .checked_add(protocol_fee)
+            .ok_or(PredictionError::MathOverflow)?;
+        engine.accounts[creator_idx as usize]
+            .capital
+            .get()
+            .checked_add(creator_fee)
+            .ok_or(PredictionError::MathOverflow)?;
+
+        engine.current_slot = now_slot;
+
+        // Losing stakes leave capital first, so their value sits in the residual
+        for (position, &idx) in self.ledger.positions[..n]
+            .iter()
+            .zip(&self.account_idx[..n])
+        {
+            let (stake, lost) = match outcome {
+                Outcome::Yes => (position.yes_capital, position.no_capital),
+                Outcome::No => (position.no_capital, position.yes_capital),
+            };
+            engine
+                .unlock_capital(idx, stake + lost)
+                .map_err(PredictionError::Engine)?;
+            if lost == 0 {
+                continue;
+            }
+            let capital = engine.accounts[idx as usize].capital.get();
+            engine.set_capital(idx as usize, capital - lost);
+            sink.on_event(&EngineEvent::StakeLost {
+                idx,
+                market_id: self.market.market_id,
+                amount: lost,
+                capital_before: capital,
+                capital_after: capital - lost,
+            });
+        }
+
+        for (position, &idx) in self.ledger.positions[..n]
+            .iter()
+            .zip(&self.account_idx[..n])
+        {
+            let stake = match outcome {
+                Outcome::Yes => position.yes_capital,
+                Outcome::No => position.no_capital,
+            };
+            let claim = mul_div_floor(stake, profit_claim_total, winner_capital_total)?;
+            if claim == 0 {
+                continue;
+            }
+            // Checked above
+            let pnl_before = engine.accounts[idx as usize].pnl.get();
+            let pnl = pnl_before + claim as i128;
+            engine.set_pnl(idx as usize, pnl);
+            engine
+                .update_warmup_slope(idx)
+                .map_err(PredictionError::Engine)?;
+            sink.on_event(&EngineEvent::WinningsCredited {
+                idx,
+                market_id: self.market.market_id,
+                amount: claim,
+                pnl_before,
+                pnl_after: pnl,
+            });
+        }
+
+        engine.insurance_fund.balance = U128::new(insurance_balance);
+        engine.insurance_fund.fee_revenue = U128::new(fee_revenue);
+        if to_insurance > 0 {
+            sink.on_event(&EngineEvent::ProtocolFeeCollected {
+                market_id: self.market.market_id,
+                amount: protocol_fee,
+                rounding_dust: profit_claim_total - claimed,
+            });
+        }
+        let creator_before = engine.accounts[creator_idx as usize].capital.get();
+        let creator_capital = creator_before + creator_fee;
+        engine.set_capital(creator_idx as usize, creator_capital);
+        if creator_fee > 0 {
+            sink.on_event(&EngineEvent::CreatorFeePaid {
+                idx: creator_idx,
+                market_id: self.market.market_id,
+                amount: creator_fee,
+                capital_before: creator_before,
+                capital_after: creator_capital,
+            });
+        }
+
+        let (h_num, h_den) = engine.haircut_ratio();
+        let (_, residual) = RiskEngine::signed_residual(
+            engine.vault.get(),
+            engine.c_tot.get(),
+            engine.insurance_fund.balance.get(),
+        );
+        let winner_profit_paid = mul_div_floor(claimed, h_num, h_den)?;
+        let settlement = Settlement {
+            outcome,
+            winner_capital_total,
+            loser_capital_total,
+            winner_capital_paid: winner_capital_total,
+            residual,
+            protocol_fee_paid: protocol_fee,
+            creator_fee_paid: creator_fee,
+            profit_claim_total,
+            h_num,
+            h_den,
+            winner_profit_paid,
+            winner_payout_total: winner_capital_total + winner_profit_paid,
+        };
+        self.settlement = Some(settlement);
+        self.transition(MarketPhase::Settled, now_slot);
+        Ok(settlement)
+    }
+
+    /// Release the stakes of a cancelled engine-backed market. Stakes never
+    /// left capital, so unlocking them is the whole refund. One-shot, like
+    /// `pay_refunds`: afterwards the market is finished and can be collected.
+    pub fn refund_on_engine(&mut self, engine: &mut RiskEngine) -> Result<(), PredictionError> {
+        self.require_phase(MarketPhase::Cancelled)?;
+        if self.refunded {
+            return Err(PredictionError::InvalidTransition);
+        }
+        self.require_engine_accounts(engine)?;
+        let n = self.ledger.num_participants as usize;
+        for (position, &idx) in self.ledger.positions[..n]
+            .iter()
+            .zip(&self.account_idx[..n])
+        {
+            let stake = position
+                .yes_capital
+                .checked_add(position.no_capital)
+                .ok_or(PredictionError::MathOverflow)?;
+            if engine.accounts[idx as usize].locked_capital.get() < stake {
+                return Err(PredictionError::Engine(RiskError::InsufficientBalance));
+            }
+        }
+        for (position, &idx) in self.ledger.positions[..n]
+            .iter()
+            .zip(&self.account_idx[..n])
+        {
+            engine
+                .unlock_capital(idx, position.yes_capital + position.no_capital)
+                .map_err(PredictionError::Engine)?;
+        }
+        self.refunded = true;
+        Ok(())
+    }
+
+    /// Every position is backed by a live engine account
+    fn require_engine_accounts(&self, engine: &RiskEngine) -> Result<(), PredictionError> {
+        let n = self.ledger.num_participants as usize;
+        for &idx in &self.account_idx[..n] {
+            if idx == NO_ENGINE_ACCOUNT {
+                return Err(PredictionError::PositionNotEngineBacked);
+            }
+            if !engine.is_used(idx as usize) {
+                return Err(PredictionError::Engine(RiskError::AccountNotFound));
+            }
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Market registry: fixed-capacity slab, same layout as RiskEngine's accounts
+// ============================================================================
+
+/// Many markets in one fixed-size, no_std structure.
+///
+/// Slots are tracked by an occupancy bitmap and a freelist exactly like
+/// `RiskEngine` accounts. Market ids come from `next_market_id`, increase
+/// monotonically and are never reused, even after a slot is collected.
+///
+/// `M` is the number of market slots and `MW = (M + 63) / 64` the number of
+/// bitmap words, defaulting to the feature-selected `MAX_MARKETS` as
+/// `RiskEngine<N, W>` does; `MarketRegistry::<8, 1>::new_sized(policy)`
+/// builds a smaller one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MarketRegistry<const M: usize = MAX_MARKETS, const MW: usize = MARKET_BITMAP_WORDS> {
+    /// Occupancy bitmap (bit set = slot holds a market)
+    pub used: [u64; MW],
+    /// O(1) count of occupied slots
+    pub num_used_markets: u16,
+    /// Next market id to assign
+    pub next_market_id: u64,
+    /// Head of the freelist (`u16::MAX` when full)
+    pub free_head: u16,
+    pub next_free: [u16; M],
+    /// Eligibility rules for every market created here
+    pub policy: EligibilityPolicy,
+    /// Slot where the next `prediction_crank` resumes
+    pub crank_cursor: u16,
+    pub markets: [MarketState; M],
+}
+
+/// Result of one `prediction_crank` call
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PredictionCrankOutcome {
+    /// Number of registered markets visited (at most `budget`)
+    pub markets_processed: u16,
+    /// Markets moved Open → Closed
+    pub num_closed: u16,
+    /// Markets cancelled at close for missing the liquidity thresholds
+    pub num_liquidity_cancelled: u16,
+    /// Disputed markets cancelled with `CancelReason::DisputeTimeout`
+    pub num_dispute_cancelled: u16,
+    /// Index where this crank stopped (next crank continues from here)
+    pub last_cursor: u16,
+    /// Whether this crank reached the last slot (cursor wrapped to 0)
+    pub sweep_complete: bool,
+}
+
+fn empty_market_state() -> MarketState {
+    MarketState::new(Market {
+        market_id: 0,
+        token_mint: [0; 32],
+        created_slot: 0,
+        close_slot: 0,
+        betting_cutoff_slot: 0,
+        rule: MarketRule::MarketCapAtCloseAtLeast {
+            target_quote_units: 0,
+        },
+        params: MarketParams {
+            max_snapshot_delay_slots: 0,
+            agreement_policy: AgreementPolicy::Exact,
+            dispute_timeout_slots: 0,
+            protocol_fee_bps: 0,
+            creator_fee_bps: 0,
+            betting_cutoff_slots: 0,
+            exit_fee_bps: 0,
+            min_total_stake: 0,
+            min_side_stake: 0,
+        },
+    })
+}
+
+impl MarketRegistry {
+    /// Create an empty registry (stack-allocates the full struct, several MB
+    /// at the production `MAX_MARKETS`; use `init_in_place` on heap or
+    /// account memory instead).
+    pub fn new(policy: EligibilityPolicy) -> Self {
+        Self::new_sized(policy)
+    }
+}
+
+impl<const M: usize, const MW: usize> MarketRegistry<M, MW> {
+    /// Number of market slots
+    pub const CAPACITY: usize = M;
+
+    /// Evaluated by the constructors: rejects unusable slab sizes at compile time
+    const SHAPE_OK: () = assert!(
+        M > 0 && M < u16::MAX as usize && MW == M.div_ceil(64),
+        "MarketRegistry<M, MW> needs 0 < M < u16::MAX and MW = (M + 63) / 64"
+    );
+
+    /// Create a registry with an explicit capacity. See `new` for the stack caveat.
+    pub fn new_sized(policy: EligibilityPolicy) -> Self {
+        let () = Self::SHAPE_OK;
+        let mut registry = Self {
+            used: [0; MW],
+            num_used_markets: 0,
+            next_market_id: 0,
+            free_head: 0,
+            next_free: [0; M],
+            policy,
+            crank_cursor: 0,
+            markets: [empty_market_state(); M],
+        };
+        registry.init_freelist();
+        registry
+    }
+
+    /// Initialize a registry in place, without building one on the stack.
+    ///
+    /// Unlike `RiskEngine::init_in_place`, the memory need not be zeroed:
+    /// every field is overwritten, one market slot at a time, so this also
+    /// resets a registry that is in use. Ids restart at 0.
+    pub fn init_in_place(&mut self, policy: EligibilityPolicy) {
+        let () = Self::SHAPE_OK;
+        self.used = [0; MW];
+        self.num_used_markets = 0;
+        self.next_market_id = 0;
+        self.free_head = 0;
+        self.policy = policy;
+        self.crank_cursor = 0;
+        for market in self.markets.iter_mut() {
+            *market = empty_market_state();
+        }
+        self.init_freelist();
+    }
+
+    /// Freelist: 0 -> 1 -> ... -> M-1 -> NONE
+    fn init_freelist(&mut self) {
+        for i in 0..M - 1 {
+            self.next_free[i] = (i + 1) as u16;
+        }
+        self.next_free[M - 1] = u16::MAX;
+    }
+
+    /// Validate and register a new market (see `create_market`) under the
+    /// next id. A rejected market consumes neither an id nor a slot.
+    pub fn create_market(
+        &mut self,
+        token: TokenStatus,
+        created_slot: u64,
+        close_slot: u64,
+        rule: MarketRule,
+        params: MarketParams,
+    ) -> Result<u64, PredictionError> {
+        if self.free_head == u16::MAX {
+            return Err(PredictionError::RegistryFull);
+        }
+        let market_id = self.next_market_id;
+        let market = create_market(
+            token,
+            &self.policy,
+            market_id,
+            created_slot,
+            close_slot,
+            rule,
+            params,
+        )?;
+
+        let idx = self.free_head as usize;
+        self.free_head = self.next_free[idx];
+        self.set_used(idx);
+        self.num_used_markets += 1;
+        self.next_market_id += 1;
+        self.markets[idx] = MarketState::new(market);
+        Ok(market_id)
+    }
+
+    /// Slot holding `market_id`, if registered
+    pub fn find(&self, market_id: u64) -> Option<u16> {
+        (0..M)
+            .find(|&idx| self.is_used(idx) && self.markets[idx].market.market_id == market_id)
+            .map(|idx| idx as u16)
+    }
+
+    pub fn get(&self, market_id: u64) -> Result<&MarketState, PredictionError> {
+        let idx = self
+            .find(market_id)
+            .ok_or(PredictionError::MarketNotFound)?;
+        Ok(&self.markets[idx as usize])
+    }
+
+    pub fn get_mut(&mut self, market_id: u64) -> Result<&mut MarketState, PredictionError> {
+        let idx = self
+            .find(market_id)
+            .ok_or(PredictionError::MarketNotFound)?;
+        Ok(&mut self.markets[idx as usize])
+    }
+
+    /// Close a registered market (see `MarketState::close`)
+    pub fn close(&mut self, market_id: u64, now_slot: u64) -> Result<(), PredictionError> {
+        self.get_mut(market_id)?.close(now_slot)
+    }
+
+    /// Advance expired markets without anyone having to find them.
+    ///
+    /// Walks occupied slots from `crank_cursor` up to the end of the slab,
+    /// visiting at most `budget` markets, and:
+    /// - closes Open markets with `now_slot >= close_slot` (which cancels
+    ///   those below the liquidity thresholds, see `MarketState::close`)
+    /// - cancels Disputed markets that have waited `dispute_timeout_slots`
+    ///
+    /// Markets whose last transition is after `now_slot` are left alone.
+    pub fn prediction_crank(&mut self, now_slot: u64, budget: u16) -> PredictionCrankOutcome {
+        let mut outcome = PredictionCrankOutcome::default();
+        let mut idx = self.crank_cursor as usize % M;
+
+        while outcome.markets_processed < budget {
+            if self.is_used(idx) {
+                outcome.markets_processed += 1;
+                let state = &mut self.markets[idx];
+                if state.phase == MarketPhase::Open
+                    && now_slot >= state.market.close_slot
+                    && state.close(now_slot).is_ok()
+                {
+                    if state.phase == MarketPhase::Cancelled {
+                        outcome.num_liquidity_cancelled += 1;
+                    } else {
+                        outcome.num_closed += 1;
+                    }
+                } else if state.phase == MarketPhase::Disputed
+                    && state.cancel(CancelReason::DisputeTimeout, now_slot).is_ok()
+                {
+                    outcome.num_dispute_cancelled += 1;
+                }
+            }
+
+            // Advance to next index; a sweep ends when it wraps to slot 0
+            idx += 1;
+            if idx == M {
+                idx = 0;
+                outcome.sweep_complete = true;
+                break;
+            }
+        }
+
+        self.crank_cursor = idx as u16;
+        outcome.last_cursor = self.crank_cursor;
+        outcome
+    }
+
+    /// Free the slot of every finished market: `Settled`, or `Cancelled`
+    /// with its refunds paid (`MarketState::pay_refunds`). Returns the
+    /// number freed.
+    ///
+    /// Collected markets can no longer be looked up, so participant payouts
+    /// must be computed from their ledger before collection.
+    pub fn garbage_collect_finished(&mut self) -> u32 {
+        let mut freed = 0;
+        for idx in 0..M {
+            if self.is_used(idx) && self.markets[idx].is_finished() {
+                self.free_slot(idx);
+                freed += 1;
+            }
+        }
+        freed
+    }
+
+    pub fn is_used(&self, idx: usize) -> bool {
+        if idx >= M {
+            return false;
+        }
+        (self.used[idx >> 6] >> (idx & 63)) & 1 == 1
+    }
+
+    fn set_used(&mut self, idx: usize) {
+        self.used[idx >> 6] |= 1u64 << (idx & 63);
+    }
+
+    fn free_slot(&mut self, idx: usize) {
+        self.markets[idx] = empty_market_state();
+        self.used[idx >> 6] &= !(1u64 << (idx & 63));
+        self.next_free[idx] = self.free_head;
+        self.free_head = idx as u16;
+        self.num_used_markets -= 1;
+    }
+}
+
+impl Default for MarketRegistry {
+    fn default() -> Self {
+        Self::new(EligibilityPolicy::default())
+    }
+}
+
+// ============================================================================
+// SHA-256 (FIPS 180-4), no_std and allocation-free, for audit hashes
+// ============================================================================
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Streaming SHA-256 state
+struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Sha256 {
+    fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total_len = self.total_len.wrapping_add(data.len() as u64);
+        while !data.is_empty() {
+            let take = min(64 - self.block_len, data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    fn finalize(mut self) -> [u8; 32] {
+        let bit_len = self.total_len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+
+        let mut out = [0u8; 32];
+        for (chunk, word) in out.chunks_exact_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+
+    fn mint(byte: u8) -> [u8; 32] {
+        [byte; 32]
+    }
+
+    /// Migrated PumpFun token with no liquidity or market-cap figures
+    fn migrated_token(mint: [u8; 32]) -> TokenStatus {
+        TokenStatus {
+            mint,
+            migrated_to_pumpswap: true,
+            launch_source: LaunchSource::PumpFun,
+            migration_slot: 0,
+            liquidity_quote_units: 0,
+            market_cap_quote_units: 0,
+            delisted: false,
+        }
+    }
+
+    fn delisted_token(mint: [u8; 32]) -> TokenStatus {
+        TokenStatus {
+            delisted: true,
+            ..migrated_token(mint)
+        }
+    }
+
+    fn test_policy() -> EligibilityPolicy {
+        EligibilityPolicy::default()
+    }
+
+    fn test_params() -> MarketParams {
+        MarketParams {
+            max_snapshot_delay_slots: 100,
+            agreement_policy: AgreementPolicy::Exact,
+            dispute_timeout_slots: 50,
+            protocol_fee_bps: 0,
+            creator_fee_bps: 0,
+            betting_cutoff_slots: 1,
+            exit_fee_bps: 0,
+            min_total_stake: 0,
+            min_side_stake: 0,
+        }
+    }
+
+    fn owner(i: usize) -> [u8; 32] {
+        let mut key = [0xAA; 32];
+        key[..8].copy_from_slice(&(i as u64).to_le_bytes());
+        key
+    }
+
+    #[test]
+    fn rejects_non_migrated_token_on_market_creation() {
+        let token = TokenStatus {
+            migrated_to_pumpswap: false,
+            ..migrated_token(mint(7))
+        };
+
+        let err = create_market(
+            token,
+            &test_policy(),
+            1,
+            100,
+            200,
+            MarketRule::MarketCapAtCloseAtLeast {
+                target_quote_units: 1_000_000,
+            },
+            test_params(),
+        )
+        .unwrap_err();
+
+        assert_eq!(err, PredictionError::TokenNotMigrated);
+    }
+
+    #[test]
+    fn full_coverage_settlement_pays_full_profit() {
+        let token = migrated_token(mint(1));
+        let market = create_market(
+            token,
+            &test_policy(),
+            42,
+            100,
+            200,
+            MarketRule::MarketCapAtCloseAtLeast {
+                target_quote_units: 1_000_000,
+            },
+            test_params(),
+        )
+        .unwrap();
+        let snapshot = TokenSnapshot {
+            mint: mint(1),
+            migrated_to_pumpswap: true,
+            market_cap_quote_units: 2_000_000,
+            price_e6: 0,
+            volume: None,
+            migration_slot: None,
+            snapshot_slot: 201,
+        };
+        let pools = Pools {
+            yes_capital: 100,
+            no_capital: 40,
+        };
+
+        let settlement = settle_market(&market, &snapshot, pools, 140).unwrap();
+        assert_eq!(settlement.outcome, Outcome::Yes);
+        assert_eq!(settlement.h_num, 40);
+        assert_eq!(settlement.h_den, 40);
+        assert_eq!(settlement.winner_payout_total, 140);
+    }
+
+    #[test]
+    fn stressed_settlement_applies_haircut() {
+        let token = migrated_token(mint(2));
+        let market = create_market(
+            token,
+            &test_policy(),
+            7,
+            10,
+            20,
+            MarketRule::MarketCapAtCloseAtLeast {
+                target_quote_units: 5_000_000,
+            },
+            test_params(),
+        )
+        .unwrap();
+        let snapshot = TokenSnapshot {
+            mint: mint(2),
+            migrated_to_pumpswap: true,
+            market_cap_quote_units: 6_000_000,
+            price_e6: 0,
+            volume: None,
+            migration_slot: None,
+            snapshot_slot: 21,
+        };
+        let pools = Pools {
+            yes_capital: 100,
+            no_capital: 80,
+        };
+
+        // Only 130 units are available, so winner profit is partially funded.
+        let settlement = settle_market(&market, &snapshot, pools, 130).unwrap();
+        assert_eq!(settlement.h_num, 30);
+        assert_eq!(settlement.h_den, 80);
+        assert_eq!(settlement.winner_profit_paid, 30);
+        assert_eq!(settlement.winner_payout_total, 130);
+    }
+
+    #[test]
+    fn never_pays_more_than_available_vault() {
+        let token = migrated_token(mint(3));
+        let market = create_market(
+            token,
+            &test_policy(),
+            99,
+            5,
+            15,
+            MarketRule::MarketCapAtCloseAtLeast {
+                target_quote_units: 10_000,
+            },
+            test_params(),
+        )
+        .unwrap();
+        let snapshot = TokenSnapshot {
+            mint: mint(3),
+            migrated_to_pumpswap: true,
+            market_cap_quote_units: 100,
+            price_e6: 0,
+            volume: None,
+            migration_slot: None,
+            snapshot_slot: 16,
+        };
+        let pools = Pools {
+            yes_capital: 70,
+            no_capital: 30,
+        };
+        let available = 20;
+
+        let settlement = settle_market(&market, &snapshot, pools, available).unwrap();
+        assert!(settlement.winner_payout_total <= available);
+    }
+
+    #[test]
+    fn underfunded_vault_caps_winner_capital() {
+        let market = price_market(1_000_000);
+        let snapshot = price_snapshot(1_000_000);
+        let pools = Pools {
+            yes_capital: 100,
+            no_capital: 50,
+        };
+
+        // Vault short of winner capital: no residual, no profit, capital capped
+        let settlement = settle_market(&market, &snapshot, pools, 60).unwrap();
+        assert_eq!(settlement.outcome, Outcome::Yes);
+        assert_eq!(settlement.winner_capital_total, 100);
+        assert_eq!(settlement.winner_capital_paid, 60);
+        assert_eq!(settlement.residual, 0);
+        assert_eq!(settlement.winner_profit_paid, 0);
+        assert_eq!(settlement.winner_payout_total, 60);
+
+        // Vault exactly covering winner capital returns all of it
+        let settlement = settle_market(&market, &snapshot, pools, 100).unwrap();
+        assert_eq!(settlement.winner_capital_paid, 100);
+        assert_eq!(settlement.winner_payout_total, 100);
+    }
+
+    fn price_market(target_price_e6: u64) -> Market {
+        let token = migrated_token(mint(4));
+        create_market(
+            token,
+            &test_policy(),
+            5,
+            100,
+            200,
+            MarketRule::PriceAtCloseAtLeast { target_price_e6 },
+            test_params(),
+        )
+        .unwrap()
+    }
+
+    fn price_snapshot(price_e6: u64) -> TokenSnapshot {
+        TokenSnapshot {
+            mint: mint(4),
+            migrated_to_pumpswap: true,
+            market_cap_quote_units: 0,
+            price_e6,
+            volume: None,
+            migration_slot: None,
+            snapshot_slot: 200,
+        }
+    }
+
+    #[test]
+    fn price_rule_resolves_yes_at_exact_target() {
+        let market = price_market(1_500_000);
+        let outcome = resolve_outcome(&market, &price_snapshot(1_500_000)).unwrap();
+        assert_eq!(outcome, Outcome::Yes);
+    }
+
+    #[test]
+    fn price_rule_resolves_no_one_unit_below_target() {
+        let market = price_market(1_500_000);
+        let outcome = resolve_outcome(&market, &price_snapshot(1_499_999)).unwrap();
+        assert_eq!(outcome, Outcome::No);
+    }
+
+    #[test]
+    fn price_rule_handles_extreme_targets() {
+        // Zero target is always met; MAX target only by an exact MAX price
+        let market = price_market(0);
+        assert_eq!(
+            resolve_outcome(&market, &price_snapshot(0)).unwrap(),
+            Outcome::Yes
+        );
+
+        let market = price_market(u64::MAX);
+        assert_eq!(
+            resolve_outcome(&market, &price_snapshot(u64::MAX - 1)).unwrap(),
+            Outcome::No
+        );
+        assert_eq!(
+            resolve_outcome(&market, &price_snapshot(u64::MAX)).unwrap(),
+            Outcome::Yes
+        );
+    }
+
+    #[test]
+    fn price_rule_ignores_market_cap() {
+        let market = price_market(2_000_000);
+        let mut snapshot = price_snapshot(1_000_000);
+        snapshot.market_cap_quote_units = u128::MAX;
+        assert_eq!(resolve_outcome(&market, &snapshot).unwrap(), Outcome::No);
+    }
+
+    fn volume_market() -> Market {
+        let token = migrated_token(mint(5));
+        create_market(
+            token,
+            &test_policy(),
+            6,
+            100,
+            200,
+            MarketRule::VolumeInWindowAtLeast {
+                window_start_slot: 120,
+                window_end_slot: 180,
+                target_volume_quote_units: 50_000,
+            },
+            test_params(),
+        )
+        .unwrap()
+    }
+
+    fn volume_snapshot(start: u64, end: u64, volume: u128) -> TokenSnapshot {
+        TokenSnapshot {
+            mint: mint(5),
+            migrated_to_pumpswap: true,
+            market_cap_quote_units: 0,
+            price_e6: 0,
+            volume: Some(VolumeWindowSnapshot {
+                window_start_slot: start,
+                window_end_slot: end,
+                cumulative_volume_quote_units: volume,
+            }),
+            migration_slot: None,
+            snapshot_slot: 200,
+        }
+    }
+
+    #[test]
+    fn volume_rule_resolves_against_target() {
+        let market = volume_market();
+        assert_eq!(
+            resolve_outcome(&market, &volume_snapshot(120, 180, 50_000)).unwrap(),
+            Outcome::Yes
+        );
+        assert_eq!(
+            resolve_outcome(&market, &volume_snapshot(120, 180, 49_999)).unwrap(),
+            Outcome::No
+        );
+    }
+
+    #[test]
+    fn volume_rule_rejects_partial_window() {
+        let market = volume_market();
+        for (start, end) in [(121, 180), (120, 179), (130, 170)] {
+            let err =
+                resolve_outcome(&market, &volume_snapshot(start, end, 1_000_000)).unwrap_err();
+            assert_eq!(err, PredictionError::SnapshotVolumeWindowPartial);
+        }
+    }
+
+    #[test]
+    fn volume_rule_rejects_mismatched_window() {
+        let market = volume_market();
+        for (start, end) in [(119, 180), (120, 181), (0, 200)] {
+            let err = resolve_outcome(&market, &volume_snapshot(start, end, 0)).unwrap_err();
+            assert_eq!(err, PredictionError::SnapshotVolumeWindowMismatch);
+        }
+    }
+
+    #[test]
+    fn volume_rule_requires_volume_data() {
+        let market = volume_market();
+        let mut snapshot = volume_snapshot(120, 180, 50_000);
+        snapshot.volume = None;
+        let err = resolve_outcome(&market, &snapshot).unwrap_err();
+        assert_eq!(err, PredictionError::SnapshotMissingVolume);
+    }
+
+    #[test]
+    fn rejects_volume_window_outside_market() {
+        let token = migrated_token(mint(5));
+        for (start, end) in [(99, 150), (150, 201), (160, 150)] {
+            let err = create_market(
+                token,
+                &test_policy(),
+                6,
+                100,
+                200,
+                MarketRule::VolumeInWindowAtLeast {
+                    window_start_slot: start,
+                    window_end_slot: end,
+                    target_volume_quote_units: 1,
+                },
+                test_params(),
+            )
+            .unwrap_err();
+            assert_eq!(err, PredictionError::InvalidVolumeWindow);
+        }
+    }
+
+    fn migration_market() -> Market {
+        let token = TokenStatus {
+            migrated_to_pumpswap: false,
+            ..migrated_token(mint(6))
+        };
+        create_market(
+            token,
+            &test_policy(),
+            8,
+            100,
+            200,
+            MarketRule::MigrationWithinWindow,
+            test_params(),
+        )
+        .unwrap()
+    }
+
+    fn migration_snapshot(migration_slot: Option<u64>) -> TokenSnapshot {
+        TokenSnapshot {
+            mint: mint(6),
+            migrated_to_pumpswap: migration_slot.is_some(),
+            market_cap_quote_units: 0,
+            price_e6: 0,
+            volume: None,
+            migration_slot,
+            snapshot_slot: 200,
+        }
+    }
+
+    #[test]
+    fn migration_rule_accepts_only_non_migrated_tokens() {
+        let migrated = migrated_token(mint(6));
+        let err = create_market(
+            migrated,
+            &test_policy(),
+            8,
+            100,
+            200,
+            MarketRule::MigrationWithinWindow,
+            test_params(),
+        )
+        .unwrap_err();
+        assert_eq!(err, PredictionError::TokenAlreadyMigrated);
+
+        // The relaxed path is scoped to this rule only
+        let pending = TokenStatus {
+            migrated_to_pumpswap: false,
+            ..migrated_token(mint(6))
+        };
+        let err = create_market(
+            pending,
+            &test_policy(),
+            8,
+            100,
+            200,
+            MarketRule::PriceAtCloseAtLeast { target_price_e6: 1 },
+            test_params(),
+        )
+        .unwrap_err();
+        assert_eq!(err, PredictionError::TokenNotMigrated);
+    }
+
+    #[test]
+    fn migration_rule_resolves_against_market_window() {
+        let market = migration_market();
+        for (slot, expected) in [
+            (99, Outcome::No),
+            (100, Outcome::Yes),
+            (200, Outcome::Yes),
+            (201, Outcome::No),
+        ] {
+            let outcome = resolve_outcome(&market, &migration_snapshot(Some(slot))).unwrap();
+            assert_eq!(outcome, expected, "migration_slot = {}", slot);
+        }
+        assert_eq!(
+            resolve_outcome(&market, &migration_snapshot(None)).unwrap(),
+            Outcome::No
+        );
+    }
+
+    #[test]
+    fn migration_rule_rejects_inconsistent_snapshot() {
+        let market = migration_market();
+        let mut snapshot = migration_snapshot(Some(150));
+        snapshot.migrated_to_pumpswap = false;
+        assert_eq!(
+            resolve_outcome(&market, &snapshot).unwrap_err(),
+            PredictionError::SnapshotMigrationInconsistent
+        );
+
+        let mut snapshot = migration_snapshot(None);
+        snapshot.migrated_to_pumpswap = true;
+        assert_eq!(
+            resolve_outcome(&market, &snapshot).unwrap_err(),
+            PredictionError::SnapshotMigrationInconsistent
+        );
+    }
+
+    fn settled_yes_market(pools: Pools, available: u128) -> Settlement {
+        let token = migrated_token(mint(9));
+        let market = create_market(
+            token,
+            &test_policy(),
+            10,
+            100,
+            200,
+            MarketRule::MarketCapAtCloseAtLeast {
+                target_quote_units: 1,
+            },
+            test_params(),
+        )
+        .unwrap();
+        let snapshot = TokenSnapshot {
+            mint: mint(9),
+            migrated_to_pumpswap: true,
+            market_cap_quote_units: 1,
+            price_e6: 0,
+            volume: None,
+            migration_slot: None,
+            snapshot_slot: 200,
+        };
+        settle_market(&market, &snapshot, pools, available).unwrap()
+    }
+
+    #[test]
+    fn ledger_aggregates_stakes_per_owner() {
+        let mut ledger = ParticipantLedger::new();
+        assert_eq!(ledger.record_stake(mint(1), Outcome::Yes, 10), Ok(0));
+        assert_eq!(ledger.record_stake(mint(2), Outcome::No, 5), Ok(1));
+        assert_eq!(ledger.record_stake(mint(1), Outcome::No, 3), Ok(0));
+        assert_eq!(ledger.record_stake(mint(1), Outcome::Yes, 7), Ok(0));
+
+        assert_eq!(ledger.num_participants, 2);
+        assert_eq!(ledger.positions[0].yes_capital, 17);
+        assert_eq!(ledger.positions[0].no_capital, 3);
+        assert_eq!(ledger.pools.yes_capital, 17);
+        assert_eq!(ledger.pools.no_capital, 8);
+        assert_eq!(
+            ledger.record_stake(mint(3), Outcome::Yes, 0),
+            Err(PredictionError::ZeroStake)
+        );
+    }
+
+    #[test]
+    fn ledger_rejects_participant_beyond_capacity() {
+        let mut ledger = ParticipantLedger::new();
+        for i in 0..MAX_PARTICIPANTS {
+            ledger.record_stake(owner(i), Outcome::Yes, 1).unwrap();
+        }
+        assert_eq!(
+            ledger.record_stake(owner(MAX_PARTICIPANTS), Outcome::Yes, 1),
+            Err(PredictionError::LedgerFull)
+        );
+        // Existing participants can still add to their stake
+        assert_eq!(ledger.record_stake(owner(0), Outcome::No, 1), Ok(0));
+    }
+
+    #[test]
+    fn participants_receive_capital_plus_haircut_profit() {
+        let mut ledger = ParticipantLedger::new();
+        ledger.record_stake(mint(1), Outcome::Yes, 60).unwrap();
+        ledger.record_stake(mint(2), Outcome::Yes, 40).unwrap();
+        ledger.record_stake(mint(3), Outcome::No, 80).unwrap();
+
+        // 130 available: 100 capital + 30 of 80 profit (h = 3/8)
+        let settlement = settled_yes_market(ledger.pools, 130);
+        let result = settle_participants(&ledger, &settlement).unwrap();
+
+        // profit_1 = 48 -> floor(48 * 3/8) = 18; profit_2 = 32 -> 12
+        assert_eq!(result.payouts[0], 78);
+        assert_eq!(result.payouts[1], 52);
+        assert_eq!(result.payouts[2], 0);
+        assert_eq!(result.total_paid, 130);
+        assert_eq!(result.rounding_dust, 0);
+    }
+
+    #[test]
+    fn participant_rounding_dust_is_tracked() {
+        let mut ledger = ParticipantLedger::new();
+        ledger.record_stake(mint(1), Outcome::Yes, 1).unwrap();
+        ledger.record_stake(mint(2), Outcome::Yes, 1).unwrap();
+        ledger.record_stake(mint(3), Outcome::Yes, 1).unwrap();
+        ledger.record_stake(mint(4), Outcome::No, 10).unwrap();
+
+        let settlement = settled_yes_market(ledger.pools, 13);
+        let result = settle_participants(&ledger, &settlement).unwrap();
+
+        // Each winner is owed 10/3 profit and receives floor = 3
+        assert_eq!(&result.payouts[..3], &[4, 4, 4]);
+        assert_eq!(result.total_paid, 12);
+        assert_eq!(result.rounding_dust, 1);
+    }
+
+    #[test]
+    fn participant_capital_is_scaled_when_vault_short() {
+        let mut ledger = ParticipantLedger::new();
+        ledger.record_stake(mint(1), Outcome::Yes, 30).unwrap();
+        ledger.record_stake(mint(2), Outcome::Yes, 70).unwrap();
+        ledger.record_stake(mint(3), Outcome::No, 50).unwrap();
+
+        let settlement = settled_yes_market(ledger.pools, 55);
+        let result = settle_participants(&ledger, &settlement).unwrap();
+
+        assert_eq!(result.payouts[0], 16); // floor(30 * 55 / 100)
+        assert_eq!(result.payouts[1], 38); // floor(70 * 55 / 100)
+        assert_eq!(result.total_paid + result.rounding_dust, 55);
+    }
+
+    #[test]
+    fn participant_settlement_rejects_foreign_pools() {
+        let mut ledger = ParticipantLedger::new();
+        ledger.record_stake(mint(1), Outcome::Yes, 10).unwrap();
+        ledger.record_stake(mint(2), Outcome::No, 10).unwrap();
+
+        let settlement = settled_yes_market(
+            Pools {
+                yes_capital: 11,
+                no_capital: 10,
+            },
+            21,
+        );
+        assert_eq!(
+            settle_participants(&ledger, &settlement).unwrap_err(),
+            PredictionError::LedgerPoolsMismatch
+        );
+    }
+
+    #[test]
+    fn participant_payouts_never_exceed_winner_total() {
+        // Deterministic xorshift sweep over stake distributions and vault levels
+        let mut x = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = move || {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+
+        for _ in 0..500 {
+            let mut ledger = ParticipantLedger::new();
+            let n = 1 + (next() as usize % MAX_PARTICIPANTS);
+            for i in 0..n {
+                let side = if next() % 2 == 0 {
+                    Outcome::Yes
+                } else {
+                    Outcome::No
+                };
+                let amount = 1 + (next() % 1_000_000) as u128;
+                ledger.record_stake(owner(i), side, amount).unwrap();
+            }
+            if ledger.pools.yes_capital == 0 {
+                continue;
+            }
+            let total = ledger.pools.yes_capital + ledger.pools.no_capital;
+            let available = (next() as u128) % (total + total / 2 + 1);
+
+            let settlement = settled_yes_market(ledger.pools, available);
+            let result = settle_participants(&ledger, &settlement).unwrap();
+
+            let sum: u128 = result.payouts.iter().sum();
+            assert_eq!(sum, result.total_paid);
+            assert!(result.total_paid <= settlement.winner_payout_total);
+            assert!(settlement.winner_payout_total <= available);
+            assert_eq!(
+                result.total_paid + result.rounding_dust,
+                settlement.winner_payout_total
+            );
+            // At most one unit lost per floor per participant
+            assert!(result.rounding_dust <= 2 * n as u128);
+        }
+    }
+
+    #[test]
+    fn participant_shares_of_large_pools_do_not_overflow() {
+        // stake * profit_claim_total exceeds u128 even though every share fits
+        let unit = 1u128 << 100;
+        let mut ledger = ParticipantLedger::new();
+        ledger
+            .record_stake(mint(1), Outcome::Yes, 3 * unit)
+            .unwrap();
+        ledger.record_stake(mint(2), Outcome::Yes, unit).unwrap();
+        ledger.record_stake(mint(3), Outcome::No, unit).unwrap();
+
+        let settlement = settled_yes_market(ledger.pools, 5 * unit);
+        let result = settle_participants(&ledger, &settlement).unwrap();
+        assert_eq!(result.payouts[0], 3 * unit + 3 * (unit / 4));
+        assert_eq!(result.payouts[1], unit + unit / 4);
+        assert_eq!(result.rounding_dust, 0);
+    }
+
+    #[test]
+    fn mul_div_floor_uses_wide_intermediate() {
+        assert_eq!(
+            mul_div_floor(u128::MAX, u128::MAX, u128::MAX),
+            Ok(u128::MAX)
+        );
+        assert_eq!(
+            mul_div_floor(u128::MAX, 3, 4),
+            Ok(u128::MAX / 4 * 3 + 2) // floor((4k + 3) * 3 / 4) = 3k + 2
+        );
+        assert_eq!(mul_div_floor(1 << 127, 1 << 127, 1 << 127), Ok(1 << 127));
+        assert_eq!(
+            mul_div_floor(u128::MAX, 2, 1),
+            Err(PredictionError::MathOverflow)
+        );
+        assert_eq!(mul_div_floor(1, 1, 0), Err(PredictionError::MathOverflow));
+    }
+
+    fn open_market_state() -> MarketState {
+        let token = migrated_token(mint(11));
+        let market = create_market(
+            token,
+            &test_policy(),
+            12,
+            100,
+            200,
+            MarketRule::MarketCapAtCloseAtLeast {
+                target_quote_units: 1_000,
+            },
+            test_params(),
+        )
+        .unwrap();
+        MarketState::new(market)
+    }
+
+    fn mcap_snapshot(market_cap_quote_units: u128, snapshot_slot: u64) -> TokenSnapshot {
+        TokenSnapshot {
+            mint: mint(11),
+            migrated_to_pumpswap: true,
+            market_cap_quote_units,
+            price_e6: 0,
+            volume: None,
+            migration_slot: None,
+            snapshot_slot,
+        }
+    }
+
+    #[test]
+    fn lifecycle_happy_path() {
+        let mut state = open_market_state();
+        state.stake(owner(1), Outcome::Yes, 100, 100).unwrap();
+        state.stake(owner(2), Outcome::No, 40, 198).unwrap();
+
+        state.close(200).unwrap();
+        assert_eq!(state.phase, MarketPhase::Closed);
+
+        let outcome = state.resolve(&mcap_snapshot(5_000, 201), 205).unwrap();
+        assert_eq!(outcome, ResolvedOutcome::Binary(Outcome::Yes));
+        assert_eq!(state.phase, MarketPhase::Resolved);
+
+        let settlement = state.settle(140, 206).unwrap();
+        assert_eq!(settlement.winner_payout_total, 140);
+        assert_eq!(state.phase, MarketPhase::Settled);
+        assert_eq!(state.settlement, Some(settlement));
+        assert!(state.is_terminal());
     }
 
     #[test]
-    fn stressed_settlement_applies_haircut() {
-        let token = TokenStatus {
-            mint: mint(2),
+    fn lifecycle_enforces_slots() {
+        let mut state = open_market_state();
+        assert_eq!(
+            state.stake(owner(1), Outcome::Yes, 1, 99),
+            Err(PredictionError::SlotRegression)
+        );
+        assert_eq!(
+            state.stake(owner(1), Outcome::Yes, 1, 200),
+            Err(PredictionError::BettingClosed)
+        );
+        assert_eq!(state.close(199), Err(PredictionError::CloseSlotNotReached));
+
+        state.stake(owner(1), Outcome::Yes, 1, 150).unwrap();
+        // Stakes move the clock forward like transitions do
+        assert_eq!(
+            state.stake(owner(2), Outcome::No, 1, 149),
+            Err(PredictionError::SlotRegression)
+        );
+        assert_eq!(state.close(149), Err(PredictionError::SlotRegression));
+        state.close(210).unwrap();
+        assert_eq!(
+            state.resolve(&mcap_snapshot(5_000, 205), 209),
+            Err(PredictionError::SlotRegression)
+        );
+        assert_eq!(
+            state.resolve(&mcap_snapshot(5_000, 220), 215),
+            Err(PredictionError::SnapshotInFuture)
+        );
+        state.resolve(&mcap_snapshot(5_000, 215), 215).unwrap();
+        assert_eq!(state.settle(1, 214), Err(PredictionError::SlotRegression));
+    }
+
+    #[test]
+    fn lifecycle_cannot_resolve_or_settle_twice() {
+        let mut state = open_market_state();
+        state.stake(owner(1), Outcome::Yes, 10, 100).unwrap();
+        state.stake(owner(2), Outcome::No, 10, 100).unwrap();
+        state.close(200).unwrap();
+        state.resolve(&mcap_snapshot(5_000, 200), 200).unwrap();
+
+        // A conflicting snapshot cannot flip the fixed outcome
+        assert_eq!(
+            state.resolve(&mcap_snapshot(0, 201), 201),
+            Err(PredictionError::InvalidTransition)
+        );
+        assert_eq!(state.outcome, Some(ResolvedOutcome::Binary(Outcome::Yes)));
+
+        let first = state.settle(20, 202).unwrap();
+        assert_eq!(
+            state.settle(5, 203),
+            Err(PredictionError::InvalidTransition)
+        );
+        assert_eq!(state.settlement, Some(first));
+        assert_eq!(
+            state.cancel_delisted(&delisted_token(mint(11)), 204),
+            Err(PredictionError::InvalidTransition)
+        );
+    }
+
+    #[test]
+    fn lifecycle_rejects_out_of_order_transitions() {
+        let mut state = open_market_state();
+        assert_eq!(
+            state.resolve(&mcap_snapshot(5_000, 200), 200),
+            Err(PredictionError::InvalidTransition)
+        );
+        assert_eq!(
+            state.settle(0, 200),
+            Err(PredictionError::InvalidTransition)
+        );
+        assert_eq!(state.dispute(200), Err(PredictionError::InvalidTransition));
+
+        state.close(200).unwrap();
+        assert_eq!(
+            state.stake(owner(1), Outcome::Yes, 1, 200),
+            Err(PredictionError::InvalidTransition)
+        );
+    }
+
+    #[test]
+    fn lifecycle_dispute_pauses_until_re_resolved() {
+        let mut state = open_market_state();
+        state.stake(owner(1), Outcome::Yes, 10, 100).unwrap();
+        state.stake(owner(2), Outcome::No, 10, 100).unwrap();
+        state.close(200).unwrap();
+
+        state.dispute(201).unwrap();
+        assert_eq!(state.phase, MarketPhase::Disputed);
+        assert_eq!(
+            state.settle(20, 202),
+            Err(PredictionError::InvalidTransition)
+        );
+
+        // A lone snapshot or source cannot end the dispute
+        assert_eq!(
+            state.resolve(&mcap_snapshot(0, 202), 203),
+            Err(PredictionError::DisputeSourcesMissing)
+        );
+        assert_eq!(
+            state.resolve_from_sources(&[sourced(1, 0)], 203),
+            Err(PredictionError::DisputeSourcesMissing)
+        );
+        assert_eq!(
+            state
+                .resolve_from_sources(&[sourced(1, 0), sourced(2, 0)], 203)
+                .unwrap(),
+            Resolution::Resolved(ResolvedOutcome::Binary(Outcome::No))
+        );
+        state.settle(20, 204).unwrap();
+    }
+
+    #[test]
+    fn lifecycle_resolved_outcome_cannot_be_disputed() {
+        let mut state = open_market_state();
+        state.stake(owner(1), Outcome::Yes, 10, 100).unwrap();
+        state.stake(owner(2), Outcome::No, 10, 100).unwrap();
+        state.close(200).unwrap();
+        state.resolve(&mcap_snapshot(5_000, 200), 200).unwrap();
+
+        assert_eq!(state.dispute(201), Err(PredictionError::InvalidTransition));
+        assert_eq!(state.phase, MarketPhase::Resolved);
+        assert_eq!(state.outcome, Some(ResolvedOutcome::Binary(Outcome::Yes)));
+        assert_eq!(state.settle(20, 202).unwrap().outcome, Outcome::Yes);
+    }
+
+    #[test]
+    fn lifecycle_cancel_from_non_terminal_phases() {
+        let mut state = open_market_state();
+        state
+            .cancel_delisted(&delisted_token(mint(11)), 150)
+            .unwrap();
+        assert_eq!(state.phase, MarketPhase::Cancelled);
+        assert_eq!(state.cancel_reason, Some(CancelReason::TokenDelisted));
+        assert_eq!(
+            state.stake(owner(1), Outcome::Yes, 1, 151),
+            Err(PredictionError::InvalidTransition)
+        );
+
+        let mut state = open_market_state();
+        state.close(200).unwrap();
+        state.dispute(201).unwrap();
+        state
+            .cancel_delisted(&delisted_token(mint(11)), 202)
+            .unwrap();
+        assert!(state.is_terminal());
+    }
+
+    #[test]
+    fn delisting_cancel_requires_evidence_before_resolution() {
+        let mut state = open_market_state();
+        assert_eq!(
+            state.cancel(CancelReason::TokenDelisted, 150),
+            Err(PredictionError::CancelNotAllowed)
+        );
+        assert_eq!(
+            state.cancel_delisted(&migrated_token(mint(11)), 150),
+            Err(PredictionError::CancelNotAllowed)
+        );
+        assert_eq!(
+            state.cancel_delisted(&delisted_token(mint(12)), 150),
+            Err(PredictionError::SnapshotTokenMismatch)
+        );
+
+        state.stake(owner(1), Outcome::Yes, 10, 150).unwrap();
+        state.stake(owner(2), Outcome::No, 10, 150).unwrap();
+        state.close(200).unwrap();
+        state.resolve(&mcap_snapshot(5_000, 200), 200).unwrap();
+        assert_eq!(
+            state.cancel_delisted(&delisted_token(mint(11)), 201),
+            Err(PredictionError::CancelNotAllowed)
+        );
+        assert_eq!(state.phase, MarketPhase::Resolved);
+
+        // A delisted token cannot back a new market either
+        assert_eq!(
+            create_market(
+                delisted_token(mint(11)),
+                &test_policy(),
+                18,
+                100,
+                200,
+                registry_rule(),
+                test_params(),
+            ),
+            Err(PredictionError::TokenDelisted)
+        );
+    }
+
+    #[test]
+    fn rejects_stale_snapshot() {
+        let token = migrated_token(mint(11));
+        let market = create_market(
+            token,
+            &test_policy(),
+            13,
+            100,
+            200,
+            MarketRule::MarketCapAtCloseAtLeast {
+                target_quote_units: 1_000,
+            },
+            MarketParams {
+                max_snapshot_delay_slots: 10,
+                ..test_params()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            resolve_outcome(&market, &mcap_snapshot(5_000, 210)).unwrap(),
+            Outcome::Yes
+        );
+        assert_eq!(
+            resolve_outcome(&market, &mcap_snapshot(5_000, 211)).unwrap_err(),
+            PredictionError::SnapshotStale
+        );
+        // Stale far-future snapshot cannot be used to settle either
+        let pools = Pools {
+            yes_capital: 10,
+            no_capital: 10,
+        };
+        assert_eq!(
+            settle_market(&market, &mcap_snapshot(0, u64::MAX), pools, 20).unwrap_err(),
+            PredictionError::SnapshotStale
+        );
+    }
+
+    #[test]
+    fn zero_delay_requires_snapshot_at_close() {
+        let token = migrated_token(mint(11));
+        let market = create_market(
+            token,
+            &test_policy(),
+            14,
+            100,
+            200,
+            MarketRule::MarketCapAtCloseAtLeast {
+                target_quote_units: 1_000,
+            },
+            MarketParams {
+                max_snapshot_delay_slots: 0,
+                ..test_params()
+            },
+        )
+        .unwrap();
+
+        assert!(resolve_outcome(&market, &mcap_snapshot(5_000, 200)).is_ok());
+        assert_eq!(
+            resolve_outcome(&market, &mcap_snapshot(5_000, 201)).unwrap_err(),
+            PredictionError::SnapshotStale
+        );
+    }
+
+    fn mcap_market_with_policy(agreement_policy: AgreementPolicy) -> Market {
+        let token = migrated_token(mint(11));
+        create_market(
+            token,
+            &test_policy(),
+            15,
+            100,
+            200,
+            MarketRule::MarketCapAtCloseAtLeast {
+                target_quote_units: 1_000,
+            },
+            MarketParams {
+                agreement_policy,
+                ..test_params()
+            },
+        )
+        .unwrap()
+    }
+
+    fn sourced(source_id: u32, market_cap_quote_units: u128) -> SourcedSnapshot {
+        SourcedSnapshot {
+            source_id,
+            snapshot: mcap_snapshot(market_cap_quote_units, 200),
+        }
+    }
+
+    #[test]
+    fn multi_source_exact_agreement_resolves() {
+        let market = mcap_market_with_policy(AgreementPolicy::Exact);
+        let sources = [sourced(1, 5_000), sourced(2, 5_000), sourced(3, 5_000)];
+        assert_eq!(
+            resolve_with_sources(&market, &sources).unwrap(),
+            Resolution::Resolved(ResolvedOutcome::Binary(Outcome::Yes))
+        );
+        // A single (primary) source is accepted as-is
+        assert_eq!(
+            resolve_with_sources(&market, &sources[..1]).unwrap(),
+            Resolution::Resolved(ResolvedOutcome::Binary(Outcome::Yes))
+        );
+    }
+
+    #[test]
+    fn multi_source_exact_mismatch_disputes() {
+        let market = mcap_market_with_policy(AgreementPolicy::Exact);
+        let sources = [sourced(1, 5_000), sourced(2, 5_000), sourced(3, 5_001)];
+        assert_eq!(
+            resolve_with_sources(&market, &sources).unwrap(),
+            Resolution::Disputed(Dispute {
+                field: DisputeField::MarketCap,
+                primary_source_id: 1,
+                conflicting_source_id: 3,
+                primary_value: 5_000,
+                conflicting_value: 5_001,
+            })
+        );
+    }
+
+    #[test]
+    fn multi_source_tolerance_boundary() {
+        // 100 bps = 1%: 10_000 vs 9_900 agrees, 9_899 does not
+        let market = mcap_market_with_policy(AgreementPolicy::WithinBps { tolerance_bps: 100 });
+        assert_eq!(
+            resolve_with_sources(&market, &[sourced(1, 10_000), sourced(2, 9_900)]).unwrap(),
+            Resolution::Resolved(ResolvedOutcome::Binary(Outcome::Yes))
+        );
+        match resolve_with_sources(&market, &[sourced(1, 10_000), sourced(2, 9_899)]).unwrap() {
+            Resolution::Disputed(d) => assert_eq!(d.field, DisputeField::MarketCap),
+            other => panic!("expected dispute, got {:?}", other),
+        }
+        // Tolerance is symmetric in source order
+        assert_eq!(
+            resolve_with_sources(&market, &[sourced(1, 9_900), sourced(2, 10_000)]).unwrap(),
+            Resolution::Resolved(ResolvedOutcome::Binary(Outcome::Yes))
+        );
+        assert!(values_agree(
+            AgreementPolicy::WithinBps {
+                tolerance_bps: 10_000
+            },
+            u128::MAX,
+            0
+        ));
+    }
+
+    #[test]
+    fn multi_source_outcome_straddle_disputes_within_tolerance() {
+        // Target is 1_000; both values within 1% but on opposite sides
+        let market = mcap_market_with_policy(AgreementPolicy::WithinBps { tolerance_bps: 100 });
+        assert_eq!(
+            resolve_with_sources(&market, &[sourced(1, 1_000), sourced(2, 999)]).unwrap(),
+            Resolution::Disputed(Dispute {
+                field: DisputeField::Outcome,
+                primary_source_id: 1,
+                conflicting_source_id: 2,
+                primary_value: 1,
+                conflicting_value: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn multi_source_validates_source_set() {
+        let market = mcap_market_with_policy(AgreementPolicy::Exact);
+        assert_eq!(
+            resolve_with_sources(&market, &[]).unwrap_err(),
+            PredictionError::NoOracleSources
+        );
+        assert_eq!(
+            resolve_with_sources(&market, &[sourced(1, 5_000), sourced(1, 5_000)]).unwrap_err(),
+            PredictionError::DuplicateOracleSource
+        );
+        let too_many = [sourced(1, 5_000); MAX_ORACLE_SOURCES + 1];
+        assert_eq!(
+            resolve_with_sources(&market, &too_many).unwrap_err(),
+            PredictionError::TooManyOracleSources
+        );
+
+        let token = migrated_token(mint(11));
+        let err = create_market(
+            token,
+            &test_policy(),
+            16,
+            100,
+            200,
+            MarketRule::MarketCapAtCloseAtLeast {
+                target_quote_units: 1,
+            },
+            MarketParams {
+                agreement_policy: AgreementPolicy::WithinBps {
+                    tolerance_bps: 10_001,
+                },
+                ..test_params()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err, PredictionError::InvalidAgreementPolicy);
+    }
+
+    #[test]
+    fn disputed_market_is_paused_until_sources_agree() {
+        let mut state = open_market_state();
+        state.stake(owner(1), Outcome::Yes, 10, 100).unwrap();
+        state.stake(owner(2), Outcome::No, 10, 100).unwrap();
+        state.close(200).unwrap();
+
+        let resolution = state
+            .resolve_from_sources(&[sourced(1, 5_000), sourced(2, 0)], 201)
+            .unwrap();
+        assert!(matches!(resolution, Resolution::Disputed(_)));
+        assert_eq!(state.phase, MarketPhase::Disputed);
+        assert_eq!(state.dispute.unwrap().conflicting_source_id, 2);
+        assert_eq!(
+            state.settle(20, 202),
+            Err(PredictionError::InvalidTransition)
+        );
+
+        // Dropping the dissenting source does not end the dispute
+        assert_eq!(
+            state.resolve_from_sources(&[sourced(1, 5_000), sourced(3, 5_000)], 202),
+            Err(PredictionError::DisputeSourcesMissing)
+        );
+        assert_eq!(
+            state.resolve(&mcap_snapshot(5_000, 200), 202),
+            Err(PredictionError::DisputeSourcesMissing)
+        );
+
+        // A new disagreement keeps the original one binding
+        state
+            .resolve_from_sources(&[sourced(1, 5_000), sourced(2, 5_000), sourced(3, 0)], 202)
+            .unwrap();
+        assert_eq!(state.phase, MarketPhase::Disputed);
+        assert_eq!(state.dispute.unwrap().conflicting_source_id, 2);
+
+        state
+            .resolve_from_sources(
+                &[sourced(1, 5_000), sourced(2, 5_000), sourced(3, 5_000)],
+                203,
+            )
+            .unwrap();
+        assert_eq!(state.phase, MarketPhase::Resolved);
+        assert_eq!(state.dispute, None);
+        assert_eq!(state.settle(20, 204).unwrap().outcome, Outcome::Yes);
+    }
+
+    fn sha256(data: &[u8]) -> [u8; 32] {
+        let mut h = Sha256::new();
+        h.update(data);
+        h.finalize()
+    }
+
+    fn hex(bytes: &[u8; 32]) -> [u8; 64] {
+        let digits = b"0123456789abcdef";
+        let mut out = [0u8; 64];
+        for (i, b) in bytes.iter().enumerate() {
+            out[2 * i] = digits[(b >> 4) as usize];
+            out[2 * i + 1] = digits[(b & 0xf) as usize];
+        }
+        out
+    }
+
+    #[test]
+    fn sha256_matches_fips_vectors() {
+        assert_eq!(
+            &hex(&sha256(b"")),
+            b"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            &hex(&sha256(b"abc")),
+            b"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            &hex(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            b"248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        // Split updates across block boundaries hash the same as one update
+        let data = [0x5au8; 200];
+        let mut h = Sha256::new();
+        h.update(&data[..1]);
+        h.update(&data[1..64]);
+        h.update(&data[64..130]);
+        h.update(&data[130..]);
+        assert_eq!(h.finalize(), sha256(&data));
+    }
+
+    #[test]
+    fn settlement_input_hash_matches_documented_encoding() {
+        let pools = Pools {
+            yes_capital: 100,
+            no_capital: 40,
+        };
+        let mut snapshot = mcap_snapshot(5_000, 201);
+        snapshot.volume = Some(VolumeWindowSnapshot {
+            window_start_slot: 3,
+            window_end_slot: 4,
+            cumulative_volume_quote_units: 5,
+        });
+        let settlement = settle_outcome(Outcome::Yes, pools, 140, &test_params()).unwrap();
+
+        let mut buf = [0u8; 4 + 33 + 107 + 32 + 177];
+        let mut at = 0;
+        let mut put = |bytes: &[u8]| {
+            buf[at..at + bytes.len()].copy_from_slice(bytes);
+            at += bytes.len();
+        };
+        put(&RESOLVER_VERSION.to_le_bytes());
+        put(&[0]);
+        put(&1u128.to_le_bytes());
+        put(&0u128.to_le_bytes());
+        put(&mint(11));
+        put(&[1]);
+        put(&5_000u128.to_le_bytes());
+        put(&0u64.to_le_bytes());
+        put(&[1]);
+        put(&3u64.to_le_bytes());
+        put(&4u64.to_le_bytes());
+        put(&5u128.to_le_bytes());
+        put(&[0]);
+        put(&0u64.to_le_bytes());
+        put(&201u64.to_le_bytes());
+        put(&100u128.to_le_bytes());
+        put(&40u128.to_le_bytes());
+        put(&[1]);
+        for v in [100u128, 40, 100, 40, 0, 0, 40, 40, 40, 40, 140] {
+            put(&v.to_le_bytes());
+        }
+        assert_eq!(at, buf.len());
+
+        let outcome = ResolvedOutcome::Binary(Outcome::Yes);
+        assert_eq!(
+            settlement_input_hash(&snapshot, outcome, pools, &settlement),
+            sha256(&buf)
+        );
+    }
+
+    #[test]
+    fn resolution_record_is_deterministic() {
+        let market = mcap_market_with_policy(AgreementPolicy::Exact);
+        let snapshot = mcap_snapshot(5_000, 200);
+        let pools = Pools {
+            yes_capital: 100,
+            no_capital: 40,
+        };
+
+        let (settlement, record) =
+            settle_market_with_record(&market, &snapshot, pools, 140, &[7, 9], 250).unwrap();
+        let (_, again) =
+            settle_market_with_record(&market, &snapshot, pools, 140, &[7, 9], 250).unwrap();
+        assert_eq!(record, again);
+
+        assert_eq!(record.market_id, market.market_id);
+        assert_eq!(record.outcome, ResolvedOutcome::Binary(settlement.outcome));
+        assert_eq!(&record.source_ids[..record.num_sources as usize], &[7, 9]);
+        assert_eq!(record.resolver_version, RESOLVER_VERSION);
+        assert_eq!(record.settled_slot, 250);
+
+        // Any change to the inputs changes the hash
+        let (_, other_vault) =
+            settle_market_with_record(&market, &snapshot, pools, 139, &[7, 9], 250).unwrap();
+        assert_ne!(record.input_hash, other_vault.input_hash);
+        let mut other_snapshot = snapshot;
+        other_snapshot.price_e6 = 1;
+        let (_, other_snap) =
+            settle_market_with_record(&market, &other_snapshot, pools, 140, &[7, 9], 250).unwrap();
+        assert_ne!(record.input_hash, other_snap.input_hash);
+    }
+
+    #[test]
+    fn resolution_record_names_bucket_and_scalar_share() {
+        // Buckets 0 and 1 hold equal stakes, so both collapse to the same
+        // binary settlement; only the recorded outcome tells them apart
+        let market = bucket_market(&[1_000_000, 5_000_000]);
+        let mut pools = CategoricalPools::new(3);
+        pools.capital[..2].copy_from_slice(&[100, 100]);
+        let record_for = |mcap| {
+            let snapshot = bucket_snapshot(mcap);
+            let (winning, settlement) =
+                settle_categorical_market(&market, &snapshot, &pools, 200).unwrap();
+            let collapsed = pools.collapse(winning).unwrap();
+            resolution_record(&market, &snapshot, collapsed, &settlement, &[7], 250).unwrap()
+        };
+        let (low, mid) = (record_for(0), record_for(2_000_000));
+        assert_eq!(low.outcome, ResolvedOutcome::Bucket(0));
+        assert_eq!(mid.outcome, ResolvedOutcome::Bucket(1));
+        let snapshot = bucket_snapshot(0);
+        let settlement = settle_categorical(0, &pools, 200, &test_params()).unwrap();
+        let collapsed = pools.collapse(0).unwrap();
+        assert_ne!(
+            settlement_input_hash(
+                &snapshot,
+                ResolvedOutcome::Bucket(0),
+                collapsed,
+                &settlement
+            ),
+            settlement_input_hash(
+                &snapshot,
+                ResolvedOutcome::Bucket(1),
+                collapsed,
+                &settlement
+            )
+        );
+
+        let market = scalar_market(1_000, 5_000);
+        let pools = Pools {
+            yes_capital: 600,
+            no_capital: 400,
+        };
+        let snapshot = bucket_snapshot(2_000);
+        let settlement = settle_scalar_market(&market, &snapshot, pools, 1_000).unwrap();
+        let record =
+            scalar_resolution_record(&market, &snapshot, pools, &settlement, &[7], 250).unwrap();
+        assert_eq!(
+            record.outcome,
+            ResolvedOutcome::Scalar {
+                long_num: 1_000,
+                long_den: 4_000
+            }
+        );
+        let other = settle_scalar(1, 2, pools, 1_000, &test_params()).unwrap();
+        assert_ne!(
+            record.input_hash,
+            scalar_settlement_input_hash(&snapshot, pools, &other)
+        );
+        assert_eq!(
+            scalar_resolution_record(&market, &snapshot, pools, &other, &[7], 250),
+            Err(PredictionError::LedgerPoolsMismatch)
+        );
+        assert_eq!(
+            resolution_record(
+                &market,
+                &snapshot,
+                pools,
+                &settled_yes_market(pools, 1_000),
+                &[7],
+                250
+            ),
+            Err(PredictionError::NotBinaryMarket)
+        );
+    }
+
+    #[test]
+    fn resolution_record_requires_sources() {
+        let market = mcap_market_with_policy(AgreementPolicy::Exact);
+        let snapshot = mcap_snapshot(5_000, 200);
+        let pools = Pools {
+            yes_capital: 1,
+            no_capital: 1,
+        };
+        assert_eq!(
+            settle_market_with_record(&market, &snapshot, pools, 2, &[], 200).unwrap_err(),
+            PredictionError::NoOracleSources
+        );
+        assert_eq!(
+            settle_market_with_record(
+                &market,
+                &snapshot,
+                pools,
+                2,
+                &[0; MAX_ORACLE_SOURCES + 1],
+                200
+            )
+            .unwrap_err(),
+            PredictionError::TooManyOracleSources
+        );
+    }
+
+    #[test]
+    fn cancel_on_empty_winner_side() {
+        let mut state = open_market_state();
+        state.stake(owner(1), Outcome::No, 30, 100).unwrap();
+        state.close(200).unwrap();
+        assert_eq!(
+            state.cancel(CancelReason::EmptyWinnerSide, 200),
+            Err(PredictionError::CancelNotAllowed)
+        );
+        state.resolve(&mcap_snapshot(5_000, 200), 200).unwrap();
+        assert_eq!(state.settle(30, 201), Err(PredictionError::EmptyWinnerSide));
+
+        state.cancel(CancelReason::EmptyWinnerSide, 201).unwrap();
+        let refunds = state.refunds(30).unwrap();
+        assert_eq!(refunds.payouts[0], 30);
+        assert_eq!(refunds.total_paid, 30);
+    }
+
+    #[test]
+    fn cancel_on_empty_winner_side_requires_empty_side() {
+        let mut state = open_market_state();
+        state.stake(owner(1), Outcome::Yes, 30, 100).unwrap();
+        state.close(200).unwrap();
+        state.resolve(&mcap_snapshot(5_000, 200), 200).unwrap();
+        assert_eq!(
+            state.cancel(CancelReason::EmptyWinnerSide, 201),
+            Err(PredictionError::CancelNotAllowed)
+        );
+    }
+
+    #[test]
+    fn cancel_when_snapshot_window_lapses() {
+        // test_params: max_snapshot_delay_slots = 100, close_slot = 200
+        let mut state = open_market_state();
+        state.close(200).unwrap();
+        assert_eq!(
+            state.cancel(CancelReason::SnapshotUnavailable, 300),
+            Err(PredictionError::CancelNotAllowed)
+        );
+        state
+            .cancel(CancelReason::SnapshotUnavailable, 301)
+            .unwrap();
+    }
+
+    #[test]
+    fn cancel_after_dispute_timeout() {
+        // test_params: dispute_timeout_slots = 50
+        let mut state = open_market_state();
+        state.close(200).unwrap();
+        state
+            .resolve_from_sources(&[sourced(1, 5_000), sourced(2, 0)], 210)
+            .unwrap();
+        // Repeated disagreement does not restart the timeout
+        state
+            .resolve_from_sources(&[sourced(1, 5_000), sourced(2, 0), sourced(3, 0)], 240)
+            .unwrap();
+        assert_eq!(state.disputed_since_slot, 210);
+        assert_eq!(
+            state.cancel(CancelReason::DisputeTimeout, 259),
+            Err(PredictionError::CancelNotAllowed)
+        );
+        state.cancel(CancelReason::DisputeTimeout, 260).unwrap();
+        assert_eq!(state.cancel_reason, Some(CancelReason::DisputeTimeout));
+    }
+
+    #[test]
+    fn refunds_require_cancelled_market() {
+        let state = open_market_state();
+        assert_eq!(
+            state.refunds(100).unwrap_err(),
+            PredictionError::InvalidTransition
+        );
+    }
+
+    #[test]
+    fn refunds_pay_par_when_vault_covers_stakes() {
+        let mut ledger = ParticipantLedger::new();
+        ledger.record_stake(owner(1), Outcome::Yes, 25).unwrap();
+        ledger.record_stake(owner(2), Outcome::No, 10).unwrap();
+        ledger.record_stake(owner(1), Outcome::No, 5).unwrap();
+
+        let refunds = refund_participants(&ledger, 1_000).unwrap();
+        assert_eq!(&refunds.payouts[..2], &[30, 10]);
+        assert_eq!(refunds.total_paid, 40);
+        assert_eq!(refunds.rounding_dust, 0);
+    }
+
+    #[test]
+    fn refunds_are_pro_rata_when_vault_short() {
+        let mut ledger = ParticipantLedger::new();
+        ledger.record_stake(owner(1), Outcome::Yes, 1).unwrap();
+        ledger.record_stake(owner(2), Outcome::Yes, 1).unwrap();
+        ledger.record_stake(owner(3), Outcome::No, 1).unwrap();
+
+        let refunds = refund_participants(&ledger, 2).unwrap();
+        // floor(1 * 2 / 3) = 0 each; the 2 units remain as dust
+        assert_eq!(&refunds.payouts[..3], &[0, 0, 0]);
+        assert_eq!(refunds.rounding_dust, 2);
+
+        let mut ledger = ParticipantLedger::new();
+        ledger.record_stake(owner(1), Outcome::Yes, 60).unwrap();
+        ledger.record_stake(owner(2), Outcome::No, 40).unwrap();
+        let refunds = refund_participants(&ledger, 50).unwrap();
+        assert_eq!(&refunds.payouts[..2], &[30, 20]);
+        assert_eq!(refunds.total_paid, 50);
+    }
+
+    fn fee_params(protocol_fee_bps: u64, creator_fee_bps: u64) -> MarketParams {
+        MarketParams {
+            protocol_fee_bps,
+            creator_fee_bps,
+            ..test_params()
+        }
+    }
+
+    #[test]
+    fn fees_come_off_loser_pool_before_profit() {
+        let pools = Pools {
+            yes_capital: 100,
+            no_capital: 1_000,
+        };
+        // 2% protocol + 1% creator on 1_000 = 20 + 10
+        let settlement = settle_outcome(Outcome::Yes, pools, 1_100, &fee_params(200, 100)).unwrap();
+        assert_eq!(settlement.protocol_fee_paid, 20);
+        assert_eq!(settlement.creator_fee_paid, 10);
+        assert_eq!(settlement.profit_claim_total, 970);
+        assert_eq!((settlement.h_num, settlement.h_den), (970, 970));
+        assert_eq!(settlement.winner_payout_total, 1_070);
+    }
+
+    #[test]
+    fn fees_are_paid_before_haircut_profit_under_stress() {
+        let pools = Pools {
+            yes_capital: 100,
+            no_capital: 1_000,
+        };
+        // Residual 50: fees take 30, winners get 20 of 970 profit
+        let settlement = settle_outcome(Outcome::Yes, pools, 150, &fee_params(200, 100)).unwrap();
+        assert_eq!(settlement.protocol_fee_paid, 20);
+        assert_eq!(settlement.creator_fee_paid, 10);
+        assert_eq!((settlement.h_num, settlement.h_den), (20, 970));
+        assert_eq!(settlement.winner_payout_total, 120);
+
+        // Residual 25: protocol paid in full, creator partially, no profit
+        let settlement = settle_outcome(Outcome::Yes, pools, 125, &fee_params(200, 100)).unwrap();
+        assert_eq!(settlement.protocol_fee_paid, 20);
+        assert_eq!(settlement.creator_fee_paid, 5);
+        assert_eq!(settlement.winner_profit_paid, 0);
+    }
+
+    #[test]
+    fn rejects_fees_above_loser_pool() {
+        let token = migrated_token(mint(11));
+        let err = create_market(
+            token,
+            &test_policy(),
+            17,
+            100,
+            200,
+            MarketRule::MarketCapAtCloseAtLeast {
+                target_quote_units: 1,
+            },
+            fee_params(9_000, 1_001),
+        )
+        .unwrap_err();
+        assert_eq!(err, PredictionError::InvalidFee);
+
+        // 100% fees are allowed and leave no profit claim
+        let pools = Pools {
+            yes_capital: 10,
+            no_capital: 10,
+        };
+        let settlement =
+            settle_outcome(Outcome::Yes, pools, 20, &fee_params(9_000, 1_000)).unwrap();
+        assert_eq!(settlement.profit_claim_total, 0);
+        assert_eq!((settlement.h_num, settlement.h_den), (1, 1));
+        assert_eq!(settlement.winner_payout_total, 10);
+
+        // Params built by hand bypass create_market; settlement still refuses them
+        let over = fee_params(9_000, 1_001);
+        assert_eq!(
+            settle_outcome(Outcome::Yes, pools, 20, &over).unwrap_err(),
+            PredictionError::InvalidFee
+        );
+        assert_eq!(
+            settle_scalar(1, 2, pools, 20, &over).unwrap_err(),
+            PredictionError::InvalidFee
+        );
+        let mut market = mcap_market_with_policy(AgreementPolicy::Exact);
+        market.params = over;
+        assert_eq!(
+            payout_multiplier(&market, &ledger_of(pools), Outcome::Yes, 1).unwrap_err(),
+            PredictionError::InvalidFee
+        );
+    }
+
+    #[test]
+    fn fees_and_payouts_stay_within_vault() {
+        let mut x = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = move || {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+
+        for _ in 0..500 {
+            let mut ledger = ParticipantLedger::new();
+            let n = 1 + (next() as usize % MAX_PARTICIPANTS);
+            for i in 0..n {
+                let side = if i == 0 || next() % 2 == 0 {
+                    Outcome::Yes
+                } else {
+                    Outcome::No
+                };
+                ledger
+                    .record_stake(owner(i), side, 1 + (next() % 1_000_000) as u128)
+                    .unwrap();
+            }
+            let protocol_bps = next() % 5_001;
+            let creator_bps = next() % 5_001;
+            let total = ledger.pools.yes_capital + ledger.pools.no_capital;
+            let available = (next() as u128) % (total + 1);
+
+            let settlement = settle_outcome(
+                Outcome::Yes,
+                ledger.pools,
+                available,
+                &fee_params(protocol_bps, creator_bps),
+            )
+            .unwrap();
+            let paid_out = settlement.winner_payout_total
+                + settlement.protocol_fee_paid
+                + settlement.creator_fee_paid;
+            assert!(paid_out <= available);
+
+            let result = settle_participants(&ledger, &settlement).unwrap();
+            assert!(result.total_paid <= settlement.winner_payout_total);
+        }
+    }
+
+    fn bucket_market(bounds: &[u128]) -> Market {
+        let mut upper_bounds = [0u128; MAX_OUTCOMES - 1];
+        upper_bounds[..bounds.len()].copy_from_slice(bounds);
+        let token = migrated_token(mint(13));
+        create_market(
+            token,
+            &test_policy(),
+            21,
+            100,
+            200,
+            MarketRule::MarketCapBuckets {
+                upper_bounds,
+                num_outcomes: bounds.len() as u8 + 1,
+            },
+            test_params(),
+        )
+        .unwrap()
+    }
+
+    fn bucket_snapshot(market_cap_quote_units: u128) -> TokenSnapshot {
+        TokenSnapshot {
+            mint: mint(13),
             migrated_to_pumpswap: true,
+            market_cap_quote_units,
+            price_e6: 0,
+            volume: None,
+            migration_slot: None,
+            snapshot_slot: 210,
+        }
+    }
+
+    #[test]
+    fn resolves_market_cap_bucket() {
+        // "<1M / 1-5M / >=5M"
+        let market = bucket_market(&[1_000_000, 5_000_000]);
+        for (mcap, bucket) in [
+            (0, 0),
+            (999_999, 0),
+            (1_000_000, 1),
+            (4_999_999, 1),
+            (5_000_000, 2),
+            (u128::MAX, 2),
+        ] {
+            assert_eq!(resolve_bucket(&market, &bucket_snapshot(mcap)), Ok(bucket));
+        }
+
+        assert_eq!(
+            resolve_outcome(&market, &bucket_snapshot(0)),
+            Err(PredictionError::NotBinaryMarket)
+        );
+        assert_eq!(
+            resolve_bucket(&open_market_state().market, &bucket_snapshot(0)),
+            Err(PredictionError::NotCategoricalMarket)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_buckets() {
+        let token = migrated_token(mint(13));
+        let mut increasing = [0u128; MAX_OUTCOMES - 1];
+        for (i, b) in increasing.iter_mut().enumerate() {
+            *b = 10 * (i as u128 + 1);
+        }
+        let mut not_increasing = increasing;
+        not_increasing[1] = not_increasing[0];
+        let mut stray_bound = [0u128; MAX_OUTCOMES - 1];
+        stray_bound[0] = 10;
+        stray_bound[2] = 30;
+
+        for (upper_bounds, num_outcomes) in [
+            (increasing, 1),
+            (increasing, MAX_OUTCOMES as u8 + 1),
+            (not_increasing, MAX_OUTCOMES as u8),
+            (stray_bound, 2),
+        ] {
+            let rule = MarketRule::MarketCapBuckets {
+                upper_bounds,
+                num_outcomes,
+            };
+            assert_eq!(
+                create_market(token, &test_policy(), 21, 100, 200, rule, test_params()),
+                Err(PredictionError::InvalidBuckets)
+            );
+        }
+
+        let rule = MarketRule::MarketCapBuckets {
+            upper_bounds: increasing,
+            num_outcomes: MAX_OUTCOMES as u8,
+        };
+        assert!(create_market(token, &test_policy(), 21, 100, 200, rule, test_params()).is_ok());
+    }
+
+    #[test]
+    fn losing_buckets_fund_winning_bucket() {
+        let market = bucket_market(&[1_000_000, 5_000_000]);
+        let mut pools = CategoricalPools::new(3);
+        pools.capital = [300, 100, 600, 0, 0, 0, 0, 0][..MAX_OUTCOMES]
+            .try_into()
+            .unwrap();
+
+        let (winning, settlement) =
+            settle_categorical_market(&market, &bucket_snapshot(2_000_000), &pools, 1_000).unwrap();
+        assert_eq!(winning, 1);
+        assert_eq!(settlement.winner_capital_total, 100);
+        assert_eq!(settlement.loser_capital_total, 900);
+        assert_eq!((settlement.h_num, settlement.h_den), (900, 900));
+        assert_eq!(settlement.winner_payout_total, 1_000);
+
+        // Stressed vault: capital first, then h < 1 on the pooled losers
+        let settlement = settle_categorical(1, &pools, 400, &test_params()).unwrap();
+        assert_eq!((settlement.h_num, settlement.h_den), (300, 900));
+        assert_eq!(settlement.winner_payout_total, 400);
+
+        assert_eq!(
+            settle_categorical(3, &pools, 1_000, &test_params()),
+            Err(PredictionError::InvalidOutcomeIndex)
+        );
+        assert_eq!(
+            settle_categorical_market(
+                &market,
+                &bucket_snapshot(0),
+                &CategoricalPools::new(4),
+                1_000
+            ),
+            Err(PredictionError::InvalidOutcomeIndex)
+        );
+    }
+
+    #[test]
+    fn categorical_ledger_collapses_to_binary_positions() {
+        let mut ledger = CategoricalLedger::new(3);
+        ledger.record_stake(owner(0), 0, 50).unwrap();
+        ledger.record_stake(owner(0), 2, 70).unwrap();
+        ledger.record_stake(owner(1), 2, 30).unwrap();
+        assert_eq!(
+            ledger.record_stake(owner(1), 3, 30),
+            Err(PredictionError::InvalidOutcomeIndex)
+        );
+
+        let binary = ledger.collapse(2).unwrap();
+        assert_eq!(
+            binary.pools,
+            Pools {
+                yes_capital: 100,
+                no_capital: 50
+            }
+        );
+        assert_eq!(binary.positions[0].yes_capital, 70);
+        assert_eq!(binary.positions[0].no_capital, 50);
+        assert_eq!(binary.positions[1].yes_capital, 30);
+        assert_eq!(binary.positions[1].no_capital, 0);
+
+        let settlement = settle_categorical(2, &ledger.pools, 150, &test_params()).unwrap();
+        let result = settle_participants(&binary, &settlement).unwrap();
+        assert_eq!(result.payouts[0], 70 + 35);
+        assert_eq!(result.payouts[1], 30 + 15);
+    }
+
+    #[test]
+    fn categorical_payouts_stay_within_vault_for_every_outcome_count() {
+        let mut x = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = move || {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
         };
-        let market = create_market(
+
+        for n in 2..=MAX_OUTCOMES as u8 {
+            for _ in 0..200 {
+                let mut ledger = CategoricalLedger::new(n);
+                let participants = 1 + (next() as usize % MAX_PARTICIPANTS);
+                for i in 0..participants {
+                    let outcome = (next() % n as u64) as u8;
+                    ledger
+                        .record_stake(owner(i), outcome, 1 + (next() % 1_000_000) as u128)
+                        .unwrap();
+                }
+                let winning = (next() % n as u64) as u8;
+                if ledger.pools.capital[winning as usize] == 0 {
+                    ledger.record_stake(owner(0), winning, 1).unwrap();
+                }
+                let total: u128 = ledger.pools.capital.iter().sum();
+                let available = (next() as u128) % (total + 1);
+                let params = MarketParams {
+                    protocol_fee_bps: next() % 1_001,
+                    creator_fee_bps: next() % 1_001,
+                    ..test_params()
+                };
+
+                let settlement =
+                    settle_categorical(winning, &ledger.pools, available, &params).unwrap();
+                assert!(
+                    settlement.winner_payout_total
+                        + settlement.protocol_fee_paid
+                        + settlement.creator_fee_paid
+                        <= available
+                );
+                assert_eq!(
+                    settlement.winner_capital_total + settlement.loser_capital_total,
+                    total
+                );
+
+                let binary = ledger.collapse(winning).unwrap();
+                let result = settle_participants(&binary, &settlement).unwrap();
+                assert!(result.total_paid <= settlement.winner_payout_total);
+            }
+        }
+    }
+
+    fn sourced_at(source_id: u32, market_cap_quote_units: u128) -> SourcedSnapshot {
+        SourcedSnapshot {
+            source_id,
+            snapshot: bucket_snapshot(market_cap_quote_units),
+        }
+    }
+
+    #[test]
+    fn multi_source_resolves_categorical_and_scalar_rules() {
+        let policy = AgreementPolicy::WithinBps { tolerance_bps: 100 };
+        let mut market = bucket_market(&[1_000_000, 5_000_000]);
+        market.params.agreement_policy = policy;
+
+        let sources = [sourced_at(1, 2_000_000), sourced_at(2, 2_010_000)];
+        assert_eq!(
+            resolve_with_sources(&market, &sources).unwrap(),
+            Resolution::Resolved(ResolvedOutcome::Bucket(1))
+        );
+        // Within 1%, but on either side of the 5M bound
+        let sources = [sourced_at(1, 4_990_000), sourced_at(2, 5_010_000)];
+        assert_eq!(
+            resolve_with_sources(&market, &sources).unwrap(),
+            Resolution::Disputed(Dispute {
+                field: DisputeField::Outcome,
+                primary_source_id: 1,
+                conflicting_source_id: 2,
+                primary_value: 1,
+                conflicting_value: 2,
+            })
+        );
+
+        // Scalar shares follow the value, so the primary's share is used
+        let mut market = scalar_market(1_000, 5_000);
+        market.params.agreement_policy = policy;
+        let sources = [sourced_at(1, 2_000), sourced_at(2, 2_010)];
+        assert_eq!(
+            resolve_with_sources(&market, &sources).unwrap(),
+            Resolution::Resolved(ResolvedOutcome::Scalar {
+                long_num: 1_000,
+                long_den: 4_000,
+            })
+        );
+        let sources = [sourced_at(1, 2_000), sourced_at(2, 2_100)];
+        assert!(matches!(
+            resolve_with_sources(&market, &sources).unwrap(),
+            Resolution::Disputed(Dispute {
+                field: DisputeField::MarketCap,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn categorical_market_state_settles_winning_bucket() {
+        let mut state = MarketState::new(bucket_market(&[1_000_000, 5_000_000]));
+        assert_eq!(
+            state.stake(owner(0), Outcome::Yes, 10, 120),
+            Err(PredictionError::NotBinaryMarket)
+        );
+        assert_eq!(
+            open_market_state().stake_outcome(owner(0), 0, 10, 120),
+            Err(PredictionError::NotCategoricalMarket)
+        );
+        state.stake_outcome(owner(0), 0, 300, 120).unwrap();
+        state.stake_outcome(owner(1), 1, 100, 120).unwrap();
+        state.stake_outcome(owner(0), 2, 600, 121).unwrap();
+        assert_eq!(
+            state.stake_outcome(owner(1), 3, 1, 121),
+            Err(PredictionError::InvalidOutcomeIndex)
+        );
+        assert_eq!(state.ledger.num_participants, 0);
+
+        state.close(200).unwrap();
+        assert_eq!(
+            state.resolve(&bucket_snapshot(2_000_000), 210),
+            Ok(ResolvedOutcome::Bucket(1))
+        );
+        let settlement = state.settle(1_000, 211).unwrap();
+        assert_eq!(settlement.winner_capital_total, 100);
+        assert_eq!(settlement.loser_capital_total, 900);
+        assert_eq!(settlement.winner_payout_total, 1_000);
+
+        let ledger = state.categorical.collapse(1).unwrap();
+        let payouts = settle_participants(&ledger, &settlement).unwrap();
+        assert_eq!(payouts.payouts[..2], [0, 1_000]);
+    }
+
+    #[test]
+    fn categorical_market_state_cancels_and_refunds() {
+        // Nothing staked on the winning bucket
+        let mut state = MarketState::new(bucket_market(&[1_000_000, 5_000_000]));
+        state.stake_outcome(owner(0), 0, 300, 120).unwrap();
+        state.stake_outcome(owner(1), 2, 600, 120).unwrap();
+        state.close(200).unwrap();
+        state.resolve(&bucket_snapshot(2_000_000), 210).unwrap();
+        assert_eq!(
+            state.settle(900, 211),
+            Err(PredictionError::EmptyWinnerSide)
+        );
+        state.cancel(CancelReason::EmptyWinnerSide, 211).unwrap();
+        assert_eq!(state.pay_refunds(900).unwrap().payouts[..2], [300, 600]);
+
+        // A bucket below min_side_stake is caught by the crank at close
+        let mut registry = test_registry();
+        let rule = bucket_market(&[1_000_000, 5_000_000]).rule;
+        let id = registry
+            .create_market(
+                migrated_token(mint(13)),
+                100,
+                200,
+                rule,
+                liquidity_params(0, 10),
+            )
+            .unwrap();
+        let state = registry.get_mut(id).unwrap();
+        state.stake_outcome(owner(0), 0, 300, 120).unwrap();
+        state.stake_outcome(owner(1), 1, 5, 120).unwrap();
+        state.stake_outcome(owner(1), 2, 600, 120).unwrap();
+
+        let outcome = registry.prediction_crank(200, 8);
+        assert_eq!(outcome.num_liquidity_cancelled, 1);
+        let state = registry.get_mut(id).unwrap();
+        assert_eq!(
+            state.cancel_reason,
+            Some(CancelReason::InsufficientLiquidity)
+        );
+        let refunds = state.pay_refunds(905).unwrap();
+        assert_eq!(refunds.payouts[..2], [300, 605]);
+        assert_eq!(registry.garbage_collect_finished(), 1);
+    }
+
+    fn scalar_market(lower_quote_units: u128, upper_quote_units: u128) -> Market {
+        let token = migrated_token(mint(13));
+        create_market(
             token,
-            7,
-            10,
-            20,
-            MarketRule::MarketCapAtCloseAtLeast {
-                target_quote_units: 5_000_000,
+            &test_policy(),
+            31,
+            100,
+            200,
+            MarketRule::MarketCapScalar {
+                lower_quote_units,
+                upper_quote_units,
             },
+            test_params(),
         )
-        .unwrap();
-        let snapshot = TokenSnapshot {
-            mint: mint(2),
-            migrated_to_pumpswap: true,
-            market_cap_quote_units: 6_000_000,
-            snapshot_slot: 21,
+        .unwrap()
+    }
+
+    #[test]
+    fn resolves_scalar_share_clamped_to_range() {
+        let market = scalar_market(1_000, 5_000);
+        for (mcap, share) in [
+            (0, (0, 4_000)),
+            (1_000, (0, 4_000)),
+            (2_000, (1_000, 4_000)),
+            (5_000, (4_000, 4_000)),
+            (u128::MAX, (4_000, 4_000)),
+        ] {
+            assert_eq!(resolve_scalar(&market, &bucket_snapshot(mcap)), Ok(share));
+        }
+
+        assert_eq!(
+            resolve_outcome(&market, &bucket_snapshot(0)),
+            Err(PredictionError::NotBinaryMarket)
+        );
+        assert_eq!(
+            resolve_scalar(&bucket_market(&[10]), &bucket_snapshot(0)),
+            Err(PredictionError::NotScalarMarket)
+        );
+
+        let token = migrated_token(mint(13));
+        let rule = MarketRule::MarketCapScalar {
+            lower_quote_units: 5_000,
+            upper_quote_units: 5_000,
         };
+        assert_eq!(
+            create_market(token, &test_policy(), 31, 100, 200, rule, test_params()),
+            Err(PredictionError::InvalidScalarRange)
+        );
+    }
+
+    #[test]
+    fn scalar_pot_splits_linearly() {
+        let market = scalar_market(1_000, 5_000);
         let pools = Pools {
-            yes_capital: 100,
-            no_capital: 80,
+            yes_capital: 600,
+            no_capital: 400,
         };
 
-        // Only 130 units are available, so winner profit is partially funded.
-        let settlement = settle_market(&market, &snapshot, pools, 130).unwrap();
-        assert_eq!(settlement.h_num, 30);
-        assert_eq!(settlement.h_den, 80);
-        assert_eq!(settlement.winner_profit_paid, 30);
-        assert_eq!(settlement.winner_payout_total, 130);
+        // 25% LONG: LONG keeps 250 of its 600, SHORT gains 350
+        let settlement =
+            settle_scalar_market(&market, &bucket_snapshot(2_000), pools, 1_000).unwrap();
+        assert_eq!((settlement.long_claim, settlement.short_claim), (250, 750));
+        assert_eq!(settlement.profit_claim_total, 350);
+        assert_eq!((settlement.h_num, settlement.h_den), (350, 350));
+        assert_eq!(settlement.long_payout_total, 250);
+        assert_eq!(settlement.short_payout_total, 750);
+
+        // Full range matches the binary YES settlement
+        let settlement = settle_scalar(1, 1, pools, 1_000, &test_params()).unwrap();
+        let binary = settle_outcome(Outcome::Yes, pools, 1_000, &test_params()).unwrap();
+        assert_eq!(settlement.long_payout_total, binary.winner_payout_total);
+        assert_eq!(settlement.short_payout_total, 0);
+
+        // Stressed vault: senior claims first, the gain is haircut
+        let settlement = settle_scalar(1, 4, pools, 800, &test_params()).unwrap();
+        assert_eq!(settlement.long_capital_paid, 250);
+        assert_eq!(settlement.short_capital_paid, 400);
+        assert_eq!((settlement.h_num, settlement.h_den), (150, 350));
+        assert_eq!(settlement.short_payout_total, 550);
+
+        assert_eq!(
+            settle_scalar(
+                1,
+                1,
+                Pools {
+                    yes_capital: 0,
+                    no_capital: 400
+                },
+                400,
+                &test_params()
+            ),
+            Err(PredictionError::EmptyWinnerSide)
+        );
     }
 
     #[test]
-    fn never_pays_more_than_available_vault() {
-        let token = TokenStatus {
-            mint: mint(3),
-            migrated_to_pumpswap: true,
+    fn scalar_settlement_handles_large_pots_and_wide_ranges() {
+        // pot * long_num alone is far past u128
+        let pools = Pools {
+            yes_capital: 1 << 120,
+            no_capital: 1 << 120,
+        };
+        let settlement =
+            settle_scalar(3 << 125, 1 << 127, pools, 1 << 121, &test_params()).unwrap();
+        assert_eq!(settlement.long_claim, 3 << 119);
+        assert_eq!(settlement.short_claim, 1 << 119);
+        assert_eq!(settlement.long_payout_total, 3 << 119);
+        assert_eq!(settlement.short_payout_total, 1 << 119);
+
+        // The widest possible range, resolved from a snapshot
+        let market = scalar_market(0, u128::MAX);
+        let settlement = settle_scalar_market(
+            &market,
+            &bucket_snapshot(u128::MAX / 4 * 3),
+            pools,
+            1 << 121,
+        )
+        .unwrap();
+        assert_eq!(settlement.long_claim + settlement.short_claim, 1 << 121);
+        assert!(settlement.long_claim >= (3 << 119) - 1 && settlement.long_claim <= 3 << 119);
+        assert!(settlement.long_payout_total + settlement.short_payout_total <= 1 << 121);
+    }
+
+    #[test]
+    fn scalar_market_state_settles_and_refunds() {
+        let mut state = MarketState::new(scalar_market(1_000, 5_000));
+        state.stake(owner(0), Outcome::Yes, 600, 120).unwrap();
+        state.stake(owner(1), Outcome::No, 400, 120).unwrap();
+        assert_eq!(
+            state.unstake(owner(0), Outcome::Yes, 100, 121),
+            Err(PredictionError::NotBinaryMarket)
+        );
+        state.close(200).unwrap();
+        assert_eq!(
+            state.resolve(&bucket_snapshot(2_000), 210),
+            Ok(ResolvedOutcome::Scalar {
+                long_num: 1_000,
+                long_den: 4_000
+            })
+        );
+        assert_eq!(
+            state.settle(1_000, 211),
+            Err(PredictionError::NotBinaryMarket)
+        );
+        let settlement = state.settle_scalar(1_000, 211).unwrap();
+        assert_eq!(state.phase, MarketPhase::Settled);
+        assert_eq!(state.scalar_settlement, Some(settlement));
+        let payouts = settle_scalar_participants(&state.ledger, &settlement).unwrap();
+        assert_eq!(payouts.payouts[..2], [250, 750]);
+        assert_eq!(
+            open_market_state().settle_scalar(0, 120),
+            Err(PredictionError::InvalidTransition)
+        );
+
+        // LONG only, but the share leaves SHORT a claim: nobody to pay it to
+        let mut state = MarketState::new(scalar_market(1_000, 5_000));
+        state.stake(owner(0), Outcome::Yes, 600, 120).unwrap();
+        state.close(200).unwrap();
+        state.resolve(&bucket_snapshot(2_000), 210).unwrap();
+        assert_eq!(
+            state.settle_scalar(600, 211),
+            Err(PredictionError::EmptyWinnerSide)
+        );
+        state.cancel(CancelReason::EmptyWinnerSide, 211).unwrap();
+        assert_eq!(state.pay_refunds(600).unwrap().payouts[0], 600);
+
+        // At the upper bound LONG takes the whole pot, so a SHORT-free market settles
+        let mut state = MarketState::new(scalar_market(1_000, 5_000));
+        state.stake(owner(0), Outcome::Yes, 600, 120).unwrap();
+        state.close(200).unwrap();
+        state.resolve(&bucket_snapshot(5_000), 210).unwrap();
+        assert_eq!(
+            state.cancel(CancelReason::EmptyWinnerSide, 211),
+            Err(PredictionError::CancelNotAllowed)
+        );
+        assert!(state.settle_scalar(600, 211).is_ok());
+    }
+
+    #[test]
+    fn scalar_payouts_are_floored_and_conserve_value() {
+        let mut x = 0xD1B5_4A32_D192_ED03u64;
+        let mut next = move || {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+
+        for _ in 0..500 {
+            let mut ledger = ParticipantLedger::new();
+            let n = 2 + (next() as usize % (MAX_PARTICIPANTS - 1));
+            for i in 0..n {
+                let side = match i {
+                    0 => Outcome::Yes,
+                    1 => Outcome::No,
+                    _ if next() % 2 == 0 => Outcome::Yes,
+                    _ => Outcome::No,
+                };
+                ledger
+                    .record_stake(owner(i), side, 1 + (next() % 1_000_000) as u128)
+                    .unwrap();
+            }
+            let long_den = 1 + (next() % 1_000_000) as u128;
+            let long_num = (next() as u128) % (long_den + 1);
+            let pot = ledger.pools.yes_capital + ledger.pools.no_capital;
+            let available = (next() as u128) % (pot + 1);
+            let params = MarketParams {
+                protocol_fee_bps: next() % 1_001,
+                creator_fee_bps: next() % 1_001,
+                ..test_params()
+            };
+
+            let settlement =
+                settle_scalar(long_num, long_den, ledger.pools, available, &params).unwrap();
+            let paid = settlement.long_payout_total
+                + settlement.short_payout_total
+                + settlement.protocol_fee_paid
+                + settlement.creator_fee_paid;
+            assert!(paid <= available);
+            assert_eq!(settlement.long_claim + settlement.short_claim, pot);
+
+            // A fully funded vault pays out exactly the pot (fees included)
+            let settlement = settle_scalar(long_num, long_den, ledger.pools, pot, &params).unwrap();
+            let fees = settlement.protocol_fee_paid + settlement.creator_fee_paid;
+            assert_eq!(
+                settlement.long_payout_total + settlement.short_payout_total + fees,
+                pot
+            );
+
+            let result = settle_scalar_participants(&ledger, &settlement).unwrap();
+            assert_eq!(
+                result.total_paid + result.rounding_dust,
+                settlement.long_payout_total + settlement.short_payout_total
+            );
+        }
+    }
+
+    #[test]
+    fn implied_probability_is_pool_share() {
+        let pools = Pools {
+            yes_capital: 300,
+            no_capital: 100,
         };
+        assert_eq!(implied_probability(pools, Outcome::Yes), Some((300, 400)));
+        assert_eq!(implied_probability(pools, Outcome::No), Some((100, 400)));
+        assert_eq!(
+            implied_probability(
+                Pools {
+                    yes_capital: 0,
+                    no_capital: 0
+                },
+                Outcome::Yes
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn payout_multiplier_includes_hypothetical_stake_and_fees() {
+        let market = price_market(1_000_000);
+        let current = ledger_of(Pools {
+            yes_capital: 300,
+            no_capital: 100,
+        });
+        // 100 more on NO: NO pool 200 shares YES's 300 -> 500 / 200
+        assert_eq!(
+            payout_multiplier(&market, &current, Outcome::No, 100),
+            Ok((500, 200))
+        );
+
+        // Matches what settlement would actually pay for that stake
+        let mut ledger = ParticipantLedger::new();
+        ledger.record_stake(owner(0), Outcome::Yes, 300).unwrap();
+        ledger.record_stake(owner(1), Outcome::No, 100).unwrap();
+        ledger.record_stake(owner(2), Outcome::No, 100).unwrap();
+        let settlement = settle_outcome(Outcome::No, ledger.pools, 500, &test_params()).unwrap();
+        let result = settle_participants(&ledger, &settlement).unwrap();
+        assert_eq!(result.payouts[2], 100 * 500 / 200);
+
+        let mut fee_market = market;
+        fee_market.params.protocol_fee_bps = 1_000;
+        assert_eq!(
+            payout_multiplier(&fee_market, &current, Outcome::Yes, 100),
+            Ok((490, 400))
+        );
+        assert_eq!(
+            payout_multiplier(&market, &current, Outcome::Yes, 0),
+            Err(PredictionError::ZeroStake)
+        );
+    }
+
+    #[test]
+    fn projected_h_reflects_current_vault() {
+        let market = price_market(1_000_000);
+        let current = ledger_of(Pools {
+            yes_capital: 300,
+            no_capital: 100,
+        });
+        assert_eq!(
+            projected_h(&market, &current, Outcome::Yes, 400),
+            Ok((100, 100))
+        );
+        assert_eq!(
+            projected_h(&market, &current, Outcome::Yes, 340),
+            Ok((40, 100))
+        );
+        assert_eq!(
+            projected_h(&market, &current, Outcome::No, 150),
+            Ok((50, 300))
+        );
+    }
+
+    /// Ledger with one YES and one NO position matching `pools`
+    fn ledger_of(pools: Pools) -> ParticipantLedger {
+        let mut ledger = ParticipantLedger::new();
+        if pools.yes_capital > 0 {
+            ledger
+                .record_stake(owner(0), Outcome::Yes, pools.yes_capital)
+                .unwrap();
+        }
+        if pools.no_capital > 0 {
+            ledger
+                .record_stake(owner(1), Outcome::No, pools.no_capital)
+                .unwrap();
+        }
+        ledger
+    }
+
+    #[test]
+    fn stakes_rejected_inside_late_bet_window() {
         let market = create_market(
-            token,
-            99,
-            5,
-            15,
+            migrated_token(mint(11)),
+            &test_policy(),
+            12,
+            100,
+            200,
             MarketRule::MarketCapAtCloseAtLeast {
-                target_quote_units: 10_000,
+                target_quote_units: 1_000,
+            },
+            MarketParams {
+                betting_cutoff_slots: 20,
+                ..test_params()
             },
         )
         .unwrap();
-        let snapshot = TokenSnapshot {
-            mint: mint(3),
-            migrated_to_pumpswap: true,
-            market_cap_quote_units: 100,
-            snapshot_slot: 16,
+        assert_eq!(market.betting_cutoff_slot, 180);
+
+        let mut state = MarketState::new(market);
+        state.stake(owner(1), Outcome::Yes, 10, 179).unwrap();
+        assert_eq!(
+            state.stake(owner(2), Outcome::No, 10, 180),
+            Err(PredictionError::BettingCutoffPassed)
+        );
+        assert_eq!(
+            state.stake(owner(2), Outcome::No, 10, 199),
+            Err(PredictionError::BettingCutoffPassed)
+        );
+        assert_eq!(state.ledger.pools.no_capital, 0);
+    }
+
+    #[test]
+    fn rejects_cutoff_not_before_close() {
+        let token = migrated_token(mint(11));
+        let rule = MarketRule::MarketCapAtCloseAtLeast {
+            target_quote_units: 1_000,
+        };
+        for betting_cutoff_slots in [0, 100, 101, u64::MAX] {
+            let params = MarketParams {
+                betting_cutoff_slots,
+                ..test_params()
+            };
+            assert_eq!(
+                create_market(token, &test_policy(), 12, 100, 200, rule, params),
+                Err(PredictionError::InvalidBettingCutoff)
+            );
+        }
+        let params = MarketParams {
+            betting_cutoff_slots: 99,
+            ..test_params()
+        };
+        assert_eq!(
+            create_market(token, &test_policy(), 12, 100, 200, rule, params)
+                .map(|m| m.betting_cutoff_slot),
+            Ok(101)
+        );
+    }
+
+    fn exit_fee_market_state(exit_fee_bps: u64) -> MarketState {
+        let mut state = open_market_state();
+        state.market.params.exit_fee_bps = exit_fee_bps;
+        state.market.params.betting_cutoff_slots = 20;
+        state.market.betting_cutoff_slot = 180;
+        state
+    }
+
+    #[test]
+    fn unstake_refunds_stake_minus_exit_fee() {
+        let mut state = exit_fee_market_state(100);
+        state.stake(owner(1), Outcome::Yes, 1_000, 120).unwrap();
+        state.stake(owner(2), Outcome::No, 500, 120).unwrap();
+
+        // 1% of 250 = 2.5, rounded up
+        assert_eq!(state.unstake(owner(1), Outcome::Yes, 250, 130), Ok(247));
+        assert_eq!(state.ledger.positions[0].yes_capital, 750);
+        assert_eq!(state.ledger.pools.yes_capital, 750);
+        assert_eq!(state.ledger.exit_fees, 3);
+
+        assert_eq!(
+            state.unstake(owner(1), Outcome::No, 1, 130),
+            Err(PredictionError::InsufficientStake)
+        );
+        assert_eq!(
+            state.unstake(owner(3), Outcome::Yes, 1, 130),
+            Err(PredictionError::InsufficientStake)
+        );
+        assert_eq!(
+            state.unstake(owner(1), Outcome::Yes, 0, 130),
+            Err(PredictionError::ZeroStake)
+        );
+        assert_eq!(
+            state.unstake(owner(1), Outcome::Yes, 1, 180),
+            Err(PredictionError::BettingCutoffPassed)
+        );
+        assert_eq!(state.ledger.exit_fees, 3);
+    }
+
+    #[test]
+    fn exit_fees_fund_winning_side() {
+        let mut state = exit_fee_market_state(1_000);
+        state.stake(owner(1), Outcome::Yes, 100, 120).unwrap();
+        state.stake(owner(2), Outcome::Yes, 100, 120).unwrap();
+        state.stake(owner(3), Outcome::No, 100, 120).unwrap();
+        // Owner 2 leaves YES; the 10 fee stays in the pot
+        assert_eq!(state.unstake(owner(2), Outcome::Yes, 100, 130), Ok(90));
+
+        state.close(200).unwrap();
+        state.resolve(&mcap_snapshot(5_000, 201), 205).unwrap();
+        let settlement = state.settle(210, 206).unwrap();
+        assert_eq!(settlement.winner_capital_total, 100);
+        assert_eq!(settlement.loser_capital_total, 110);
+        assert_eq!(settlement.winner_payout_total, 210);
+
+        let result = settle_participants(&state.ledger, &settlement).unwrap();
+        assert_eq!(result.payouts[0], 210);
+        assert_eq!(result.payouts[1], 0);
+        assert_eq!(result.total_paid, 210);
+    }
+
+    #[test]
+    fn odds_queries_count_exit_fees() {
+        let mut state = exit_fee_market_state(1_000);
+        state.stake(owner(1), Outcome::Yes, 100, 120).unwrap();
+        state.stake(owner(2), Outcome::Yes, 100, 120).unwrap();
+        state.stake(owner(3), Outcome::No, 100, 120).unwrap();
+        state.unstake(owner(2), Outcome::Yes, 100, 130).unwrap();
+
+        // The 10 exit fee joins the losing NO pool: 100 + 110 over 100
+        let multiplier = payout_multiplier(&state.market, &state.ledger, Outcome::Yes, 1);
+        assert_eq!(multiplier, Ok((211, 101)));
+        assert_eq!(
+            projected_h(&state.market, &state.ledger, Outcome::Yes, 150),
+            Ok((50, 110))
+        );
+
+        // and matches what settlement pays
+        state.close(200).unwrap();
+        state.resolve(&mcap_snapshot(5_000, 201), 205).unwrap();
+        let settlement = state.settle(150, 206).unwrap();
+        assert_eq!((settlement.h_num, settlement.h_den), (50, 110));
+    }
+
+    #[test]
+    fn cancelled_market_refunds_exit_fees_to_payers() {
+        let mut state = exit_fee_market_state(1_000);
+        state.stake(owner(1), Outcome::Yes, 100, 120).unwrap();
+        state.stake(owner(2), Outcome::No, 100, 120).unwrap();
+        assert_eq!(state.unstake(owner(2), Outcome::No, 60, 130), Ok(54));
+        assert_eq!(state.ledger.positions[1].exit_fees, 6);
+
+        state.close(200).unwrap();
+        state
+            .cancel(CancelReason::SnapshotUnavailable, 301)
+            .unwrap();
+
+        // Owner 2 gets the 40 still staked plus the 6 fee: 54 + 46 = 100 in total
+        let refunds = state.refunds(146).unwrap();
+        assert_eq!(&refunds.payouts[..2], &[100, 46]);
+        assert_eq!(refunds.total_paid, 146);
+        assert_eq!(refunds.rounding_dust, 0);
+
+        // Shortfalls scale fees with stakes
+        let refunds = state.refunds(73).unwrap();
+        assert_eq!(&refunds.payouts[..2], &[50, 23]);
+    }
+
+    #[test]
+    fn rejects_exit_fee_above_stake() {
+        let token = migrated_token(mint(11));
+        let rule = MarketRule::MarketCapAtCloseAtLeast {
+            target_quote_units: 1,
+        };
+        let params = MarketParams {
+            exit_fee_bps: 10_001,
+            ..test_params()
+        };
+        assert_eq!(
+            create_market(token, &test_policy(), 12, 100, 200, rule, params),
+            Err(PredictionError::InvalidFee)
+        );
+    }
+
+    #[test]
+    fn stake_unstake_sequences_never_drain_more_than_deposited() {
+        let mut x = 0xA076_1D64_78BD_642Fu64;
+        let mut next = move || {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+
+        for round in 0..200 {
+            let mut state = exit_fee_market_state([0, 1, 30, 10_000][round % 4]);
+            let mut deposited = 0u128;
+            let mut withdrawn = 0u128;
+
+            for _ in 0..100 {
+                let who = owner((next() % 4) as usize);
+                let side = if next() % 2 == 0 {
+                    Outcome::Yes
+                } else {
+                    Outcome::No
+                };
+                let amount = (next() % 1_000) as u128;
+                if next() % 2 == 0 {
+                    if state.stake(who, side, amount, 150).is_ok() {
+                        deposited += amount;
+                    }
+                } else if let Ok(refund) = state.unstake(who, side, amount, 150) {
+                    assert!(refund <= amount);
+                    withdrawn += refund;
+                }
+
+                // Whatever left the market plus whatever is still in it is
+                // exactly what came in
+                assert!(withdrawn <= deposited);
+                let pools = state.ledger.pools;
+                assert_eq!(
+                    withdrawn + pools.yes_capital + pools.no_capital + state.ledger.exit_fees,
+                    deposited
+                );
+                let fees: u128 = state.ledger.positions.iter().map(|p| p.exit_fees).sum();
+                assert_eq!(fees, state.ledger.exit_fees);
+            }
+        }
+    }
+
+    fn liquidity_params(min_total_stake: u128, min_side_stake: u128) -> MarketParams {
+        MarketParams {
+            min_total_stake,
+            min_side_stake,
+            ..test_params()
+        }
+    }
+
+    #[test]
+    fn rejects_inconsistent_stake_thresholds() {
+        let token = migrated_token(mint(11));
+        let rule = MarketRule::MarketCapAtCloseAtLeast {
+            target_quote_units: 1,
+        };
+        for params in [liquidity_params(199, 100), liquidity_params(0, u128::MAX)] {
+            assert_eq!(
+                create_market(token, &test_policy(), 12, 100, 200, rule, params),
+                Err(PredictionError::InvalidStakeThreshold)
+            );
+        }
+        for params in [
+            liquidity_params(200, 100),
+            liquidity_params(0, 100),
+            liquidity_params(1_000, 0),
+        ] {
+            assert!(create_market(token, &test_policy(), 12, 100, 200, rule, params).is_ok());
+        }
+    }
+
+    #[test]
+    fn settle_market_refuses_thin_or_lopsided_pools() {
+        let mut market = mcap_market_with_policy(AgreementPolicy::Exact);
+        market.params = liquidity_params(1_000, 10);
+        let snapshot = mcap_snapshot(5_000, 205);
+
+        let lopsided = Pools {
+            yes_capital: 1,
+            no_capital: 1_000_000,
+        };
+        let thin = Pools {
+            yes_capital: 400,
+            no_capital: 500,
+        };
+        for pools in [lopsided, thin] {
+            assert_eq!(
+                settle_market(&market, &snapshot, pools, 1_000_000),
+                Err(PredictionError::InsufficientLiquidity)
+            );
+        }
+        let enough = Pools {
+            yes_capital: 10,
+            no_capital: 990,
         };
+        assert!(settle_market(&market, &snapshot, enough, 1_000).is_ok());
+    }
+
+    #[test]
+    fn market_below_thresholds_becomes_refund_only_at_close() {
+        let mut state = open_market_state();
+        state.market.params = liquidity_params(0, 10);
+        state.stake(owner(1), Outcome::Yes, 1, 120).unwrap();
+        state.stake(owner(2), Outcome::No, 1_000_000, 120).unwrap();
+
+        state.close(200).unwrap();
+        assert_eq!(state.phase, MarketPhase::Cancelled);
+        assert_eq!(
+            state.cancel_reason,
+            Some(CancelReason::InsufficientLiquidity)
+        );
+        assert_eq!(
+            state.resolve(&mcap_snapshot(5_000, 201), 205),
+            Err(PredictionError::InvalidTransition)
+        );
+
+        let refunds = state.refunds(1_000_001).unwrap();
+        assert_eq!(refunds.payouts[0], 1);
+        assert_eq!(refunds.payouts[1], 1_000_000);
+    }
+
+    #[test]
+    fn liquidity_threshold_applies_to_scalar_and_categorical() {
+        let mut market = scalar_market(1_000, 5_000);
+        market.params = liquidity_params(0, 10);
         let pools = Pools {
-            yes_capital: 70,
-            no_capital: 30,
+            yes_capital: 5,
+            no_capital: 100,
         };
-        let available = 20;
+        assert_eq!(
+            settle_scalar_market(&market, &bucket_snapshot(2_000), pools, 105),
+            Err(PredictionError::InsufficientLiquidity)
+        );
 
-        let settlement = settle_market(&market, &snapshot, pools, available).unwrap();
-        assert!(settlement.winner_payout_total <= available);
+        // Every outcome needs min_side_stake, whichever one wins
+        let mut market = bucket_market(&[1_000_000, 5_000_000]);
+        market.params = liquidity_params(0, 10);
+        let mut pools = CategoricalPools::new(3);
+        pools.capital[..3].copy_from_slice(&[300, 5, 600]);
+        for mcap in [0, 2_000_000, 5_000_000] {
+            assert_eq!(
+                settle_categorical_market(&market, &bucket_snapshot(mcap), &pools, 905),
+                Err(PredictionError::InsufficientLiquidity)
+            );
+        }
+        pools.capital[1] = 10;
+        assert!(settle_categorical_market(&market, &bucket_snapshot(0), &pools, 910).is_ok());
+
+        // And the total minimum must cover every outcome's minimum
+        let token = migrated_token(mint(13));
+        let rule = market.rule;
+        assert_eq!(
+            create_market(
+                token,
+                &test_policy(),
+                22,
+                100,
+                200,
+                rule,
+                liquidity_params(29, 10)
+            ),
+            Err(PredictionError::InvalidStakeThreshold)
+        );
+        assert!(create_market(
+            token,
+            &test_policy(),
+            22,
+            100,
+            200,
+            rule,
+            liquidity_params(30, 10)
+        )
+        .is_ok());
     }
-}
 
+    #[test]
+    fn categorical_refunds_cover_every_outcome() {
+        let mut ledger = CategoricalLedger::new(3);
+        ledger.record_stake(owner(0), 0, 50).unwrap();
+        ledger.record_stake(owner(0), 2, 70).unwrap();
+        ledger.record_stake(owner(1), 1, 30).unwrap();
+
+        let refunds = refund_categorical_participants(&ledger, 150).unwrap();
+        assert_eq!(refunds.payouts[..2], [120, 30]);
+        assert_eq!(refunds.rounding_dust, 0);
+
+        // Vault short: pro-rata across all of a participant's outcomes
+        let refunds = refund_categorical_participants(&ledger, 100).unwrap();
+        assert_eq!(refunds.payouts[..2], [80, 20]);
+        assert!(refunds.total_paid <= 100);
+    }
+
+    /// Registries are large at production sizes; tests use a small boxed one
+    type TestRegistry = MarketRegistry<8, 1>;
+
+    fn test_registry() -> Box<TestRegistry> {
+        Box::new(TestRegistry::new_sized(test_policy()))
+    }
+
+    fn registry_rule() -> MarketRule {
+        MarketRule::MarketCapAtCloseAtLeast {
+            target_quote_units: 1_000,
+        }
+    }
+
+    #[test]
+    fn registry_assigns_monotonic_ids_until_full() {
+        let mut registry = test_registry();
+        for expected in 0..TestRegistry::CAPACITY as u64 {
+            let id = registry
+                .create_market(
+                    migrated_token(mint(11)),
+                    100,
+                    200,
+                    registry_rule(),
+                    test_params(),
+                )
+                .unwrap();
+            assert_eq!(id, expected);
+            assert_eq!(registry.get(id).unwrap().market.market_id, id);
+        }
+        assert_eq!(registry.num_used_markets as usize, TestRegistry::CAPACITY);
+        assert_eq!(
+            registry.create_market(
+                migrated_token(mint(11)),
+                100,
+                200,
+                registry_rule(),
+                test_params()
+            ),
+            Err(PredictionError::RegistryFull)
+        );
+
+        // An invalid market consumes neither an id nor a slot
+        let mut registry = test_registry();
+        assert_eq!(
+            registry.create_market(
+                migrated_token(mint(11)),
+                200,
+                200,
+                registry_rule(),
+                test_params()
+            ),
+            Err(PredictionError::InvalidCloseSlot)
+        );
+        assert_eq!(registry.next_market_id, 0);
+        assert_eq!(registry.num_used_markets, 0);
+    }
+
+    #[test]
+    fn registry_collects_settled_markets_without_recycling_ids() {
+        let mut registry = test_registry();
+        let settled = registry
+            .create_market(
+                migrated_token(mint(11)),
+                100,
+                200,
+                registry_rule(),
+                test_params(),
+            )
+            .unwrap();
+        let open = registry
+            .create_market(
+                migrated_token(mint(11)),
+                100,
+                300,
+                registry_rule(),
+                test_params(),
+            )
+            .unwrap();
+
+        let state = registry.get_mut(settled).unwrap();
+        state.stake(owner(1), Outcome::Yes, 100, 120).unwrap();
+        state.stake(owner(2), Outcome::No, 40, 120).unwrap();
+        registry.close(settled, 200).unwrap();
+        let state = registry.get_mut(settled).unwrap();
+        state.resolve(&mcap_snapshot(5_000, 201), 205).unwrap();
+        state.settle(140, 206).unwrap();
+
+        assert_eq!(
+            registry.close(open, 250),
+            Err(PredictionError::CloseSlotNotReached)
+        );
+        assert_eq!(registry.garbage_collect_finished(), 1);
+        assert_eq!(registry.get(settled), Err(PredictionError::MarketNotFound));
+        assert_eq!(
+            registry.close(settled, 300),
+            Err(PredictionError::MarketNotFound)
+        );
+        assert_eq!(registry.get(open).unwrap().phase, MarketPhase::Open);
+        assert_eq!(registry.num_used_markets, 1);
+        assert_eq!(registry.garbage_collect_finished(), 0);
+
+        // The freed slot is reused, under a fresh id
+        let slot = registry.free_head;
+        let id = registry
+            .create_market(
+                migrated_token(mint(11)),
+                100,
+                200,
+                registry_rule(),
+                test_params(),
+            )
+            .unwrap();
+        assert_eq!(id, 2);
+        assert_eq!(registry.find(id), Some(slot));
+    }
+
+    #[test]
+    fn registry_collects_cancelled_markets_once_refunded() {
+        let mut registry = test_registry();
+        let id = registry
+            .create_market(
+                migrated_token(mint(11)),
+                100,
+                200,
+                registry_rule(),
+                test_params(),
+            )
+            .unwrap();
+        let state = registry.get_mut(id).unwrap();
+        state.stake(owner(1), Outcome::Yes, 100, 120).unwrap();
+        state.close(200).unwrap();
+        state
+            .cancel(CancelReason::SnapshotUnavailable, 301)
+            .unwrap();
+
+        // Refunds not yet paid: the ledger is still needed
+        assert_eq!(registry.garbage_collect_finished(), 0);
+        let state = registry.get_mut(id).unwrap();
+        assert_eq!(state.pay_refunds(100).unwrap().payouts[0], 100);
+        assert_eq!(
+            state.pay_refunds(100),
+            Err(PredictionError::InvalidTransition)
+        );
+        assert_eq!(registry.garbage_collect_finished(), 1);
+        assert_eq!(registry.get(id), Err(PredictionError::MarketNotFound));
+    }
+
+    #[test]
+    fn registry_init_in_place_resets_to_new() {
+        let mut registry = test_registry();
+        registry
+            .create_market(
+                migrated_token(mint(11)),
+                100,
+                200,
+                registry_rule(),
+                test_params(),
+            )
+            .unwrap();
+        registry.prediction_crank(200, 1);
+
+        registry.init_in_place(test_policy());
+        assert!(*registry == *test_registry());
+    }
+
+    #[test]
+    fn prediction_crank_closes_expired_and_cancels_timed_out_disputes() {
+        let mut registry = test_registry();
+        let expired = registry
+            .create_market(
+                migrated_token(mint(11)),
+                100,
+                200,
+                registry_rule(),
+                test_params(),
+            )
+            .unwrap();
+        let running = registry
+            .create_market(
+                migrated_token(mint(11)),
+                100,
+                400,
+                registry_rule(),
+                test_params(),
+            )
+            .unwrap();
+        let thin = {
+            let mut params = test_params();
+            params.min_total_stake = 50;
+            registry
+                .create_market(migrated_token(mint(11)), 100, 200, registry_rule(), params)
+                .unwrap()
+        };
+        let disputed = registry
+            .create_market(
+                migrated_token(mint(11)),
+                100,
+                150,
+                registry_rule(),
+                test_params(),
+            )
+            .unwrap();
+        let state = registry.get_mut(disputed).unwrap();
+        state.close(150).unwrap();
+        state.dispute(160).unwrap();
+
+        // Dispute window (50 slots) not yet over at 205
+        let outcome = registry.prediction_crank(205, TestRegistry::CAPACITY as u16);
+        assert_eq!(outcome.markets_processed, 4);
+        assert_eq!(outcome.num_closed, 1);
+        assert_eq!(outcome.num_liquidity_cancelled, 1);
+        assert_eq!(outcome.num_dispute_cancelled, 0);
+        assert!(outcome.sweep_complete);
+        assert_eq!(registry.get(expired).unwrap().phase, MarketPhase::Closed);
+        assert_eq!(registry.get(running).unwrap().phase, MarketPhase::Open);
+        assert_eq!(
+            registry.get(thin).unwrap().cancel_reason,
+            Some(CancelReason::InsufficientLiquidity)
+        );
+
+        let outcome = registry.prediction_crank(210, TestRegistry::CAPACITY as u16);
+        assert_eq!(outcome.num_closed, 0);
+        assert_eq!(outcome.num_dispute_cancelled, 1);
+        assert_eq!(
+            registry.get(disputed).unwrap().cancel_reason,
+            Some(CancelReason::DisputeTimeout)
+        );
+    }
+
+    #[test]
+    fn prediction_crank_resumes_from_cursor_within_budget() {
+        let mut registry = test_registry();
+        for _ in 0..3 {
+            registry
+                .create_market(
+                    migrated_token(mint(11)),
+                    100,
+                    200,
+                    registry_rule(),
+                    test_params(),
+                )
+                .unwrap();
+        }
+
+        let first = registry.prediction_crank(200, 2);
+        assert_eq!(first.markets_processed, 2);
+        assert_eq!(first.num_closed, 2);
+        assert_eq!(first.last_cursor, 2);
+        assert!(!first.sweep_complete);
+        assert_eq!(registry.get(2).unwrap().phase, MarketPhase::Open);
+
+        let second = registry.prediction_crank(200, 2);
+        assert_eq!(second.markets_processed, 1);
+        assert_eq!(second.num_closed, 1);
+        assert!(second.sweep_complete);
+        assert_eq!(second.last_cursor, 0);
+        assert!((0..3).all(|id| registry.get(id).unwrap().phase == MarketPhase::Closed));
+    }
+
+    #[test]
+    fn eligibility_policy_rejects_each_failed_check() {
+        let rule = registry_rule();
+        let token = TokenStatus {
+            migration_slot: 40,
+            liquidity_quote_units: 5_000,
+            market_cap_quote_units: 90_000,
+            ..migrated_token(mint(12))
+        };
+        let mut policy = EligibilityPolicy {
+            min_slots_since_migration: 60,
+            min_liquidity_quote_units: 5_000,
+            min_market_cap_quote_units: 90_000,
+            ..EligibilityPolicy::default()
+        };
+        policy.denylist[0] = mint(13);
+        policy.denylist_len = 1;
+        let create = |token: TokenStatus, policy: &EligibilityPolicy| {
+            create_market(token, policy, 1, 100, 200, rule, test_params()).map(|_| ())
+        };
+
+        // Every threshold met exactly
+        assert_eq!(create(token, &policy), Ok(()));
+
+        let cases = [
+            (
+                TokenStatus {
+                    launch_source: LaunchSource::Other,
+                    ..token
+                },
+                PredictionError::UnsupportedLaunchSource,
+            ),
+            (
+                TokenStatus {
+                    mint: mint(13),
+                    ..token
+                },
+                PredictionError::MintDenylisted,
+            ),
+            (
+                TokenStatus {
+                    migration_slot: 41,
+                    ..token
+                },
+                PredictionError::MigrationTooRecent,
+            ),
+            (
+                TokenStatus {
+                    liquidity_quote_units: 4_999,
+                    ..token
+                },
+                PredictionError::InsufficientTokenLiquidity,
+            ),
+            (
+                TokenStatus {
+                    market_cap_quote_units: 89_999,
+                    ..token
+                },
+                PredictionError::MarketCapTooLow,
+            ),
+        ];
+        for (token, err) in cases {
+            assert_eq!(create(token, &policy), Err(err));
+        }
+
+        // Entries past denylist_len are ignored
+        policy.denylist_len = 0;
+        let unlisted = TokenStatus {
+            mint: mint(13),
+            ..token
+        };
+        assert_eq!(create(unlisted, &policy), Ok(()));
 
+        // Migration age does not apply to tokens that have not migrated yet
+        let pending = TokenStatus {
+            migrated_to_pumpswap: false,
+            migration_slot: 0,
+            ..token
+        };
+        assert!(create_market(
+            pending,
+            &policy,
+            2,
+            100,
+            200,
+            MarketRule::MigrationWithinWindow,
+            test_params()
+        )
+        .is_ok());
+    }
+}
@@ -0,0 +1,455 @@
+//! Human-readable engine snapshots (feature `snapshot`).
+//!
+//! `RiskEngine::to_snapshot` keeps only what is needed to rebuild the engine:
+//! `RiskParams`, the global aggregates and the occupied accounts (with their
+//! slot index). `EngineSnapshot` round-trips through JSON and TOML, and
+//! `RiskEngine::load_snapshot` rebuilds an engine from it, rejecting any
+//! state that fails `validate_layout` or `check_conservation`. Both work for
+//! any `RiskEngine<N, W>`; a snapshot loads into any engine whose capacity
+//! covers its slot indices.
+//!
+//! Integers wider than 32 bits are written as decimal strings and byte arrays
+//! as hex, because TOML integers are 64-bit signed.
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::layout::{validate, LayoutError, Scalars, Slab};
+use crate::{
+    empty_account, Account, AccountKind, ConservationSums, InsuranceFund, RiskEngine, RiskParams,
+    I128, U128,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Text is not a valid JSON/TOML snapshot
+    Parse(String),
+    /// Snapshot could not be written as JSON/TOML
+    Serialize(String),
+    /// Account slot index not below the engine's capacity
+    InvalidAccountIndex,
+    /// Two accounts share a slot index
+    DuplicateAccount,
+    /// Rebuilt engine fails a structural check (bitmap, ids, aggregates)
+    Layout(LayoutError),
+    /// Rebuilt engine fails `check_conservation` at the given oracle price
+    ConservationViolated,
+}
+
+/// Engine state without empty slots, cursors or the freelist
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EngineSnapshot {
+    pub params: ParamsSnapshot,
+    pub aggregates: AggregatesSnapshot,
+    pub accounts: Vec<AccountSnapshot>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParamsSnapshot {
+    #[serde(with = "dec")]
+    pub warmup_period_slots: u64,
+    #[serde(with = "dec")]
+    pub maintenance_margin_bps: u64,
+    #[serde(with = "dec")]
+    pub initial_margin_bps: u64,
+    #[serde(with = "dec")]
+    pub trading_fee_bps: u64,
+    #[serde(with = "dec")]
+    pub max_accounts: u64,
+    #[serde(with = "dec")]
+    pub new_account_fee: u128,
+    #[serde(with = "dec")]
+    pub risk_reduction_threshold: u128,
+    #[serde(with = "dec")]
+    pub maintenance_fee_per_slot: u128,
+    #[serde(with = "dec")]
+    pub max_crank_staleness_slots: u64,
+    #[serde(with = "dec")]
+    pub liquidation_fee_bps: u64,
+    #[serde(with = "dec")]
+    pub liquidation_fee_cap: u128,
+    #[serde(with = "dec")]
+    pub liquidation_buffer_bps: u64,
+    #[serde(with = "dec")]
+    pub min_liquidation_abs: u128,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AggregatesSnapshot {
+    #[serde(with = "dec")]
+    pub vault: u128,
+    #[serde(with = "dec")]
+    pub insurance_balance: u128,
+    #[serde(with = "dec")]
+    pub insurance_fee_revenue: u128,
+    #[serde(with = "dec")]
+    pub c_tot: u128,
+    #[serde(with = "dec")]
+    pub pnl_pos_tot: u128,
+    #[serde(with = "dec")]
+    pub total_open_interest: u128,
+    #[serde(with = "dec")]
+    pub current_slot: u64,
+    #[serde(with = "dec")]
+    pub funding_index_qpb_e6: i128,
+    #[serde(with = "dec")]
+    pub last_funding_slot: u64,
+    #[serde(with = "dec")]
+    pub funding_rate_bps_per_slot_last: i64,
+    #[serde(with = "dec")]
+    pub last_crank_slot: u64,
+    #[serde(with = "dec")]
+    pub last_full_sweep_start_slot: u64,
+    #[serde(with = "dec")]
+    pub last_full_sweep_completed_slot: u64,
+    #[serde(with = "dec")]
+    pub net_lp_pos: i128,
+    #[serde(with = "dec")]
+    pub lp_sum_abs: u128,
+    #[serde(with = "dec")]
+    pub lp_max_abs: u128,
+    #[serde(with = "dec")]
+    pub lifetime_liquidations: u64,
+    #[serde(with = "dec")]
+    pub lifetime_force_realize_closes: u64,
+    #[serde(with = "dec")]
+    pub next_account_id: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KindSnapshot {
+    User,
+    LP,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    /// Slot index in the engine
+    pub idx: u16,
+    #[serde(with = "dec")]
+    pub account_id: u64,
+    pub kind: KindSnapshot,
+    #[serde(with = "dec")]
+    pub capital: u128,
+    #[serde(with = "dec")]
+    pub pnl: i128,
+    #[serde(with = "dec")]
+    pub reserved_pnl: u64,
+    #[serde(with = "dec")]
+    pub warmup_started_at_slot: u64,
+    #[serde(with = "dec")]
+    pub warmup_slope_per_step: u128,
+    #[serde(with = "dec")]
+    pub position_size: i128,
+    #[serde(with = "dec")]
+    pub entry_price: u64,
+    #[serde(with = "dec")]
+    pub funding_index: i128,
+    #[serde(with = "hex32")]
+    pub matcher_program: [u8; 32],
+    #[serde(with = "hex32")]
+    pub matcher_context: [u8; 32],
+    #[serde(with = "hex32")]
+    pub owner: [u8; 32],
+    #[serde(with = "dec")]
+    pub fee_credits: i128,
+    #[serde(with = "dec")]
+    pub last_fee_slot: u64,
+    #[serde(with = "dec", default)]
+    pub locked_capital: u128,
+}
+
+/// Integers as decimal strings
+mod dec {
+    use super::String;
+    use core::fmt::Display;
+    use core::str::FromStr;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(value: &T, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(d: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(d)?.parse().map_err(D::Error::custom)
+    }
+}
+
+/// 32-byte keys as 64 hex digits
+mod hex32 {
+    use super::String;
+    use core::fmt::Write;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8; 32], s: S) -> Result<S::Ok, S::Error> {
+        let mut out = String::with_capacity(64);
+        for byte in bytes {
+            let _ = write!(out, "{:02x}", byte);
+        }
+        s.serialize_str(&out)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<[u8; 32], D::Error> {
+        let text = String::deserialize(d)?;
+        if text.len() != 64 || !text.is_ascii() {
+            return Err(D::Error::custom("expected 64 hex digits"));
+        }
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16).map_err(D::Error::custom)?;
+        }
+        Ok(bytes)
+    }
+}
+
+impl EngineSnapshot {
+    pub fn to_json(&self) -> Result<String, SnapshotError> {
+        serde_json::to_string_pretty(self).map_err(|e| SnapshotError::Serialize(e.to_string()))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, SnapshotError> {
+        serde_json::from_str(text).map_err(|e| SnapshotError::Parse(e.to_string()))
+    }
+
+    pub fn to_toml(&self) -> Result<String, SnapshotError> {
+        toml::to_string_pretty(self).map_err(|e| SnapshotError::Serialize(e.to_string()))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, SnapshotError> {
+        toml::from_str(text).map_err(|e| SnapshotError::Parse(e.to_string()))
+    }
+}
+
+impl<const N: usize, const W: usize> RiskEngine<N, W> {
+    /// Params, aggregates and occupied accounts, in slot order
+    pub fn to_snapshot(&self) -> EngineSnapshot {
+        let p = &self.params;
+        let params = ParamsSnapshot {
+            warmup_period_slots: p.warmup_period_slots,
+            maintenance_margin_bps: p.maintenance_margin_bps,
+            initial_margin_bps: p.initial_margin_bps,
+            trading_fee_bps: p.trading_fee_bps,
+            max_accounts: p.max_accounts,
+            new_account_fee: p.new_account_fee.get(),
+            risk_reduction_threshold: p.risk_reduction_threshold.get(),
+            maintenance_fee_per_slot: p.maintenance_fee_per_slot.get(),
+            max_crank_staleness_slots: p.max_crank_staleness_slots,
+            liquidation_fee_bps: p.liquidation_fee_bps,
+            liquidation_fee_cap: p.liquidation_fee_cap.get(),
+            liquidation_buffer_bps: p.liquidation_buffer_bps,
+            min_liquidation_abs: p.min_liquidation_abs.get(),
+        };
+        let aggregates = AggregatesSnapshot {
+            vault: self.vault.get(),
+            insurance_balance: self.insurance_fund.balance.get(),
+            insurance_fee_revenue: self.insurance_fund.fee_revenue.get(),
+            c_tot: self.c_tot.get(),
+            pnl_pos_tot: self.pnl_pos_tot.get(),
+            total_open_interest: self.total_open_interest.get(),
+            current_slot: self.current_slot,
+            funding_index_qpb_e6: self.funding_index_qpb_e6.get(),
+            last_funding_slot: self.last_funding_slot,
+            funding_rate_bps_per_slot_last: self.funding_rate_bps_per_slot_last,
+            last_crank_slot: self.last_crank_slot,
+            last_full_sweep_start_slot: self.last_full_sweep_start_slot,
+            last_full_sweep_completed_slot: self.last_full_sweep_completed_slot,
+            net_lp_pos: self.net_lp_pos.get(),
+            lp_sum_abs: self.lp_sum_abs.get(),
+            lp_max_abs: self.lp_max_abs.get(),
+            lifetime_liquidations: self.lifetime_liquidations,
+            lifetime_force_realize_closes: self.lifetime_force_realize_closes,
+            next_account_id: self.next_account_id,
+        };
+        let mut accounts = Vec::with_capacity(self.num_used_accounts as usize);
+        for (idx, a) in self.accounts.iter().enumerate() {
+            if !self.is_used(idx) {
+                continue;
+            }
+            accounts.push(AccountSnapshot {
+                idx: idx as u16,
+                account_id: a.account_id,
+                kind: match a.kind {
+                    AccountKind::User => KindSnapshot::User,
+                    AccountKind::LP => KindSnapshot::LP,
+                },
+                capital: a.capital.get(),
+                pnl: a.pnl.get(),
+                reserved_pnl: a.reserved_pnl,
+                warmup_started_at_slot: a.warmup_started_at_slot,
+                warmup_slope_per_step: a.warmup_slope_per_step.get(),
+                position_size: a.position_size.get(),
+                entry_price: a.entry_price,
+                funding_index: a.funding_index.get(),
+                matcher_program: a.matcher_program,
+                matcher_context: a.matcher_context,
+                owner: a.owner,
+                fee_credits: a.fee_credits.get(),
+                last_fee_slot: a.last_fee_slot,
+                locked_capital: a.locked_capital.get(),
+            });
+        }
+        EngineSnapshot {
+            params,
+            aggregates,
+            accounts,
+        }
+    }
+
+    /// Replace the whole engine with `snapshot`.
+    ///
+    /// Accounts go back to their recorded slots, the freelist is rebuilt from
+    /// the remaining slots in ascending order, and crank cursors restart at 0.
+    /// The snapshot is rebuilt off to the side first and must pass the
+    /// `validate_layout` checks and `check_conservation` at `oracle_price`;
+    /// on error `self` is left untouched.
+    pub fn load_snapshot(
+        &mut self,
+        snapshot: &EngineSnapshot,
+        oracle_price: u64,
+    ) -> Result<(), SnapshotError> {
+        let p = &snapshot.params;
+        let g = &snapshot.aggregates;
+        let mut scalars = Scalars {
+            vault: U128::new(g.vault),
+            insurance_fund: InsuranceFund {
+                balance: U128::new(g.insurance_balance),
+                fee_revenue: U128::new(g.insurance_fee_revenue),
+            },
+            params: RiskParams {
+                warmup_period_slots: p.warmup_period_slots,
+                maintenance_margin_bps: p.maintenance_margin_bps,
+                initial_margin_bps: p.initial_margin_bps,
+                trading_fee_bps: p.trading_fee_bps,
+                max_accounts: p.max_accounts,
+                new_account_fee: U128::new(p.new_account_fee),
+                risk_reduction_threshold: U128::new(p.risk_reduction_threshold),
+                maintenance_fee_per_slot: U128::new(p.maintenance_fee_per_slot),
+                max_crank_staleness_slots: p.max_crank_staleness_slots,
+                liquidation_fee_bps: p.liquidation_fee_bps,
+                liquidation_fee_cap: U128::new(p.liquidation_fee_cap),
+                liquidation_buffer_bps: p.liquidation_buffer_bps,
+                min_liquidation_abs: U128::new(p.min_liquidation_abs),
+            },
+            current_slot: g.current_slot,
+            funding_index_qpb_e6: I128::new(g.funding_index_qpb_e6),
+            last_funding_slot: g.last_funding_slot,
+            funding_rate_bps_per_slot_last: g.funding_rate_bps_per_slot_last,
+            last_crank_slot: g.last_crank_slot,
+            max_crank_staleness_slots: p.max_crank_staleness_slots,
+            total_open_interest: U128::new(g.total_open_interest),
+            c_tot: U128::new(g.c_tot),
+            pnl_pos_tot: U128::new(g.pnl_pos_tot),
+            liq_cursor: 0,
+            gc_cursor: 0,
+            last_full_sweep_start_slot: g.last_full_sweep_start_slot,
+            last_full_sweep_completed_slot: g.last_full_sweep_completed_slot,
+            crank_cursor: 0,
+            sweep_start_idx: 0,
+            lifetime_liquidations: g.lifetime_liquidations,
+            lifetime_force_realize_closes: g.lifetime_force_realize_closes,
+            net_lp_pos: I128::new(g.net_lp_pos),
+            lp_sum_abs: U128::new(g.lp_sum_abs),
+            lp_max_abs: U128::new(g.lp_max_abs),
+            lp_max_abs_sweep: U128::ZERO,
+            num_used_accounts: 0,
+            next_account_id: g.next_account_id,
+            free_head: u16::MAX,
+        };
+
+        let mut scratch = Scratch {
+            used: vec![0; W],
+            next_free: vec![u16::MAX; N],
+            accounts: vec![empty_account(); N],
+        };
+        for a in snapshot.accounts.iter() {
+            let idx = a.idx as usize;
+            if idx >= N {
+                return Err(SnapshotError::InvalidAccountIndex);
+            }
+            if scratch.is_used(idx) {
+                return Err(SnapshotError::DuplicateAccount);
+            }
+            scratch.used[idx >> 6] |= 1u64 << (idx & 63);
+            scratch.accounts[idx] = Account {
+                account_id: a.account_id,
+                capital: U128::new(a.capital),
+                kind: match a.kind {
+                    KindSnapshot::User => AccountKind::User,
+                    KindSnapshot::LP => AccountKind::LP,
+                },
+                pnl: I128::new(a.pnl),
+                reserved_pnl: a.reserved_pnl,
+                warmup_started_at_slot: a.warmup_started_at_slot,
+                warmup_slope_per_step: U128::new(a.warmup_slope_per_step),
+                position_size: I128::new(a.position_size),
+                entry_price: a.entry_price,
+                funding_index: I128::new(a.funding_index),
+                matcher_program: a.matcher_program,
+                matcher_context: a.matcher_context,
+                owner: a.owner,
+                fee_credits: I128::new(a.fee_credits),
+                last_fee_slot: a.last_fee_slot,
+                locked_capital: U128::new(a.locked_capital),
+            };
+        }
+        // Distinct indices below N, so this fits in u16
+        scalars.num_used_accounts = snapshot.accounts.len() as u16;
+
+        // Freelist: free slots in ascending order -> NONE
+        for idx in (0..N).rev() {
+            if !scratch.is_used(idx) {
+                scratch.next_free[idx] = scalars.free_head;
+                scalars.free_head = idx as u16;
+            }
+        }
+
+        validate::<N, W>(&scalars, &scratch).map_err(SnapshotError::Layout)?;
+        let mut sums = ConservationSums::default();
+        for (idx, account) in scratch.accounts.iter().enumerate() {
+            if scratch.is_used(idx) {
+                sums.add(account, scalars.funding_index_qpb_e6, oracle_price);
+            }
+        }
+        if !sums.holds(
+            scalars.vault.get(),
+            scalars.insurance_fund.balance.get(),
+            N as u128,
+        ) {
+            return Err(SnapshotError::ConservationViolated);
+        }
+
+        scalars.apply(self);
+        self.used.copy_from_slice(&scratch.used);
+        self.next_free.copy_from_slice(&scratch.next_free);
+        self.accounts.copy_from_slice(&scratch.accounts);
+        Ok(())
+    }
+}
+
+/// Slab rebuilt from a snapshot on the heap, checked before it is copied in
+struct Scratch {
+    used: Vec<u64>,
+    next_free: Vec<u16>,
+    accounts: Vec<Account>,
+}
+
+impl Slab for Scratch {
+    fn used_word(&self, word: usize) -> u64 {
+        self.used[word]
+    }
+    fn next_free(&self, idx: usize) -> u16 {
+        self.next_free[idx]
+    }
+    fn account(&self, idx: usize) -> Result<Account, LayoutError> {
+        Ok(self.accounts[idx])
+    }
+}
@@ -0,0 +1,89 @@
+//! Explicitly sized engines (`RiskEngine<N, W>`)
+//! Run with: cargo test --test capacity_tests (no feature needed)
+
+use percolator::*;
+
+const DEFAULT_ORACLE: u64 = 1_000_000;
+
+fn default_params() -> RiskParams {
+    RiskParams {
+        warmup_period_slots: 100,
+        maintenance_margin_bps: 500,
+        initial_margin_bps: 1000,
+        trading_fee_bps: 10,
+        max_accounts: 1000,
+        new_account_fee: U128::new(0),
+        risk_reduction_threshold: U128::new(0),
+        maintenance_fee_per_slot: U128::new(0),
+        max_crank_staleness_slots: u64::MAX,
+        liquidation_fee_bps: 50,
+        liquidation_fee_cap: U128::new(100_000),
+        liquidation_buffer_bps: 100,
+        min_liquidation_abs: U128::new(100_000),
+    }
+}
+
+#[test]
+fn test_engines_of_different_sizes_side_by_side() {
+    let mut small = RiskEngine::<4, 1>::new_sized(default_params());
+    let mut large = Box::new(RiskEngine::<256, 4>::new_sized(default_params()));
+    assert_eq!(RiskEngine::<4, 1>::CAPACITY, 4);
+    assert_eq!(large.used.len(), 4);
+
+    for i in 0..4 {
+        assert_eq!(small.add_user(0).unwrap(), i);
+        large.add_user(0).unwrap();
+    }
+    // The slab, not params.max_accounts, is the binding limit here
+    assert_eq!(small.add_user(0), Err(RiskError::Overflow));
+    assert_eq!(small.vault.get(), 0);
+    assert_eq!(large.add_user(0).unwrap(), 4);
+    assert!(!small.is_used(4));
+
+    // params.max_accounts still applies below the slab size
+    let mut capped = RiskEngine::<8, 1>::new_sized(RiskParams {
+        max_accounts: 2,
+        ..default_params()
+    });
+    capped.add_user(0).unwrap();
+    capped.add_user(0).unwrap();
+    assert_eq!(capped.add_user(0), Err(RiskError::Overflow));
+}
+
+#[test]
+fn test_small_engine_trades_and_sweeps() {
+    let mut engine = Box::new(RiskEngine::<8, 1>::new_sized(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    let dust = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 1).unwrap();
+    engine.deposit(user, 1_000_000, 1).unwrap();
+
+    let outcome = engine
+        .keeper_crank(user, 2, DEFAULT_ORACLE, 0, false)
+        .unwrap();
+    assert!(outcome.sweep_complete);
+    assert_eq!(outcome.num_gc_closed, 1);
+    assert!(!engine.is_used(dust as usize));
+
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 2, DEFAULT_ORACLE, 1_000)
+        .unwrap();
+    assert_eq!(engine.accounts[user as usize].position_size.get(), 1_000);
+    assert!(engine.check_conservation(DEFAULT_ORACLE));
+
+    // The freed slot is reused
+    assert_eq!(engine.add_user(0).unwrap(), dust);
+}
+
+#[test]
+fn test_init_in_place_matches_new_sized() {
+    // Zeroed memory apart from params, as a freshly allocated account would be
+    let mut engine = RiskEngine::<64, 1>::new_sized(RiskParams {
+        warmup_period_slots: 1,
+        ..default_params()
+    });
+    engine.next_free = [0; 64];
+    engine.init_in_place(default_params());
+    assert!(engine == RiskEngine::<64, 1>::new_sized(default_params()));
+}
@@ -0,0 +1,165 @@
+//! Tests for the `percolator` binary: commands run against a state file that
+//! persists between invocations.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+struct StateFile(PathBuf);
+
+impl StateFile {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("percolator-{}-{}.state", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        Self(path)
+    }
+
+    fn run(&self, args: &str) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_percolator"))
+            .arg("--state")
+            .arg(&self.0)
+            .args(args.split_whitespace())
+            .output()
+            .expect("run percolator")
+    }
+
+    /// Run a command that must succeed; returns its stdout
+    fn ok(&self, args: &str) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "`{}` failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn log(&self) -> String {
+        fs::read_to_string(&self.0).unwrap()
+    }
+}
+
+impl Drop for StateFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn test_engine_state_persists_between_invocations() {
+    let state = StateFile::new("engine");
+    assert_eq!(state.ok("add-lp 0").trim(), "idx=0 account_id=0");
+    assert_eq!(state.ok("add-user 0").trim(), "idx=1 account_id=1");
+    state.ok("deposit 0 10000000 1");
+    assert_eq!(state.ok("deposit 1 1000000 1").trim(), "capital=1000000");
+    state.ok("crank 0 2 1000000");
+    assert_eq!(
+        state.ok("trade 0 1 2 1000000 1000").trim(),
+        "position_size=1000"
+    );
+
+    let show = state.ok("show");
+    assert!(show.contains("account idx=0 id=0 kind=LP capital=10000000"));
+    assert!(show.contains("position_size=1000"));
+
+    // `show` is read-only and never logged
+    assert_eq!(state.log().lines().count(), 7);
+    assert!(!state.log().contains("show"));
+}
+
+#[test]
+fn test_failed_command_is_not_logged() {
+    let state = StateFile::new("failed");
+    state.ok("add-user 0");
+    state.ok("deposit 0 500 1");
+    let before = state.log();
+
+    let output = state.run("withdraw 0 501 2 1000000");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("InsufficientBalance"));
+
+    let output = state.run("deposit 0 oops 2");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(state.log(), before);
+
+    // Replaying the log still yields the committed state
+    assert_eq!(state.ok("withdraw 0 500 2 1000000").trim(), "capital=0");
+}
+
+#[test]
+fn test_prediction_market_flow_settles_on_engine() {
+    let state = StateFile::new("prediction");
+    state.ok("add-user 0");
+    state.ok("add-user 0");
+    state.ok("add-user 0");
+    assert_eq!(
+        state.ok("create-market ab 10 100 mcap 5000").trim(),
+        "market_id=0"
+    );
+    state.ok("stake 0 1 yes 300 20");
+    assert_eq!(
+        state.ok("stake 0 2 no 100 20").trim(),
+        "yes_capital=300 no_capital=100"
+    );
+    // Resolution needs an explicit close first
+    let output = state.run("resolve 0 100 6000 1");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(state.ok("close 0 100").trim(), "phase=Closed");
+    assert_eq!(state.ok("resolve 0 100 6000 1").trim(), "outcome=Yes");
+
+    let settle = state.ok("settle 0 0 101");
+    assert!(settle.contains("winner_capital_total=300 loser_capital_total=100"));
+    assert!(settle.contains("h=100/100"));
+
+    let show = state.ok("show");
+    assert!(show.contains("account idx=1 id=1 kind=User capital=300 pnl=100"));
+    assert!(show.contains("account idx=2 id=2 kind=User capital=0 pnl=0"));
+    assert!(show.contains("market id=0 phase=Settled"));
+}
+
+#[test]
+fn test_unreplayable_log_is_rejected() {
+    let state = StateFile::new("corrupt");
+    state.ok("add-user 0");
+    fs::write(&state.0, state.log() + "deposit 7 10 1\n").unwrap();
+    let output = state.run("show");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(":3: replay failed"), "{}", stderr);
+}
+
+#[test]
+fn test_log_from_another_build_is_rejected() {
+    let state = StateFile::new("header");
+    state.ok("add-user 0");
+    let header = state.log().lines().next().unwrap().to_string();
+    assert!(
+        header.starts_with("# percolator state v2 accounts="),
+        "{}",
+        header
+    );
+    assert!(header.contains(" layout=0x"), "{}", header);
+
+    for stale in [
+        "# percolator state v1".to_string(),
+        header.replace("accounts=", "accounts=1"),
+    ] {
+        fs::write(&state.0, format!("{}\nadd-user 0\n", stale)).unwrap();
+        let output = state.run("show");
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("incompatible build"), "{}", stderr);
+    }
+}
+
+#[test]
+fn test_empty_state_file_gets_a_header() {
+    let state = StateFile::new("empty");
+    fs::write(&state.0, "").unwrap();
+    state.ok("add-user 0");
+    assert_eq!(state.ok("add-user 0").trim(), "idx=1 account_id=1");
+    assert!(state.log().starts_with("# percolator state v2 "));
+    assert_eq!(state.log().lines().count(), 3);
+}
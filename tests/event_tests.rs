@@ -0,0 +1,276 @@
+//! Event sink: typed events with before/after amounts
+//! Run with: cargo test --features test
+
+use percolator::*;
+
+const DEFAULT_ORACLE: u64 = 1_000_000;
+
+fn default_params() -> RiskParams {
+    RiskParams {
+        warmup_period_slots: 100,
+        maintenance_margin_bps: 500,
+        initial_margin_bps: 1000,
+        trading_fee_bps: 10,
+        max_accounts: 1000,
+        new_account_fee: U128::new(0),
+        risk_reduction_threshold: U128::new(0),
+        maintenance_fee_per_slot: U128::new(0),
+        max_crank_staleness_slots: u64::MAX,
+        liquidation_fee_bps: 50,
+        liquidation_fee_cap: U128::new(100_000),
+        liquidation_buffer_bps: 100,
+        min_liquidation_abs: U128::new(100_000),
+    }
+}
+
+#[derive(Default)]
+struct Log(Vec<EngineEvent>);
+
+impl EventSink for Log {
+    fn on_event(&mut self, event: &EngineEvent) {
+        self.0.push(*event);
+    }
+}
+
+impl Log {
+    fn take(&mut self) -> Vec<EngineEvent> {
+        core::mem::take(&mut self.0)
+    }
+}
+
+/// LP with 10M and a user with 1M, cranked at slot 2
+fn funded_engine(log: &mut Log) -> (Box<RiskEngine>, u16, u16) {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit_with_sink(log, lp, 10_000_000, 1).unwrap();
+    engine.deposit_with_sink(log, user, 1_000_000, 1).unwrap();
+    engine
+        .keeper_crank_with_sink(log, user, 2, DEFAULT_ORACLE, 0, false)
+        .unwrap();
+    (engine, lp, user)
+}
+
+#[test]
+fn test_account_lifecycle_events() {
+    let mut log = Log::default();
+    let (mut engine, lp, user) = funded_engine(&mut log);
+    assert_eq!(
+        log.take(),
+        vec![
+            EngineEvent::Deposited {
+                idx: lp,
+                amount: 10_000_000,
+                capital_before: 0,
+                capital_after: 10_000_000,
+            },
+            EngineEvent::Deposited {
+                idx: user,
+                amount: 1_000_000,
+                capital_before: 0,
+                capital_after: 1_000_000,
+            },
+        ]
+    );
+
+    // Notional 1_000 at 10 bps rounds the fee up to 1
+    engine
+        .execute_trade_with_sink(&mut log, &NoOpMatcher, lp, user, 2, DEFAULT_ORACLE, 1_000)
+        .unwrap();
+    assert_eq!(
+        log.take(),
+        vec![
+            EngineEvent::Traded {
+                user_idx: user,
+                lp_idx: lp,
+                size: 1_000,
+                price: DEFAULT_ORACLE,
+                user_position_before: 0,
+                user_position_after: 1_000,
+                lp_position_before: 0,
+                lp_position_after: -1_000,
+            },
+            EngineEvent::FeeCharged {
+                idx: user,
+                kind: FeeKind::Trading,
+                amount: 1,
+                capital_before: 1_000_000,
+                capital_after: 999_999,
+            },
+        ]
+    );
+
+    engine
+        .execute_trade_with_sink(&mut log, &NoOpMatcher, lp, user, 3, DEFAULT_ORACLE, -1_000)
+        .unwrap();
+    assert_eq!(log.take().len(), 2);
+
+    engine
+        .withdraw_with_sink(&mut log, user, 500, 4, DEFAULT_ORACLE)
+        .unwrap();
+    assert_eq!(
+        log.take(),
+        vec![EngineEvent::Withdrawn {
+            idx: user,
+            amount: 500,
+            capital_before: 999_998,
+            capital_after: 999_498,
+        }]
+    );
+
+    let returned = engine
+        .close_account_with_sink(&mut log, user, 5, DEFAULT_ORACLE)
+        .unwrap();
+    assert_eq!(
+        log.take(),
+        vec![EngineEvent::AccountClosed {
+            idx: user,
+            account_id: 1,
+            capital: returned,
+        }]
+    );
+}
+
+#[test]
+fn test_fee_paid_from_deposit_leaves_capital_unchanged() {
+    let mut engine = Box::new(RiskEngine::new(RiskParams {
+        maintenance_fee_per_slot: U128::new(10),
+        ..default_params()
+    }));
+    let user = engine.add_user(0).unwrap();
+    let mut log = Log::default();
+    engine.deposit_with_sink(&mut log, user, 1_000, 5).unwrap();
+    assert_eq!(
+        log.take(),
+        vec![
+            EngineEvent::FeeCharged {
+                idx: user,
+                kind: FeeKind::Maintenance,
+                amount: 50,
+                capital_before: 0,
+                capital_after: 0,
+            },
+            EngineEvent::Deposited {
+                idx: user,
+                amount: 1_000,
+                capital_before: 0,
+                capital_after: 950,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_loss_settlement_and_warmup_conversion() {
+    let mut log = Log::default();
+    let (mut engine, lp, user) = funded_engine(&mut log);
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 2, DEFAULT_ORACLE, 1_000)
+        .unwrap();
+    log.take();
+
+    // Oracle +10%: the user's +100 starts warming up, nothing converts yet
+    let oracle = 1_100_000;
+    engine
+        .touch_account_full_with_sink(&mut log, user, 3, oracle)
+        .unwrap();
+    assert_eq!(log.take(), vec![]);
+
+    // The LP's -100 is paid from capital, which funds the user's conversion
+    engine
+        .touch_account_full_with_sink(&mut log, lp, 200, oracle)
+        .unwrap();
+    assert_eq!(
+        log.take(),
+        vec![EngineEvent::LossSettled {
+            idx: lp,
+            pnl_before: -100,
+            pnl_after: 0,
+            capital_before: 10_000_000,
+            capital_after: 9_999_900,
+        }]
+    );
+
+    engine
+        .touch_account_full_with_sink(&mut log, user, 200, oracle)
+        .unwrap();
+    assert_eq!(
+        log.take(),
+        vec![EngineEvent::WarmupConverted {
+            idx: user,
+            pnl_before: 100,
+            pnl_after: 0,
+            capital_before: 999_999,
+            capital_after: 1_000_099,
+        }]
+    );
+}
+
+#[test]
+fn test_liquidation_reports_close_then_fee() {
+    // Same setup as test_liquidation_fee_calculation in unit_tests.rs
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let user = engine.add_user(0).unwrap();
+    engine.accounts[user as usize].capital = U128::new(4_000);
+    engine.accounts[user as usize].position_size = I128::new(100_000);
+    engine.accounts[user as usize].entry_price = DEFAULT_ORACLE;
+    engine.total_open_interest = U128::new(100_000);
+    engine.vault = U128::new(4_000);
+
+    let mut log = Log::default();
+    assert!(engine
+        .liquidate_at_oracle_with_sink(&mut log, user, 0, DEFAULT_ORACLE)
+        .unwrap());
+    assert_eq!(
+        log.take(),
+        vec![
+            EngineEvent::Liquidated {
+                idx: user,
+                oracle_price: DEFAULT_ORACLE,
+                position_before: 100_000,
+                position_after: 0,
+                capital_before: 4_000,
+                capital_after: 4_000,
+            },
+            EngineEvent::FeeCharged {
+                idx: user,
+                kind: FeeKind::Liquidation,
+                amount: 500,
+                capital_before: 4_000,
+                capital_after: 3_500,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_crank_writes_off_and_collects_dust() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let dust = engine.add_user(0).unwrap();
+    engine.deposit(lp, 1_000, 1).unwrap();
+    engine.accounts[dust as usize].pnl = I128::new(-7);
+
+    let mut log = Log::default();
+    let outcome = engine
+        .keeper_crank_with_sink(&mut log, lp, 2, DEFAULT_ORACLE, 0, false)
+        .unwrap();
+    assert_eq!(outcome.num_gc_closed, 1);
+    assert_eq!(
+        log.take(),
+        vec![
+            EngineEvent::LossSettled {
+                idx: dust,
+                pnl_before: -7,
+                pnl_after: 0,
+                capital_before: 0,
+                capital_after: 0,
+            },
+            EngineEvent::DustCollected {
+                idx: dust,
+                account_id: 1,
+                pnl_written_off: 0,
+            },
+        ]
+    );
+}
@@ -2103,6 +2103,7 @@ fn fast_account_equity_computes_correctly() {
         owner: [0; 32],
         fee_credits: I128::ZERO,
         last_fee_slot: 0,
+        locked_capital: U128::ZERO,
     };
 
     let equity = engine.account_equity(&account);
@@ -8100,3 +8101,196 @@ fn proof_flaw3_warmup_converts_after_single_slot() {
     }
     kani::assert(canonical_inv(&engine), "INV after warmup settle");
 }
+
+// ============================================================================
+// Prediction Market Settlement Proofs
+// ============================================================================
+
+/// PM1: Per-participant payouts sum to at most the market-level winner payout,
+/// which itself never exceeds the vault. Rounding dust is exactly the remainder.
+#[kani::proof]
+#[kani::unwind(5)]
+#[kani::solver(cadical)]
+fn proof_pm_participant_payouts_bounded_by_winner_total() {
+    use percolator::prediction::*;
+
+    let token = TokenStatus {
+        mint: [1; 32],
+        migrated_to_pumpswap: true,
+        launch_source: LaunchSource::PumpFun,
+        migration_slot: 0,
+        liquidity_quote_units: 0,
+        market_cap_quote_units: 0,
+        delisted: false,
+    };
+    let market = create_market(
+        token,
+        &EligibilityPolicy::default(),
+        1,
+        10,
+        20,
+        MarketRule::MarketCapAtCloseAtLeast {
+            target_quote_units: 1,
+        },
+        MarketParams {
+            max_snapshot_delay_slots: 0,
+            agreement_policy: AgreementPolicy::Exact,
+            dispute_timeout_slots: 0,
+            protocol_fee_bps: 100,
+            creator_fee_bps: 50,
+            betting_cutoff_slots: 1,
+            exit_fee_bps: 0,
+            min_total_stake: 0,
+            min_side_stake: 0,
+        },
+    )
+    .unwrap();
+    let snapshot = TokenSnapshot {
+        mint: [1; 32],
+        migrated_to_pumpswap: true,
+        market_cap_quote_units: 1,
+        price_e6: 0,
+        volume: None,
+        migration_slot: None,
+        snapshot_slot: 20,
+    };
+
+    let mut ledger = ParticipantLedger::new();
+    for i in 0..MAX_PARTICIPANTS {
+        let amount: u16 = kani::any();
+        kani::assume(amount > 0 && amount <= 1_000);
+        let side = if kani::any() { Outcome::Yes } else { Outcome::No };
+        assert_ok!(
+            ledger.record_stake([i as u8; 32], side, amount as u128),
+            "stake recorded"
+        );
+    }
+    kani::assume(ledger.pools.yes_capital > 0);
+
+    let available: u16 = kani::any();
+    let settlement = settle_market(&market, &snapshot, ledger.pools, available as u128).unwrap();
+    let result = settle_participants(&ledger, &settlement).unwrap();
+
+    kani::assert(
+        settlement.winner_payout_total + settlement.protocol_fee_paid + settlement.creator_fee_paid
+            <= available as u128,
+        "PM1: market payout plus fees bounded by vault"
+    );
+    kani::assert(
+        result.total_paid <= settlement.winner_payout_total,
+        "PM1: participant payouts bounded by winner total"
+    );
+    kani::assert(
+        result.total_paid + result.rounding_dust == settlement.winner_payout_total,
+        "PM1: rounding dust is the exact remainder"
+    );
+}
+
+/// PM2: For every outcome count N in 2..=MAX_OUTCOMES and any winning bucket,
+/// categorical settlement pays winners plus fees at most the vault.
+#[kani::proof]
+#[kani::unwind(5)]
+#[kani::solver(cadical)]
+fn proof_pm_categorical_payout_bounded_by_vault() {
+    use percolator::prediction::*;
+
+    let n: u8 = kani::any();
+    kani::assume(n >= 2 && n as usize <= MAX_OUTCOMES);
+
+    let mut pools = CategoricalPools::new(n);
+    for i in 0..n as usize {
+        let amount: u16 = kani::any();
+        pools.capital[i] = amount as u128;
+    }
+    let winning: u8 = kani::any();
+    kani::assume(winning < n && pools.capital[winning as usize] > 0);
+
+    let params = MarketParams {
+        max_snapshot_delay_slots: 0,
+        agreement_policy: AgreementPolicy::Exact,
+        dispute_timeout_slots: 0,
+        protocol_fee_bps: 100,
+        creator_fee_bps: 50,
+        betting_cutoff_slots: 1,
+        exit_fee_bps: 0,
+        min_total_stake: 0,
+        min_side_stake: 0,
+    };
+    let available: u16 = kani::any();
+    let settlement = settle_categorical(winning, &pools, available as u128, &params).unwrap();
+
+    kani::assert(
+        settlement.winner_payout_total + settlement.protocol_fee_paid + settlement.creator_fee_paid
+            <= available as u128,
+        "PM2: categorical payout plus fees bounded by vault"
+    );
+    kani::assert(
+        settlement.winner_capital_paid <= settlement.winner_capital_total,
+        "PM2: winner capital never overpaid"
+    );
+}
+
+/// PM3: A stake followed by any unstake never returns more than was staked,
+/// and what is returned plus what remains in the market equals the stake.
+#[kani::proof]
+#[kani::unwind(5)]
+#[kani::solver(cadical)]
+fn proof_pm_unstake_never_exceeds_stake() {
+    use percolator::prediction::*;
+
+    let token = TokenStatus {
+        mint: [1; 32],
+        migrated_to_pumpswap: true,
+        launch_source: LaunchSource::PumpFun,
+        migration_slot: 0,
+        liquidity_quote_units: 0,
+        market_cap_quote_units: 0,
+        delisted: false,
+    };
+    let exit_fee_bps: u16 = kani::any();
+    kani::assume(exit_fee_bps <= 10_000);
+    let market = create_market(
+        token,
+        &EligibilityPolicy::default(),
+        1,
+        10,
+        20,
+        MarketRule::MarketCapAtCloseAtLeast {
+            target_quote_units: 1,
+        },
+        MarketParams {
+            max_snapshot_delay_slots: 0,
+            agreement_policy: AgreementPolicy::Exact,
+            dispute_timeout_slots: 0,
+            protocol_fee_bps: 0,
+            creator_fee_bps: 0,
+            betting_cutoff_slots: 1,
+            exit_fee_bps: exit_fee_bps as u64,
+            min_total_stake: 0,
+            min_side_stake: 0,
+        },
+    )
+    .unwrap();
+    let mut state = MarketState::new(market);
+
+    let staked: u16 = kani::any();
+    kani::assume(staked > 0);
+    assert_ok!(
+        state.stake([1; 32], Outcome::Yes, staked as u128, 10),
+        "stake recorded"
+    );
+
+    let amount: u16 = kani::any();
+    if let Ok(refund) = state.unstake([1; 32], Outcome::Yes, amount as u128, 11) {
+        kani::assert(refund <= amount as u128, "PM3: refund bounded by amount");
+        kani::assert(
+            refund + state.ledger.pools.yes_capital + state.ledger.exit_fees == staked as u128,
+            "PM3: unstake conserves value"
+        );
+    } else {
+        kani::assert(
+            state.ledger.pools.yes_capital == staked as u128 && state.ledger.exit_fees == 0,
+            "PM3: failed unstake leaves no trace"
+        );
+    }
+}
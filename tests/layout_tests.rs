@@ -0,0 +1,320 @@
+//! Versioned binary layout: round-trips and validating loads
+//! Run with: cargo test --test layout_tests (no feature needed)
+
+use percolator::layout::*;
+use percolator::*;
+
+/// Sized explicitly, so these tests do not depend on the `test` feature
+type Engine = RiskEngine<64, 1>;
+
+const DEFAULT_ORACLE: u64 = 1_000_000;
+
+fn default_params() -> RiskParams {
+    RiskParams {
+        warmup_period_slots: 100,
+        maintenance_margin_bps: 500,
+        initial_margin_bps: 1000,
+        trading_fee_bps: 10,
+        max_accounts: 1000,
+        new_account_fee: U128::new(0),
+        risk_reduction_threshold: U128::new(0),
+        maintenance_fee_per_slot: U128::new(0),
+        max_crank_staleness_slots: u64::MAX,
+        liquidation_fee_bps: 50,
+        liquidation_fee_cap: U128::new(100_000),
+        liquidation_buffer_bps: 100,
+        min_liquidation_abs: U128::new(100_000),
+    }
+}
+
+/// Engine with an LP, a user with an open position, and a dust account
+/// the crank garbage-collected back onto the freelist
+fn active_engine() -> Box<Engine> {
+    let mut engine = Box::new(Engine::new_sized(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    let gone = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 1).unwrap();
+    engine.deposit(user, 1_000_000, 1).unwrap();
+    engine.set_owner(user, [3; 32]).unwrap();
+    engine
+        .keeper_crank(user, 2, DEFAULT_ORACLE, 0, false)
+        .unwrap();
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 2, DEFAULT_ORACLE, 1_000)
+        .unwrap();
+    assert!(!engine.is_used(gone as usize));
+    engine
+}
+
+fn encode(engine: &Engine) -> Vec<u8> {
+    let mut bytes = vec![0u8; Engine::ENCODED_LEN];
+    engine.to_bytes(&mut bytes).unwrap();
+    bytes
+}
+
+fn load(bytes: &[u8]) -> core::result::Result<(), LayoutError> {
+    let mut engine = Box::new(Engine::new_sized(default_params()));
+    engine.load_from_bytes(bytes)
+}
+
+#[test]
+fn test_round_trip_restores_identical_engine() {
+    let engine = active_engine();
+    let bytes = encode(&engine);
+    assert_eq!(&bytes[..4], &ENGINE_MAGIC);
+
+    let mut loaded = Box::new(Engine::new_sized(RiskParams {
+        warmup_period_slots: 1,
+        ..default_params()
+    }));
+    loaded.load_from_bytes(&bytes).unwrap();
+    assert!(*loaded == *engine);
+    assert!(loaded.check_conservation(DEFAULT_ORACLE));
+
+    // Re-encoding is byte-identical
+    assert_eq!(encode(&loaded), bytes);
+}
+
+#[test]
+fn test_decode_and_encode_copy_the_engine() {
+    let engine = active_engine();
+    let mut buf = vec![0u8; Engine::ENCODED_LEN];
+    let bytes = engine.encode(&mut buf).unwrap().to_vec();
+    assert_eq!(bytes, encode(&engine));
+
+    let decoded = Box::new(Engine::decode(&bytes).unwrap());
+    assert!(*decoded == *engine);
+    assert_eq!(
+        Engine::decode(&bytes[1..]).err(),
+        Some(LayoutError::BufferLength)
+    );
+}
+
+#[test]
+fn test_every_field_round_trips() {
+    // Distinct non-zero values everywhere the validating load allows them
+    let mut engine = Box::new(Engine::new_sized(RiskParams {
+        warmup_period_slots: 1,
+        maintenance_margin_bps: 2,
+        initial_margin_bps: 3,
+        trading_fee_bps: 4,
+        max_accounts: 5,
+        new_account_fee: U128::new(6),
+        risk_reduction_threshold: U128::new(7),
+        maintenance_fee_per_slot: U128::new(8),
+        max_crank_staleness_slots: 9,
+        liquidation_fee_bps: 10,
+        liquidation_fee_cap: U128::new(11),
+        liquidation_buffer_bps: 12,
+        min_liquidation_abs: U128::new(13),
+    }));
+    let idx = engine.add_user(6).unwrap();
+    engine.next_account_id = 40;
+    engine.accounts[idx as usize] = Account {
+        account_id: 39,
+        capital: U128::new(1_000),
+        kind: AccountKind::LP,
+        pnl: I128::new(-17),
+        reserved_pnl: 18,
+        warmup_started_at_slot: 19,
+        warmup_slope_per_step: U128::new(20),
+        position_size: I128::new(-21),
+        entry_price: 22,
+        funding_index: I128::new(-23),
+        matcher_program: [24; 32],
+        matcher_context: [25; 32],
+        owner: [26; 32],
+        fee_credits: I128::new(-27),
+        last_fee_slot: 28,
+        locked_capital: U128::new(29),
+    };
+    engine.c_tot = U128::new(1_000);
+    engine.total_open_interest = U128::new(21);
+    engine.insurance_fund = InsuranceFund {
+        balance: U128::new(30),
+        fee_revenue: U128::new(31),
+    };
+    engine.vault = U128::new(1_032);
+    engine.current_slot = 33;
+    engine.funding_index_qpb_e6 = I128::new(-34);
+    engine.last_funding_slot = 35;
+    engine.funding_rate_bps_per_slot_last = -36;
+    engine.last_crank_slot = 37;
+    engine.max_crank_staleness_slots = 38;
+    engine.liq_cursor = 41;
+    engine.gc_cursor = 42;
+    engine.last_full_sweep_start_slot = 43;
+    engine.last_full_sweep_completed_slot = 44;
+    engine.crank_cursor = 45;
+    engine.sweep_start_idx = 46;
+    engine.lifetime_liquidations = 47;
+    engine.lifetime_force_realize_closes = 48;
+    engine.net_lp_pos = I128::new(-49);
+    engine.lp_sum_abs = U128::new(50);
+    engine.lp_max_abs = U128::new(51);
+    engine.lp_max_abs_sweep = U128::new(52);
+
+    let bytes = encode(&engine);
+    let decoded = Box::new(Engine::decode(&bytes).unwrap());
+    assert!(*decoded == *engine);
+
+    // Pinned sizes: a change here is a layout change and needs a new
+    // ENGINE_LAYOUT_VERSION
+    assert_eq!(ACCOUNT_BYTES_LEN, 249);
+    assert_eq!(Engine::ENCODED_LEN, 16 + 400 + 8 + 12 + 64 * 2 + 64 * 249);
+    assert_eq!(bytes.len(), Engine::ENCODED_LEN);
+}
+
+#[test]
+fn test_rejected_load_leaves_engine_untouched() {
+    let mut corrupt = active_engine();
+    corrupt.c_tot = U128::new(corrupt.c_tot.get() + 1);
+    let bytes = encode(&corrupt);
+
+    let mut target = Box::new(Engine::new_sized(default_params()));
+    let user = target.add_user(0).unwrap();
+    target.deposit(user, 500, 1).unwrap();
+    let before = encode(&target);
+    assert_eq!(
+        target.load_from_bytes(&bytes),
+        Err(LayoutError::InconsistentAggregates)
+    );
+    assert_eq!(encode(&target), before);
+}
+
+#[test]
+fn test_rejects_wrong_length_and_header() {
+    let engine = active_engine();
+    let mut short = vec![0u8; Engine::ENCODED_LEN - 1];
+    assert_eq!(engine.to_bytes(&mut short), Err(LayoutError::BufferLength));
+
+    let bytes = encode(&engine);
+    assert_eq!(load(&bytes[1..]), Err(LayoutError::BufferLength));
+
+    let mut bad = bytes.clone();
+    bad[0] ^= 0xff;
+    assert_eq!(load(&bad), Err(LayoutError::BadMagic));
+
+    let mut bad = bytes.clone();
+    bad[4..8].copy_from_slice(&(ENGINE_LAYOUT_VERSION + 1).to_le_bytes());
+    assert_eq!(load(&bad), Err(LayoutError::UnsupportedVersion));
+
+    let mut bad = bytes;
+    bad[8..16].copy_from_slice(&(Engine::LAYOUT_HASH ^ 1).to_le_bytes());
+    assert_eq!(load(&bad), Err(LayoutError::LayoutMismatch));
+}
+
+#[test]
+fn test_each_capacity_has_its_own_layout() {
+    assert_ne!(
+        RiskEngine::<64, 1>::LAYOUT_HASH,
+        RiskEngine::<256, 4>::LAYOUT_HASH
+    );
+    assert_eq!(RiskEngine::<256, 4>::ENCODED_LEN, engine_bytes_len(256, 4));
+
+    // A larger engine round-trips through its own encoding
+    let mut large = Box::new(RiskEngine::<256, 4>::new_sized(default_params()));
+    let user = large.add_user(0).unwrap();
+    large.deposit(user, 1_000, 1).unwrap();
+    let mut bytes = vec![0u8; RiskEngine::<256, 4>::ENCODED_LEN];
+    large.to_bytes(&mut bytes).unwrap();
+    let mut loaded = Box::new(RiskEngine::<256, 4>::new_sized(default_params()));
+    loaded.load_from_bytes(&bytes).unwrap();
+    assert!(*loaded == *large);
+
+    // and is rejected by an engine of another size
+    assert_eq!(load(&bytes), Err(LayoutError::BufferLength));
+    let mut header_only = encode(&active_engine());
+    header_only[8..16].copy_from_slice(&RiskEngine::<256, 4>::LAYOUT_HASH.to_le_bytes());
+    assert_eq!(load(&header_only), Err(LayoutError::LayoutMismatch));
+}
+
+#[test]
+fn test_rejects_corrupted_bitmap() {
+    // Extra bit set: popcount no longer matches num_used_accounts
+    let mut engine = active_engine();
+    engine.used[0] |= 1 << 5;
+    assert_eq!(load(&encode(&engine)), Err(LayoutError::CorruptBitmap));
+
+    let mut engine = active_engine();
+    engine.num_used_accounts += 1;
+    assert_eq!(load(&encode(&engine)), Err(LayoutError::CorruptBitmap));
+}
+
+#[test]
+fn test_rejects_corrupted_freelist() {
+    let mut engine = active_engine();
+    engine.free_head = Engine::CAPACITY as u16;
+    assert_eq!(load(&encode(&engine)), Err(LayoutError::CorruptFreelist));
+
+    // Freelist pointing at a used slot
+    let mut engine = active_engine();
+    let head = engine.free_head as usize;
+    engine.next_free[head] = 0;
+    assert_eq!(load(&encode(&engine)), Err(LayoutError::CorruptFreelist));
+
+    // Cycle back to the head
+    let mut engine = active_engine();
+    let head = engine.free_head;
+    engine.next_free[head as usize] = head;
+    assert_eq!(load(&encode(&engine)), Err(LayoutError::CorruptFreelist));
+
+    // Truncated freelist leaves free slots unreachable
+    let mut engine = active_engine();
+    let head = engine.free_head as usize;
+    engine.next_free[head] = u16::MAX;
+    assert_eq!(load(&encode(&engine)), Err(LayoutError::CorruptFreelist));
+}
+
+#[test]
+fn test_rejects_inconsistent_aggregates() {
+    let mut engine = active_engine();
+    engine.c_tot = U128::new(engine.c_tot.get() + 1);
+    assert_eq!(
+        load(&encode(&engine)),
+        Err(LayoutError::InconsistentAggregates)
+    );
+
+    let mut engine = active_engine();
+    engine.total_open_interest = U128::ZERO;
+    assert_eq!(
+        load(&encode(&engine)),
+        Err(LayoutError::InconsistentAggregates)
+    );
+
+    // Vault below c_tot + insurance
+    let mut engine = active_engine();
+    engine.vault = U128::new(engine.c_tot.get());
+    engine.insurance_fund.balance = U128::new(1);
+    assert_eq!(
+        load(&encode(&engine)),
+        Err(LayoutError::InconsistentAggregates)
+    );
+}
+
+#[test]
+fn test_rejects_invalid_fields() {
+    let mut engine = active_engine();
+    engine.crank_cursor = Engine::CAPACITY as u16;
+    assert_eq!(load(&encode(&engine)), Err(LayoutError::InvalidCursor));
+
+    let mut engine = active_engine();
+    engine.next_account_id = 1;
+    assert_eq!(load(&encode(&engine)), Err(LayoutError::InvalidAccountId));
+
+    let mut engine = active_engine();
+    let capital = engine.accounts[0].capital.get();
+    engine.accounts[0].locked_capital = U128::new(capital + 1);
+    assert_eq!(
+        load(&encode(&engine)),
+        Err(LayoutError::InvalidLockedCapital)
+    );
+
+    // Account 0's kind byte follows its id (u64) and capital (u128)
+    let engine = active_engine();
+    let mut bytes = encode(&engine);
+    let kind_offset = Engine::ENCODED_LEN - Engine::CAPACITY * ACCOUNT_BYTES_LEN + 8 + 16;
+    bytes[kind_offset] = 7;
+    assert_eq!(load(&bytes), Err(LayoutError::InvalidAccountKind));
+}
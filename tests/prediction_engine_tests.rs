@@ -0,0 +1,587 @@
+//! Prediction markets settled on RiskEngine accounts
+//! Run with: cargo test --features test
+
+use percolator::prediction::*;
+use percolator::*;
+
+const DEFAULT_ORACLE: u64 = 1_000_000;
+
+fn default_params() -> RiskParams {
+    RiskParams {
+        warmup_period_slots: 100,
+        maintenance_margin_bps: 500,
+        initial_margin_bps: 1000,
+        trading_fee_bps: 10,
+        max_accounts: 1000,
+        new_account_fee: U128::new(0),
+        risk_reduction_threshold: U128::new(0),
+        maintenance_fee_per_slot: U128::new(0),
+        max_crank_staleness_slots: u64::MAX,
+        liquidation_fee_bps: 50,
+        liquidation_fee_cap: U128::new(100_000),
+        liquidation_buffer_bps: 100,
+        min_liquidation_abs: U128::new(100_000),
+    }
+}
+
+fn market_params(protocol_fee_bps: u64, creator_fee_bps: u64) -> MarketParams {
+    MarketParams {
+        max_snapshot_delay_slots: 100,
+        agreement_policy: AgreementPolicy::Exact,
+        dispute_timeout_slots: 50,
+        protocol_fee_bps,
+        creator_fee_bps,
+        betting_cutoff_slots: 1,
+        exit_fee_bps: 0,
+        min_total_stake: 0,
+        min_side_stake: 0,
+    }
+}
+
+fn open_market(params: MarketParams) -> MarketState {
+    let token = TokenStatus {
+        mint: [11; 32],
+        migrated_to_pumpswap: true,
+        launch_source: LaunchSource::PumpFun,
+        migration_slot: 0,
+        liquidity_quote_units: 0,
+        market_cap_quote_units: 0,
+        delisted: false,
+    };
+    let market = create_market(
+        token,
+        &EligibilityPolicy::default(),
+        1,
+        100,
+        200,
+        MarketRule::MarketCapAtCloseAtLeast {
+            target_quote_units: 1_000,
+        },
+        params,
+    )
+    .unwrap();
+    MarketState::new(market)
+}
+
+/// Close at 200 and resolve from a snapshot at 210 (`YES` iff `yes`)
+fn close_and_resolve(state: &mut MarketState, yes: bool) {
+    state.close(200).unwrap();
+    let snapshot = TokenSnapshot {
+        mint: [11; 32],
+        migrated_to_pumpswap: true,
+        market_cap_quote_units: if yes { 1_000 } else { 999 },
+        price_e6: 0,
+        volume: None,
+        migration_slot: None,
+        snapshot_slot: 210,
+    };
+    state.resolve(&snapshot, 210).unwrap();
+}
+
+#[derive(Default)]
+struct Log(Vec<EngineEvent>);
+
+impl EventSink for Log {
+    fn on_event(&mut self, event: &EngineEvent) {
+        self.0.push(*event);
+    }
+}
+
+fn add_participant(engine: &mut RiskEngine, tag: u8) -> u16 {
+    let idx = engine.add_user(0).unwrap();
+    engine.set_owner(idx, [tag; 32]).unwrap();
+    idx
+}
+
+#[test]
+fn test_stake_is_held_as_capital() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut state = open_market(market_params(0, 0));
+    let a = add_participant(&mut engine, 1);
+
+    state
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 700, 120)
+        .unwrap();
+    state
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 300, 130)
+        .unwrap();
+
+    assert_eq!(engine.accounts[a as usize].capital.get(), 1_000);
+    assert_eq!(engine.vault.get(), 1_000);
+    assert_eq!(state.ledger.pools.yes_capital, 1_000);
+    assert_eq!(state.account_idx[0], a);
+    assert!(engine.check_conservation(DEFAULT_ORACLE));
+}
+
+#[test]
+fn test_settlement_moves_losing_stakes_into_winner_pnl() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut state = open_market(market_params(0, 0));
+    let a = add_participant(&mut engine, 1);
+    let b = add_participant(&mut engine, 2);
+    let c = add_participant(&mut engine, 3);
+    let creator = add_participant(&mut engine, 4);
+
+    state
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 1_000, 120)
+        .unwrap();
+    state
+        .stake_on_engine(&mut engine, b, Outcome::No, 600, 120)
+        .unwrap();
+    state
+        .stake_on_engine(&mut engine, c, Outcome::No, 400, 120)
+        .unwrap();
+    close_and_resolve(&mut state, true);
+
+    let settlement = state.settle_on_engine(&mut engine, creator, 220).unwrap();
+    assert_eq!(settlement.loser_capital_total, 1_000);
+    assert_eq!(settlement.profit_claim_total, 1_000);
+    assert_eq!((settlement.h_num, settlement.h_den), (1_000, 1_000));
+    assert_eq!(settlement.winner_payout_total, 2_000);
+    assert_eq!(state.phase, MarketPhase::Settled);
+
+    // Losers lose their stake; the winner's profit is junior PnL, not capital
+    assert_eq!(engine.accounts[b as usize].capital.get(), 0);
+    assert_eq!(engine.accounts[c as usize].capital.get(), 0);
+    assert_eq!(engine.accounts[a as usize].capital.get(), 1_000);
+    assert_eq!(engine.accounts[a as usize].pnl.get(), 1_000);
+    assert_eq!(engine.vault.get(), 2_000);
+    assert!(engine.check_conservation(DEFAULT_ORACLE));
+
+    // Profit converts to capital only through warmup
+    engine.advance_slot(50);
+    engine.settle_warmup_to_capital(a).unwrap();
+    assert_eq!(engine.accounts[a as usize].capital.get(), 1_500);
+    engine.advance_slot(100);
+    engine.settle_warmup_to_capital(a).unwrap();
+    assert_eq!(engine.accounts[a as usize].capital.get(), 2_000);
+    assert_eq!(engine.accounts[a as usize].pnl.get(), 0);
+    assert!(engine.check_conservation(DEFAULT_ORACLE));
+}
+
+#[test]
+fn test_settlement_fees_go_to_insurance_and_creator() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut state = open_market(market_params(200, 100));
+    let a = add_participant(&mut engine, 1);
+    let e = add_participant(&mut engine, 2);
+    let b = add_participant(&mut engine, 3);
+    let creator = add_participant(&mut engine, 4);
+
+    state
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 2, 120)
+        .unwrap();
+    state
+        .stake_on_engine(&mut engine, e, Outcome::Yes, 1, 120)
+        .unwrap();
+    state
+        .stake_on_engine(&mut engine, b, Outcome::No, 1_000, 120)
+        .unwrap();
+    close_and_resolve(&mut state, true);
+
+    let settlement = state.settle_on_engine(&mut engine, creator, 220).unwrap();
+    assert_eq!(settlement.protocol_fee_paid, 20);
+    assert_eq!(settlement.creator_fee_paid, 10);
+    assert_eq!(settlement.profit_claim_total, 970);
+
+    // floor(2 * 970 / 3) + floor(970 / 3) = 969; the 1 unit of dust is insured
+    assert_eq!(engine.accounts[a as usize].pnl.get(), 646);
+    assert_eq!(engine.accounts[e as usize].pnl.get(), 323);
+    assert_eq!(engine.insurance_fund.balance.get(), 21);
+    assert_eq!(engine.insurance_fund.fee_revenue.get(), 20);
+    assert_eq!(engine.accounts[creator as usize].capital.get(), 10);
+    assert!(engine.check_conservation(DEFAULT_ORACLE));
+}
+
+#[test]
+fn test_settlement_reports_debits_credits_and_fees() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut state = open_market(market_params(200, 100));
+    let a = add_participant(&mut engine, 1);
+    let e = add_participant(&mut engine, 2);
+    let b = add_participant(&mut engine, 3);
+    let creator = add_participant(&mut engine, 4);
+    let mut log = Log::default();
+
+    state
+        .stake_on_engine_with_sink(&mut log, &mut engine, a, Outcome::Yes, 2, 120)
+        .unwrap();
+    state
+        .stake_on_engine(&mut engine, e, Outcome::Yes, 1, 120)
+        .unwrap();
+    state
+        .stake_on_engine(&mut engine, b, Outcome::No, 1_000, 120)
+        .unwrap();
+    assert_eq!(
+        log.0,
+        vec![EngineEvent::Deposited {
+            idx: a,
+            amount: 2,
+            capital_before: 0,
+            capital_after: 2,
+        }]
+    );
+    close_and_resolve(&mut state, true);
+
+    let mut log = Log::default();
+    state
+        .settle_on_engine_with_sink(&mut log, &mut engine, creator, 220)
+        .unwrap();
+    assert_eq!(
+        log.0,
+        vec![
+            EngineEvent::StakeLost {
+                idx: b,
+                market_id: 1,
+                amount: 1_000,
+                capital_before: 1_000,
+                capital_after: 0,
+            },
+            EngineEvent::WinningsCredited {
+                idx: a,
+                market_id: 1,
+                amount: 646,
+                pnl_before: 0,
+                pnl_after: 646,
+            },
+            EngineEvent::WinningsCredited {
+                idx: e,
+                market_id: 1,
+                amount: 323,
+                pnl_before: 0,
+                pnl_after: 323,
+            },
+            EngineEvent::ProtocolFeeCollected {
+                market_id: 1,
+                amount: 20,
+                rounding_dust: 1,
+            },
+            EngineEvent::CreatorFeePaid {
+                idx: creator,
+                market_id: 1,
+                amount: 10,
+                capital_before: 0,
+                capital_after: 10,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_winnings_are_haircut_by_engine_ratio() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut state = open_market(market_params(0, 0));
+    let a = add_participant(&mut engine, 1);
+    let b = add_participant(&mut engine, 2);
+    let creator = add_participant(&mut engine, 3);
+
+    state
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 1_000, 120)
+        .unwrap();
+    state
+        .stake_on_engine(&mut engine, b, Outcome::No, 1_000, 120)
+        .unwrap();
+    close_and_resolve(&mut state, true);
+
+    // Another account already holds 1_000 of junior profit against the same residual
+    let other = add_participant(&mut engine, 9);
+    engine.set_pnl(other as usize, 1_000);
+
+    let settlement = state.settle_on_engine(&mut engine, creator, 220).unwrap();
+    assert_eq!((settlement.h_num, settlement.h_den), (1_000, 2_000));
+    assert_eq!(settlement.winner_profit_paid, 500);
+    assert_eq!(settlement.winner_payout_total, 1_500);
+
+    engine.advance_slot(100);
+    engine.settle_warmup_to_capital(a).unwrap();
+    assert_eq!(engine.accounts[a as usize].capital.get(), 1_500);
+    assert!(engine.vault.get() >= engine.c_tot.get() + engine.insurance_fund.balance.get());
+}
+
+#[test]
+fn test_staked_capital_is_locked_until_settlement() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut state = open_market(market_params(0, 0));
+    let a = add_participant(&mut engine, 1);
+    let b = add_participant(&mut engine, 2);
+    let creator = add_participant(&mut engine, 3);
+
+    state
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 1_000, 120)
+        .unwrap();
+    engine.deposit(b, 250, 120).unwrap();
+    state
+        .stake_on_engine(&mut engine, b, Outcome::No, 1_000, 120)
+        .unwrap();
+    assert_eq!(engine.accounts[b as usize].locked_capital.get(), 1_000);
+    close_and_resolve(&mut state, true);
+
+    // The loser cannot pull its stake out between resolution and settlement
+    assert_eq!(
+        engine.withdraw(b, 1_000, 215, DEFAULT_ORACLE),
+        Err(RiskError::InsufficientBalance)
+    );
+    assert_eq!(
+        engine.close_account(b, 215, DEFAULT_ORACLE),
+        Err(RiskError::CapitalLocked)
+    );
+    engine.withdraw(b, 250, 215, DEFAULT_ORACLE).unwrap();
+
+    let settlement = state.settle_on_engine(&mut engine, creator, 220).unwrap();
+    assert_eq!(settlement.loser_capital_total, 1_000);
+    assert_eq!(engine.accounts[a as usize].pnl.get(), 1_000);
+    assert_eq!(engine.accounts[a as usize].locked_capital.get(), 0);
+    assert_eq!(engine.accounts[b as usize].capital.get(), 0);
+    assert_eq!(engine.accounts[b as usize].locked_capital.get(), 0);
+    assert!(engine.check_conservation(DEFAULT_ORACLE));
+
+    // The winner's stake is free again
+    engine.withdraw(a, 1_000, 230, DEFAULT_ORACLE).unwrap();
+}
+
+#[test]
+fn test_stake_records_capital_actually_added() {
+    let mut engine = Box::new(RiskEngine::new(RiskParams {
+        maintenance_fee_per_slot: U128::new(1),
+        ..default_params()
+    }));
+    let mut state = open_market(market_params(0, 0));
+    let a = add_participant(&mut engine, 1);
+
+    // 120 slots of maintenance fee come off the deposit first
+    state
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 1_000, 120)
+        .unwrap();
+    assert_eq!(state.ledger.pools.yes_capital, 880);
+    assert_eq!(engine.accounts[a as usize].capital.get(), 880);
+    assert_eq!(engine.accounts[a as usize].locked_capital.get(), 880);
+
+    // A deposit swallowed entirely by fee debt stakes nothing and is undone
+    let vault = engine.vault.get();
+    assert_eq!(
+        state.stake_on_engine(&mut engine, a, Outcome::Yes, 5, 130),
+        Err(PredictionError::ZeroStake)
+    );
+    assert_eq!(engine.vault.get(), vault);
+    assert_eq!(engine.current_slot, 120);
+    assert_eq!(state.ledger.pools.yes_capital, 880);
+
+    // Locked capital pays no maintenance fees
+    engine
+        .settle_maintenance_fee(a, 180, DEFAULT_ORACLE)
+        .unwrap();
+    assert_eq!(engine.accounts[a as usize].capital.get(), 880);
+}
+
+#[test]
+fn test_stake_excludes_warmed_pnl() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut first = open_market(market_params(0, 0));
+    let a = add_participant(&mut engine, 1);
+    let b = add_participant(&mut engine, 2);
+    let creator = add_participant(&mut engine, 3);
+    first
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 1_000, 120)
+        .unwrap();
+    first
+        .stake_on_engine(&mut engine, b, Outcome::No, 1_000, 120)
+        .unwrap();
+    close_and_resolve(&mut first, true);
+    first.settle_on_engine(&mut engine, creator, 220).unwrap();
+    assert_eq!(engine.accounts[a as usize].pnl.get(), 1_000);
+
+    // By slot 400 the winnings have fully warmed up, but only the new
+    // deposit is staked and locked
+    let token = TokenStatus {
+        mint: [11; 32],
+        migrated_to_pumpswap: true,
+        launch_source: LaunchSource::PumpFun,
+        migration_slot: 0,
+        liquidity_quote_units: 0,
+        market_cap_quote_units: 0,
+        delisted: false,
+    };
+    let market = create_market(
+        token,
+        &EligibilityPolicy::default(),
+        2,
+        300,
+        500,
+        MarketRule::MarketCapAtCloseAtLeast {
+            target_quote_units: 1_000,
+        },
+        market_params(0, 0),
+    )
+    .unwrap();
+    let mut second = MarketState::new(market);
+    second
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 100, 400)
+        .unwrap();
+    assert_eq!(second.ledger.pools.yes_capital, 100);
+    assert_eq!(engine.accounts[a as usize].locked_capital.get(), 100);
+    assert_eq!(engine.accounts[a as usize].capital.get(), 2_100);
+    assert_eq!(engine.accounts[a as usize].pnl.get(), 0);
+    assert!(engine.check_conservation(DEFAULT_ORACLE));
+}
+
+#[test]
+fn test_stake_requires_flat_account_and_monotonic_slots() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut state = open_market(market_params(0, 0));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    engine.deposit(lp, 10_000_000, 1).unwrap();
+    let trader = add_participant(&mut engine, 1);
+    engine.deposit(trader, 1_000_000, 1).unwrap();
+    engine
+        .keeper_crank(trader, 110, DEFAULT_ORACLE, 0, false)
+        .unwrap();
+    engine
+        .execute_trade(&NoOpMatcher, lp, trader, 110, DEFAULT_ORACLE, 1_000)
+        .unwrap();
+
+    assert_eq!(
+        state.stake_on_engine(&mut engine, trader, Outcome::Yes, 100, 120),
+        Err(PredictionError::AccountNotFlat)
+    );
+
+    let a = add_participant(&mut engine, 3);
+    assert_eq!(
+        state.stake_on_engine(&mut engine, a, Outcome::Yes, 100, 105),
+        Err(PredictionError::SlotRegression)
+    );
+    state
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 100, 120)
+        .unwrap();
+
+    // Locked accounts cannot trade
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, a, 121, DEFAULT_ORACLE, 1),
+        Err(RiskError::CapitalLocked)
+    );
+}
+
+#[test]
+fn test_engine_settlement_rejects_slot_regression() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut state = open_market(market_params(0, 0));
+    let a = add_participant(&mut engine, 1);
+    let b = add_participant(&mut engine, 2);
+    let creator = add_participant(&mut engine, 3);
+
+    state
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 1_000, 120)
+        .unwrap();
+    state
+        .stake_on_engine(&mut engine, b, Outcome::No, 1_000, 120)
+        .unwrap();
+    close_and_resolve(&mut state, true);
+    engine.deposit(creator, 1, 300).unwrap();
+
+    assert_eq!(
+        state.settle_on_engine(&mut engine, creator, 220),
+        Err(PredictionError::SlotRegression)
+    );
+    assert_eq!(engine.accounts[b as usize].capital.get(), 1_000);
+    state.settle_on_engine(&mut engine, creator, 300).unwrap();
+}
+
+#[test]
+fn test_cancelled_market_leaves_stakes_in_capital() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut state = open_market(market_params(0, 0));
+    let a = add_participant(&mut engine, 1);
+    let b = add_participant(&mut engine, 2);
+
+    state
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 1_000, 120)
+        .unwrap();
+    state
+        .stake_on_engine(&mut engine, b, Outcome::No, 500, 120)
+        .unwrap();
+    let delisted = TokenStatus {
+        mint: [11; 32],
+        migrated_to_pumpswap: true,
+        launch_source: LaunchSource::PumpFun,
+        migration_slot: 0,
+        liquidity_quote_units: 0,
+        market_cap_quote_units: 0,
+        delisted: true,
+    };
+    state.cancel_delisted(&delisted, 150).unwrap();
+
+    // Refunds go through the engine, which releases the locked stakes
+    assert_eq!(
+        state.pay_refunds(1_500),
+        Err(PredictionError::AccountMismatch)
+    );
+    state.refund_on_engine(&mut engine).unwrap();
+    assert!(state.is_finished());
+    assert_eq!(
+        state.refund_on_engine(&mut engine),
+        Err(PredictionError::InvalidTransition)
+    );
+
+    assert_eq!(engine.accounts[a as usize].capital.get(), 1_000);
+    assert_eq!(engine.accounts[a as usize].locked_capital.get(), 0);
+    assert_eq!(engine.accounts[b as usize].capital.get(), 500);
+    assert_eq!(engine.accounts[b as usize].locked_capital.get(), 0);
+    assert!(engine.check_conservation(DEFAULT_ORACLE));
+}
+
+#[test]
+fn test_engine_settlement_requires_engine_backed_positions() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut state = open_market(market_params(0, 0));
+    let a = add_participant(&mut engine, 1);
+    let creator = add_participant(&mut engine, 3);
+
+    state
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 1_000, 120)
+        .unwrap();
+    state.stake([7; 32], Outcome::No, 500, 120).unwrap();
+    close_and_resolve(&mut state, true);
+
+    assert_eq!(
+        state.settle_on_engine(&mut engine, creator, 220),
+        Err(PredictionError::PositionNotEngineBacked)
+    );
+    assert_eq!(state.phase, MarketPhase::Resolved);
+}
+
+#[test]
+fn test_stake_rejects_unknown_or_mismatched_account() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut state = open_market(market_params(0, 0));
+    let a = add_participant(&mut engine, 1);
+    let same_owner = add_participant(&mut engine, 1);
+
+    assert_eq!(
+        state.stake_on_engine(&mut engine, 42, Outcome::Yes, 100, 120),
+        Err(PredictionError::Engine(RiskError::AccountNotFound))
+    );
+
+    state
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 100, 120)
+        .unwrap();
+    assert_eq!(
+        state.stake_on_engine(&mut engine, same_owner, Outcome::Yes, 100, 120),
+        Err(PredictionError::AccountMismatch)
+    );
+    assert_eq!(engine.vault.get(), 100);
+}
+
+#[test]
+fn test_engine_backed_positions_cannot_unstake() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut state = open_market(market_params(0, 0));
+    let a = add_participant(&mut engine, 1);
+
+    state
+        .stake_on_engine(&mut engine, a, Outcome::Yes, 1_000, 120)
+        .unwrap();
+    assert_eq!(
+        state.unstake([1; 32], Outcome::Yes, 100, 130),
+        Err(PredictionError::AccountMismatch)
+    );
+    assert_eq!(state.ledger.pools.yes_capital, 1_000);
+}
@@ -0,0 +1,138 @@
+//! JSON/TOML engine snapshots
+//! Run with: cargo test --features snapshot --test snapshot_tests
+
+#![cfg(feature = "snapshot")]
+
+use percolator::layout::LayoutError;
+use percolator::snapshot::*;
+use percolator::*;
+
+/// Sized explicitly, so these tests do not depend on the `test` feature
+type Engine = RiskEngine<64, 1>;
+
+const DEFAULT_ORACLE: u64 = 1_000_000;
+
+fn default_params() -> RiskParams {
+    RiskParams {
+        warmup_period_slots: 100,
+        maintenance_margin_bps: 500,
+        initial_margin_bps: 1000,
+        trading_fee_bps: 10,
+        max_accounts: 1000,
+        new_account_fee: U128::new(0),
+        risk_reduction_threshold: U128::new(0),
+        maintenance_fee_per_slot: U128::new(0),
+        max_crank_staleness_slots: u64::MAX,
+        liquidation_fee_bps: 50,
+        liquidation_fee_cap: U128::new(100_000),
+        liquidation_buffer_bps: 100,
+        min_liquidation_abs: U128::new(100_000),
+    }
+}
+
+/// LP and user with an open position, skipping slot 1 so indices matter
+fn active_engine() -> Box<Engine> {
+    let mut engine = Box::new(Engine::new_sized(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let dust = engine.add_user(0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 1).unwrap();
+    engine.deposit(user, 1_000_000, 1).unwrap();
+    engine.set_owner(user, [0xab; 32]).unwrap();
+    engine
+        .keeper_crank(user, 2, DEFAULT_ORACLE, 0, false)
+        .unwrap();
+    assert!(!engine.is_used(dust as usize));
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 2, DEFAULT_ORACLE, 1_000)
+        .unwrap();
+    engine
+}
+
+fn load(snapshot: &EngineSnapshot) -> core::result::Result<Box<Engine>, SnapshotError> {
+    let mut engine = Box::new(Engine::new_sized(default_params()));
+    engine.load_snapshot(snapshot, DEFAULT_ORACLE)?;
+    Ok(engine)
+}
+
+#[test]
+fn test_snapshot_lists_only_occupied_accounts() {
+    let snapshot = active_engine().to_snapshot();
+    let idxs: Vec<u16> = snapshot.accounts.iter().map(|a| a.idx).collect();
+    assert_eq!(idxs, vec![0, 2]);
+    assert_eq!(snapshot.accounts[1].position_size, 1_000);
+    assert_eq!(snapshot.params.max_crank_staleness_slots, u64::MAX);
+}
+
+#[test]
+fn test_json_and_toml_round_trip() {
+    let engine = active_engine();
+    let snapshot = engine.to_snapshot();
+
+    let json = snapshot.to_json().unwrap();
+    assert!(json.contains(&"ab".repeat(32)));
+    assert_eq!(EngineSnapshot::from_json(&json).unwrap(), snapshot);
+
+    let toml = snapshot.to_toml().unwrap();
+    assert_eq!(EngineSnapshot::from_toml(&toml).unwrap(), snapshot);
+
+    let loaded = load(&EngineSnapshot::from_toml(&toml).unwrap()).unwrap();
+    assert_eq!(loaded.to_snapshot(), snapshot);
+    assert_eq!(loaded.accounts[2], engine.accounts[2]);
+    assert!(!loaded.is_used(1));
+}
+
+#[test]
+fn test_loaded_engine_keeps_operating() {
+    let snapshot = active_engine().to_snapshot();
+    let mut engine = load(&snapshot).unwrap();
+    let user = engine.add_user(0).unwrap();
+    assert_eq!(user, 1);
+    assert_eq!(
+        engine.accounts[1].account_id,
+        snapshot.aggregates.next_account_id
+    );
+    engine.deposit(user, 500, 3).unwrap();
+    engine.withdraw(user, 500, 4, DEFAULT_ORACLE).unwrap();
+    assert!(engine.check_conservation(DEFAULT_ORACLE));
+}
+
+#[test]
+fn test_import_rejects_invalid_state() {
+    let snapshot = active_engine().to_snapshot();
+
+    let mut bad = snapshot.clone();
+    bad.accounts[1].idx = 0;
+    assert_eq!(load(&bad).err(), Some(SnapshotError::DuplicateAccount));
+
+    let mut bad = snapshot.clone();
+    bad.accounts[1].idx = Engine::CAPACITY as u16;
+    assert_eq!(load(&bad).err(), Some(SnapshotError::InvalidAccountIndex));
+
+    let mut bad = snapshot.clone();
+    bad.aggregates.c_tot += 1;
+    assert_eq!(
+        load(&bad).err(),
+        Some(SnapshotError::Layout(LayoutError::InconsistentAggregates))
+    );
+
+    // Unexplained vault surplus breaks the conservation slack bound
+    let mut bad = snapshot.clone();
+    bad.aggregates.vault += 1_000_000;
+    assert_eq!(load(&bad).err(), Some(SnapshotError::ConservationViolated));
+
+    // A rejected snapshot leaves the target engine as it was
+    let mut engine = active_engine();
+    let before = engine.to_snapshot();
+    let cursor = engine.crank_cursor;
+    bad.accounts[1].capital += 1;
+    assert!(engine.load_snapshot(&bad, DEFAULT_ORACLE).is_err());
+    assert_eq!(engine.to_snapshot(), before);
+    assert_eq!(engine.crank_cursor, cursor);
+
+    let json = snapshot.to_json().unwrap().replace(&"ab".repeat(32), "zz");
+    assert!(matches!(
+        EngineSnapshot::from_json(&json),
+        Err(SnapshotError::Parse(_))
+    ));
+}